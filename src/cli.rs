@@ -7,6 +7,7 @@ use tracing::Level;
 use clap::ValueHint::DirPath;
 #[cfg(feature = "instrumentation")]
 use console_subscriber::ConsoleLayer;
+use rudicom::config::Role;
 use rudicom::tools::import::ImportMode;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt;
@@ -73,6 +74,12 @@ pub enum Commands {
 	},
 	/// restore database from SureQL snapshot
 	Restore {file:PathBuf},
+	/// create a user of the http interface (or change its password and role), the password is read from stdin
+	SetUser {
+		name:String,
+		#[arg(long, default_value = "admin")]
+		role:Role,
+	},
 	/// import (big chunks of) data from the filesystem
 	Import {
 		/// report on already existing files
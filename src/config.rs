@@ -1,23 +1,274 @@
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use config::{Config,ConfigError, File, FileFormat::Toml};
 use std::sync::OnceLock;
-use dicom::core::DataDictionary;
-use dicom::dictionary_std::StandardDataDictionary;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::db::metadata::TagPattern;
 use crate::dcm::AttributeSelector;
+use crate::tools::filename::Pattern;
 
 #[derive(Debug,Serialize,Deserialize)]
 pub struct Limits{pub upload_sizelimit:byte_unit::Byte, pub max_files:u16, pub db_capacity:usize}
 #[derive(Debug,Serialize,Deserialize)]
-pub struct Paths{pub filename_pattern:String,pub storage_path:PathBuf}
+pub struct Paths{
+	pub filename_pattern:String,
+	pub storage_path:PathBuf,
+	pub quarantine_path:Option<PathBuf>,
+	#[serde(default)]
+	pub import_roots:Vec<PathBuf>,
+	/// which characters of values are replaced in filenames
+	#[serde(default)]
+	pub sanitize:Sanitize,
+	/// used in filenames for missing values
+	#[serde(default = "default_none_placeholder")]
+	pub none_placeholder:String,
+	/// used in filenames for empty values
+	#[serde(default = "default_empty_placeholder")]
+	pub empty_placeholder:String,
+	/// what to do if the filename of a new instance is already taken
+	#[serde(default)]
+	pub on_collision:Collision,
+}
+fn default_none_placeholder() -> String {"__none__".into()}
+fn default_empty_placeholder() -> String {"__empty__".into()}
+/// characters replaced by "_" in values used in filenames
+#[derive(Debug,Default,Clone,Copy,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sanitize{
+	/// "/", " " and NUL
+	#[default]
+	Posix,
+	/// also control characters and `<>:"\|?*`, reserved names get a "_" prefix
+	Windows,
+	/// everything but ASCII letters, digits and "._-"
+	Portable,
+}
+/// how to store an instance whose filename is already taken by another file
+#[derive(Debug,Default,Clone,Copy,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Collision{
+	/// refuse to store the instance
+	#[default]
+	Fail,
+	/// add "_1", "_2", ... to the filename
+	Suffix,
+	/// add the first 8 characters of the md5 of the SOPInstanceUID (and a counter if that's taken as well)
+	UidHash,
+}
+#[derive(Debug,Default,Serialize,Deserialize)]
+pub struct StoreCfg{#[serde(default)] pub versioning:bool}
+#[derive(Debug,Default,Serialize,Deserialize)]
+pub struct AuditCfg{pub syslog:Option<String>}
+
+/// roles of users of the http interface, each includes the privileges of the ones before
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Serialize,Deserialize)]
+#[serde(rename_all="kebab-case")]
+pub enum Role{ReadOnly,Uploader,Admin}
+impl Role {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Role::ReadOnly => "read-only",
+			Role::Uploader => "uploader",
+			Role::Admin => "admin",
+		}
+	}
+}
+impl Display for Role {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {f.write_str(self.as_str())}
+}
+impl FromStr for Role {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		[Role::ReadOnly,Role::Uploader,Role::Admin].into_iter()
+			.find(|r|r.as_str() == s)
+			.ok_or(format!(r#""{s}" is not a valid role"#))
+	}
+}
+#[derive(Debug,Serialize,Deserialize)]
+pub struct TokenCfg{pub user:String, pub role:Role}
+#[derive(Debug,Serialize,Deserialize)]
+pub struct JwtCfg{
+	pub key:PathBuf,
+	pub algorithm:String,
+	pub issuer:Option<String>,
+	pub audience:Option<String>,
+	pub role_claim:Option<String>,
+}
+#[derive(Debug,Default,Serialize,Deserialize)]
+pub struct AuthCfg{
+	pub anonymous:Option<Role>,
+	#[serde(default)]
+	pub tokens:HashMap<String,TokenCfg>,
+	pub jwt:Option<JwtCfg>,
+}
+#[derive(Debug,Serialize,Deserialize)]
+pub struct WebhookCfg{
+	pub url:String,
+	/// if set, the body is signed with HMAC-SHA256 (header `X-Rudicom-Signature: sha256=<hex>`)
+	pub secret:Option<String>,
+	/// the events sent to this hook (all if not set)
+	pub events:Option<Vec<String>>,
+	/// how often a failed delivery is retried
+	#[serde(default = "default_retries")]
+	pub retries:u32,
+}
+fn default_retries() -> u32 {5}
+#[derive(Debug,Default,Serialize,Deserialize)]
+#[serde(default)]
+pub struct EventsCfg{
+	pub webhooks:Vec<WebhookCfg>,
+}
+#[derive(Debug,Serialize,Deserialize)]
+#[serde(default)]
+pub struct CompletenessCfg{
+	/// seconds without new instances after which a series is considered complete
+	pub series_timeout:u64,
+	/// seconds without new instances after which a study is considered complete
+	pub study_timeout:u64,
+	/// compare the number of instances of a series with ImagesInAcquisition
+	pub check_counts:bool,
+}
+impl Default for CompletenessCfg {
+	fn default() -> Self {CompletenessCfg{series_timeout:60,study_timeout:120,check_counts:false}}
+}
+/// the full dataset (without pixel data) stored as `metadata` of every instance
+#[derive(Debug,Serialize,Deserialize)]
+#[serde(default)]
+pub struct MetadataCfg{
+	pub enabled:bool,
+	/// elements bigger than this (encoded) are left out
+	pub max_element_size:byte_unit::Byte,
+	/// elements are left out once the document would get bigger than this (as JSON)
+	pub max_size:byte_unit::Byte,
+	/// elements left out (with all their content)
+	pub exclude:Vec<TagPattern>,
+}
+impl Default for MetadataCfg {
+	fn default() -> Self {
+		MetadataCfg{
+			enabled:false,
+			max_element_size:byte_unit::Byte::from_u64(4*1024),
+			max_size:byte_unit::Byte::from_u64(256*1024),
+			exclude:vec![]
+		}
+	}
+}
+/// forwards data matching the conditions to a DIMSE peer and/or a webhook
+#[derive(Debug,Serialize,Deserialize)]
+pub struct RoutingRule{
+	pub name:String,
+	/// the event triggering the rule ("instance_stored" or "study_complete")
+	#[serde(default = "default_trigger")]
+	pub on:String,
+	/// tag name and glob pattern its value must match (of the first instance for studies)
+	#[serde(default)]
+	pub conditions:HashMap<String,String>,
+	/// AE title of a peer in `[dimse.peers]` the instances are sent to via C-STORE
+	pub peer:Option<String>,
+	/// url the event is posted to
+	pub webhook:Option<String>,
+	/// if set, the webhook body is signed with HMAC-SHA256 (header `X-Rudicom-Signature: sha256=<hex>`)
+	pub secret:Option<String>,
+}
+fn default_trigger() -> String {"instance_stored".into()}
+#[derive(Debug,Serialize,Deserialize)]
+#[serde(default)]
+pub struct RoutingCfg{
+	pub rules:Vec<RoutingRule>,
+	/// how often a failed forward is tried before it is marked failed
+	pub max_attempts:u32,
+	/// seconds before the first retry, doubled for every further one
+	pub retry_delay:u64,
+}
+impl Default for RoutingCfg {
+	fn default() -> Self {RoutingCfg{rules:vec![],max_attempts:10,retry_delay:30}}
+}
+/// command or python function run with the files of new instances or complete series
+#[derive(Debug,Serialize,Deserialize)]
+pub struct HookCfg{
+	pub name:String,
+	/// the event triggering the hook ("instance_stored" or "series_complete")
+	#[serde(default = "default_trigger")]
+	pub on:String,
+	/// program and arguments, `{files}` is replaced by the file paths (appended if not given), `{id}` by the id of the entry
+	pub command:Option<Vec<String>>,
+	/// python code with a function `hook(paths:list[str]) -> Optional[str]`
+	pub python:Option<String>,
+	/// seconds after which the hook is aborted
+	#[serde(default = "default_hook_timeout")]
+	pub timeout:u64,
+	/// how many runs of this hook may run at the same time
+	#[serde(default = "default_concurrency")]
+	pub concurrency:usize,
+}
+fn default_hook_timeout() -> u64 {300}
+fn default_concurrency() -> usize {1}
+/// how values of a column of the children of a series / study are combined
+#[derive(Debug,Clone,Copy,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateOp{Min, Max, Distinct, CountDistinct, Count, Sum, Duration}
+#[derive(Debug,Serialize,Deserialize)]
+pub struct AggregateCfg{
+	/// the column of the children (instances for series; series if they have it, otherwise instances for studies)
+	pub of:String,
+	pub op:AggregateOp,
+}
+/// certificate and key for TLS (PEM files)
+#[derive(Debug,Serialize,Deserialize)]
+pub struct TlsCfg{
+	pub cert:PathBuf,
+	pub key:PathBuf,
+	/// CA certificates used to verify the certificates of peers
+	pub ca:Option<PathBuf>,
+	/// require clients to present a certificate signed by `ca`
+	#[serde(default)]
+	pub verify_clients:bool,
+}
+/// what a calling DIMSE AE is allowed to do
+#[derive(Debug,Clone,Serialize,Deserialize)]
+#[serde(default)]
+pub struct AePermissions{
+	/// C-STORE
+	pub store:bool,
+	/// C-FIND
+	pub query:bool,
+	/// AE titles (of the peers) that may be used as C-MOVE destination, "*" allows all
+	pub move_destinations:Vec<String>,
+	/// associations of the AE open at the same time, unlimited if not set
+	pub max_associations:Option<usize>,
+}
+impl Default for AePermissions {
+	fn default() -> Self {
+		AePermissions{store:true,query:true,move_destinations:vec!["*".into()],max_associations:None}
+	}
+}
+impl AePermissions {
+	pub fn may_move_to(&self, destination:&str) -> bool {
+		self.move_destinations.iter().any(|d|d == "*" || d == destination)
+	}
+}
 #[derive(Debug,Serialize,Deserialize)]
 pub struct DimseCfg{
 	pub aet:String,
 	pub address:String,
 	pub peers:HashMap<String,SocketAddr>,
+	/// permissions of calling AEs that are not listed in `access`
+	#[serde(default)]
+	pub default:AePermissions,
+	/// permissions per calling AE title
+	#[serde(default)]
+	pub access:HashMap<String,AePermissions>,
+	/// DICOM TLS for the SCP and connections to the peers
+	pub tls:Option<TlsCfg>,
+}
+impl DimseCfg {
+	/// the permissions of the given calling AE (the default ones if it is unknown)
+	pub fn permissions(&self, calling_ae:Option<&str>) -> &AePermissions {
+		calling_ae.and_then(|ae|self.access.get(ae.trim())).unwrap_or(&self.default)
+	}
 }
 
 #[derive(Debug,Serialize,Deserialize)]
@@ -26,8 +277,30 @@ pub struct ConfigStruct
 	pub instance_tags:HashMap<String,Vec<AttributeSelector>>,
 	pub series_tags:HashMap<String,Vec<AttributeSelector>>,
 	pub study_tags:HashMap<String,Vec<AttributeSelector>>,
+	#[serde(default)]
+	pub series_aggregates:HashMap<String,AggregateCfg>,
+	#[serde(default)]
+	pub study_aggregates:HashMap<String,AggregateCfg>,
 	pub limits: Limits,
 	pub paths: Paths,
+	#[serde(default)]
+	pub store: StoreCfg,
+	#[serde(default)]
+	pub audit: AuditCfg,
+	#[serde(default)]
+	pub auth: AuthCfg,
+	/// HTTPS for the http interface
+	pub tls: Option<TlsCfg>,
+	#[serde(default)]
+	pub events: EventsCfg,
+	#[serde(default)]
+	pub completeness: CompletenessCfg,
+	#[serde(default)]
+	pub routing: RoutingCfg,
+	#[serde(default)]
+	pub metadata: MetadataCfg,
+	#[serde(default)]
+	pub hooks: Vec<HookCfg>,
 	pub dimse: Option<DimseCfg>,
 	pub filters:HashMap<String,String>,
 
@@ -36,33 +309,30 @@ pub struct ConfigStruct
 impl Serialize for AttributeSelector {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer
 	{
-		let v = match self {
-			AttributeSelector::Core(c) => c.to_string(),
-			AttributeSelector::CSA { base, element } => format!("{}#CSA.{}", base, element),
-		};
-		serializer.serialize_str(v.as_str())
+		serializer.serialize_str(self.to_string().as_str())
+	}
+}
+impl Serialize for TagPattern {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer
+	{
+		serializer.serialize_str(self.to_string().as_str())
+	}
+}
+impl<'de> Deserialize<'de> for TagPattern {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de>
+	{
+		TagPattern::from_str(String::deserialize(deserializer)?.as_str())
+			.map_err(serde::de::Error::custom)
 	}
 }
 impl<'de> Deserialize<'de> for AttributeSelector {
 	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de>
 	{
-		let dict = StandardDataDictionary::default();
-
-		let parts=String::deserialize(deserializer)?;
-		if let Some(p) = parts.find("#CSA"){
-			let base = &parts[..p];
-			let base = dict.parse_selector(base).map_err(serde::de::Error::custom)?;
-			Ok(AttributeSelector::CSA {base,element:parts[p+5..].to_string()})
-		} else {
-			dict.parse_selector(parts.as_str())
-				.map(AttributeSelector::Core)
-				.map_err(serde::de::Error::custom)
-		}
+		AttributeSelector::from_str(String::deserialize(deserializer)?.as_str())
+			.map_err(serde::de::Error::custom)
 	}
 }
 
-
-
 static CONFIG:OnceLock<ConfigStruct> = OnceLock::new();
 
 static CONFIG_STR:&str = include_str!("config.toml");
@@ -82,7 +352,21 @@ pub fn init(config_file:Option<PathBuf>) -> Result<(),ConfigError>{
 		tracing::warn!(r#"no config file given loading defaults (use "write-config" subcommand to write it to a file)"#);
 	}
 
-	CONFIG.set(builder.build().unwrap().try_deserialize()?).expect("Failed to set config");
+	let mut config:ConfigStruct = builder.build().unwrap().try_deserialize()?;
+	// import roots are compared with canonical paths of the files to import
+	config.paths.import_roots = config.paths.import_roots.iter()
+		.filter_map(|root|root.canonicalize()
+			.inspect_err(|e|tracing::warn!("ignoring import root {} ({e})",root.display()))
+			.ok()
+		)
+		.collect();
+	let column = |name:&str| [&config.instance_tags, &config.series_tags, &config.study_tags].iter().any(|t|t.contains_key(name));
+	let pattern = Pattern::parse(&config.paths.filename_pattern, &column)
+		.map_err(|e|ConfigError::Message(format!("invalid filename_pattern: {e}")))?;
+	for warning in pattern.uniqueness_warnings() {
+		tracing::warn!("filename_pattern: {warning}");
+	}
+	CONFIG.set(config).expect("Failed to set config");
 	Ok(())
 }
 
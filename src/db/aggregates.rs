@@ -0,0 +1,80 @@
+use crate::config::{self, AggregateCfg, AggregateOp};
+use crate::db::{RecordId, DB};
+use crate::dcm::SERIES_TAGS;
+use crate::tools::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+use surrealdb::method::Transaction;
+use surrealdb::Connection;
+
+/// the configured aggregates of the table (series or studies)
+pub fn configured(table:&str) -> &'static HashMap<String,AggregateCfg>
+{
+	match table {
+		"studies" => &config::get().study_aggregates,
+		_ => &config::get().series_aggregates,
+	}
+}
+
+fn ident(name:&str) -> String
+{
+	format!("`{}`", name.replace('`', "\\`"))
+}
+
+/// the query updating the aggregates of $rec (None if there are none configured for the table)
+fn query(table:&str) -> Option<String>
+{
+	let aggregates = configured(table);
+	if aggregates.is_empty() {return None}
+	// every aggregate gets its values into $v<n> and the result into $r<n>
+	let mut lets = String::new();
+	let mut fields = vec![];
+	for (n,(name,AggregateCfg{of,op})) in aggregates.iter().enumerate() {
+		let field = ident(of);
+		// the children are taken from the references of $rec, so no table has to be scanned
+		let source = match table {
+			"studies" if SERIES_TAGS.contains_key(of) || config::get().series_aggregates.contains_key(of) =>
+				format!("SELECT VALUE {field} FROM $rec.series WHERE {field} != NONE"),
+			"studies" =>
+				format!("SELECT VALUE {field} FROM array::flatten($rec.series.instances) WHERE {field} != NONE"),
+			_ =>
+				format!("SELECT VALUE {field} FROM $rec.instances WHERE {field} != NONE"),
+		};
+		let v = format!("$v{n}");
+		let result = match op {
+			AggregateOp::Min => format!("array::min({v})"),
+			AggregateOp::Max => format!("array::max({v})"),
+			AggregateOp::Distinct => format!("array::sort(array::distinct({v}))"),
+			AggregateOp::CountDistinct => format!("array::len(array::distinct({v}))"),
+			AggregateOp::Count => format!("array::len({v})"),
+			AggregateOp::Sum => format!("math::sum({v})"),
+			AggregateOp::Duration => format!("IF array::len({v}) > 0 {{ array::max({v}) - array::min({v}) }} ELSE {{ NONE }}"),
+		};
+		// multi-valued columns count with every value
+		writeln!(lets, "LET {v} = array::flatten(({source}));\nLET $r{n} = {result};").unwrap();
+		fields.push(format!("{}: $r{n}", serde_json::Value::from(name.as_str())));
+	}
+	Some(format!("{lets}UPDATE $rec MERGE {{ {} }} RETURN NONE;", fields.join(", ")))
+}
+
+/// recalculates the aggregates of a series or study inside a transaction (e.g. of a registration)
+pub(crate) async fn update<C>(id:&RecordId, transaction:&Transaction<C>) -> Result<()> where C:Connection
+{
+	if let Some(query) = query(id.table.as_str()) {
+		transaction.query(query).bind(("rec", id.0.clone())).await
+			.and_then(|r|r.check().map(|_|()))
+			.context(format!("updating aggregates of {id}"))?;
+	}
+	Ok(())
+}
+
+/// recalculates the aggregates of a series or study (e.g. after instances were removed)
+pub async fn refresh(id:&RecordId) -> Result<()>
+{
+	if let Some(query) = query(id.table.as_str()) {
+		DB.query(query).bind(("rec", id.0.clone())).await
+			.and_then(|r|r.check().map(|_|()))
+			.context(format!("updating aggregates of {id}"))?;
+	}
+	Ok(())
+}
@@ -0,0 +1,237 @@
+use crate::config;
+use crate::db::{Pickable, RecordId, DB};
+use crate::tools::Error::{InvalidFilter, UnexpectedResult};
+use crate::tools::{Context, Error, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use surrealdb::method::Transaction;
+use surrealdb::types as db_types;
+use surrealdb::types::SurrealValue;
+use surrealdb::Connection;
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+/// who caused an audited operation
+#[derive(Clone,Debug,Default)]
+pub struct Actor
+{
+	/// the authenticated user (if known)
+	pub user:Option<String>,
+	/// where the request came from (IP address, "local", or "dimse" if the peer's address is unknown)
+	pub source:Option<String>,
+}
+
+impl Actor
+{
+	/// operations started locally (e.g. from the command line)
+	pub fn local() -> Actor {Actor{user:None,source:Some("local".into())}}
+	/// http requests
+	pub fn from_addr(addr:&SocketAddr) -> Actor {Actor{user:None,source:Some(addr.ip().to_string())}}
+	/// DIMSE associations, the calling AE title is recorded as user
+	pub fn dimse(calling_ae:Option<&str>, address:Option<IpAddr>) -> Actor {
+		Actor{
+			user:calling_ae.map(|ae|ae.trim().to_string()),
+			source:Some(address.map_or("dimse".into(),|a|a.to_string()))
+		}
+	}
+}
+
+/// An entry of the audit table
+pub struct AuditEntry
+{
+	pub id:RecordId,
+	pub timestamp:db_types::Value,
+	pub actor:Actor,
+	pub action:String,
+	pub target:RecordId,
+	/// what was changed (e.g. before/after values)
+	pub diff:db_types::Value,
+}
+
+/// filter for [query], all given conditions must match
+#[derive(Deserialize,Default,Clone)]
+pub struct AuditFilter
+{
+	pub action:Option<String>,
+	pub user:Option<String>,
+	pub source:Option<String>,
+	/// RFC 3339 timestamp
+	pub since:Option<String>,
+	/// RFC 3339 timestamp
+	pub until:Option<String>,
+	/// maximum number of entries returned (newest first), defaults to 100
+	pub limit:Option<u32>,
+}
+
+fn content(actor:&Actor, action:&str, target:&RecordId, diff:db_types::Value) -> db_types::Object
+{
+	let mut content = db_types::Object::default();
+	content.insert("actor", actor.user.clone().into_value());
+	content.insert("source", actor.source.clone().into_value());
+	content.insert("action", action.to_string());
+	content.insert("target", target.0.clone().into_value());
+	content.insert("diff", diff);
+	content
+}
+
+/// Records a mutating operation on `target` in the audit table as part of the given transaction.
+///
+/// `diff` should describe the change (e.g. before/after values).
+/// The returned entry should be passed to [export] once the transaction is committed.
+pub async fn record<C>(transaction: &Transaction<C>, actor:&Actor, action:&str, target:&RecordId, diff:db_types::Value) -> Result<AuditEntry> where C:Connection
+{
+	transaction.query("CREATE ONLY audit CONTENT $content")
+		.bind(("content",content(actor, action, target, diff))).await
+		.and_then(|mut r|r.take::<Option<db_types::Value>>(0))
+		.context(format!("recording {action} on {target} in audit"))?
+		.ok_or(Error::NotFound)?
+		.try_into()
+}
+
+/// Records an already finished operation on `target` in the audit table and exports it.
+///
+/// As the operation cannot be undone anymore, failures are only logged.
+pub async fn log(actor:&Actor, action:&str, target:&RecordId, diff:serde_json::Value)
+{
+	let diff = crate::tools::conv::json_to_value(diff);
+	let entry:Result<AuditEntry> = DB.query("CREATE ONLY audit CONTENT $content")
+		.bind(("content",content(actor, action, target, diff))).await
+		.and_then(|mut r|r.take::<Option<db_types::Value>>(0))
+		.context(format!("recording {action} on {target} in audit"))
+		.and_then(|v|v.ok_or(Error::NotFound))
+		.and_then(AuditEntry::try_from);
+	match entry {
+		Ok(entry) => export(&entry).await,
+		Err(e) => warn!("Failed to record {action} on {target} in audit: {e}")
+	}
+}
+
+fn check_timestamp(name:&str, timestamp:&Option<String>) -> Result<()>
+{
+	match timestamp.as_deref().map(chrono::DateTime::parse_from_rfc3339) {
+		Some(Err(e)) => Err(InvalidFilter {name:name.into(),reason:format!("{e}, expected an RFC 3339 timestamp")}),
+		_ => Ok(())
+	}
+}
+
+/// Lists audit entries (newest first), optionally only those of `target`.
+pub async fn query(target:Option<&RecordId>, filter:&AuditFilter) -> Result<Vec<AuditEntry>>
+{
+	check_timestamp("since", &filter.since)?;
+	check_timestamp("until", &filter.until)?;
+	let mut conditions = vec!["true"];
+	if target.is_some() {conditions.push("target = $target")}
+	if filter.action.is_some() {conditions.push("action = $action")}
+	if filter.user.is_some() {conditions.push("actor = $user")}
+	if filter.source.is_some() {conditions.push("source = $source")}
+	if filter.since.is_some() {conditions.push("timestamp >= <datetime>$since")}
+	if filter.until.is_some() {conditions.push("timestamp <= <datetime>$until")}
+	let query = format!("SELECT * FROM audit WHERE {} ORDER BY timestamp DESC LIMIT $limit", conditions.join(" AND "));
+	DB.query(query)
+		.bind(("target",target.map(|t|t.0.clone())))
+		.bind(("action",filter.action.clone()))
+		.bind(("user",filter.user.clone()))
+		.bind(("source",filter.source.clone()))
+		.bind(("since",filter.since.clone()))
+		.bind(("until",filter.until.clone()))
+		.bind(("limit",filter.limit.unwrap_or(100) as i64))
+		.await?
+		.take::<Vec<db_types::Value>>(0)?
+		.into_iter().map(AuditEntry::try_from)
+		.collect::<Result<Vec<_>>>()
+		.context("querying audit")
+}
+
+/// Sends the entry to the syslog server configured as `syslog` in the `[audit]` section of the config (if any).
+///
+/// Failures are only logged, as the audited operation is already done.
+pub async fn export(entry:&AuditEntry)
+{
+	let Some(address) = &config::get().audit.syslog else {return};
+	let sent = match UdpSocket::bind("0.0.0.0:0").await {
+		Ok(socket) => socket.send_to(syslog_message(entry).as_bytes(), address).await,
+		Err(e) => Err(e)
+	};
+	if let Err(e) = sent {
+		warn!("Failed to send audit entry {} to {address}: {e}",entry.id);
+	}
+}
+
+fn xml_escape(s:&str) -> String
+{
+	s.replace('&',"&amp;").replace('"',"&quot;").replace('<',"&lt;").replace('>',"&gt;")
+}
+
+/// RFC 5424 syslog message with an IHE ATNA (DICOM PS3.15 A.5) like audit message
+pub fn syslog_message(entry:&AuditEntry) -> String
+{
+	let (code, text, action) = match entry.action.as_str() {
+		"store" | "import" | "move" => ("110104", "DICOM Instances Transferred", "C"),
+		"delete" => ("110105", "DICOM Study Deleted", "D"),
+		_ => ("110103", "DICOM Instances Accessed", "U"),
+	};
+	let now = chrono::Utc::now().to_rfc3339();
+	let source = entry.actor.source.as_deref().unwrap_or("unknown");
+	let user = entry.actor.user.as_deref().unwrap_or(source);
+	let message = format!(concat!(
+		r#"<AuditMessage><EventIdentification EventActionCode="{action}" EventDateTime="{now}" EventOutcomeIndicator="0">"#,
+		r#"<EventID csd-code="{code}" codeSystemName="DCM" originalText="{text}"/><EventTypeCode originalText="{event}"/></EventIdentification>"#,
+		r#"<ActiveParticipant UserID="{user}" NetworkAccessPointID="{source}" UserIsRequestor="true"/>"#,
+		r#"<AuditSourceIdentification AuditSourceID="{app}"/>"#,
+		r#"<ParticipantObjectIdentification ParticipantObjectID="{uid}" ParticipantObjectTypeCode="2">"#,
+		r#"<ParticipantObjectIDTypeCode originalText="{table}"/></ParticipantObjectIdentification></AuditMessage>"#),
+		action=action, now=now, code=code, text=text, event=xml_escape(&entry.action),
+		user=xml_escape(user), source=xml_escape(source), app=env!("CARGO_PKG_NAME"),
+		uid=xml_escape(&entry.target.str_key()), table=entry.target.table
+	);
+	// facility 10 (security/authorization), severity 5 (notice)
+	format!("<85>1 {now} - {} - DICOM+RFC3881 - {message}", env!("CARGO_PKG_NAME"))
+}
+
+impl TryFrom<db_types::Value> for AuditEntry
+{
+	type Error = Error;
+
+	fn try_from(value: db_types::Value) -> std::result::Result<Self, Self::Error> {
+		let kind = value.kind().to_string();
+		match value {
+			db_types::Value::Object(obj) => obj.try_into(),
+			_ => Err(UnexpectedResult {expected:"object".into(),found:kind})
+		}.context("parsing database object as audit entry")
+	}
+}
+
+impl TryFrom<db_types::Object> for AuditEntry
+{
+	type Error = Error;
+
+	fn try_from(mut obj: db_types::Object) -> std::result::Result<Self, Self::Error> {
+		let mut optional_string = |key:&str| obj.remove(key)
+			.filter(|v|!v.is_nullish())
+			.map(|v|v.into_string()).transpose();
+		let actor = Actor{user:optional_string("actor")?,source:optional_string("source")?};
+		Ok(AuditEntry{
+			id: RecordId(obj.pick_remove("id")?.into_record()?),
+			timestamp: obj.remove("timestamp").unwrap_or_default(),
+			actor,
+			action: obj.pick_remove("action")?.into_string()?,
+			target: RecordId(obj.pick_remove("target")?.into_record()?),
+			diff: obj.remove("diff").unwrap_or_default(),
+		})
+	}
+}
+
+impl From<&AuditEntry> for serde_json::Value
+{
+	fn from(entry: &AuditEntry) -> Self {
+		json!({
+			"timestamp":crate::tools::conv::value_to_json(entry.timestamp.clone()),
+			"actor":entry.actor.user,
+			"source":entry.actor.source,
+			"action":entry.action,
+			"target":entry.target.str_path(),
+			"diff":crate::tools::conv::value_to_json(entry.diff.clone()),
+		})
+	}
+}
@@ -1,24 +1,51 @@
-use crate::db::{lookup_uid, Entry, LocalSession, Session};
-use crate::dcm::{AttributeSelector, INSTANCE_TAGS, SERIES_TAGS, STUDY_TAGS};
+use crate::db::audit::{self, Actor};
+use crate::db::{aggregates, lookup_uid, Entry, LocalSession, Provenance, RegisterResult, Session};
+use crate::dcm::{self, AttributeSelector, INSTANCE_TAGS, SERIES_TAGS, STUDY_TAGS};
 use crate::tools::store::store_ob;
-use crate::{db, tools};
-use dicom::core::VR;
+use crate::config::AePermissions;
+use crate::{config, db, tools};
+use dicom::core::dictionary::VirtualVr;
+use dicom::core::{DataDictionary, VR};
 use dicom::dictionary_std::tags;
-use dicom::object::{FileDicomObject, InMemDicomObject};
+use dicom::object::{FileDicomObject, InMemDicomObject, StandardDataDictionary};
 use dimse::definitions::FailureCode;
 use dimse::identifier::Identifier;
 use dimse::io::ItemResult;
 use dimse::status::{failure, success, Comment, Offending, Status, StatusFailure};
 use dimse::RetrieveLevel;
 use futures::{stream, stream::BoxStream, StreamExt};
+use serde_json::json;
 use std::collections::{HashMap, VecDeque};
 use std::ops::Deref;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use dicom::object::mem::InMemElement;
 use surrealdb::types::ToSql;
+use tracing::warn;
 
-#[derive(Clone)]
-pub struct Accessor {}
+/// database access of the SCP for one association (see [crate::tools::gateway])
+#[derive(Clone,Default)]
+pub struct Accessor {
+	/// AE title of the peer, the permissions in `[dimse.default]` are used if it is not known
+	pub calling_ae:Option<String>,
+	/// address of the peer
+	pub peer:Option<SocketAddr>,
+}
+
+impl Accessor {
+	/// refuses (and logs) the operation if the calling AE is not allowed to do it
+	fn check(&self, operation:&str, allowed:impl FnOnce(&AePermissions)->bool) -> Result<(), StatusFailure>
+	{
+		let calling = self.calling_ae.as_deref();
+		if config::get().dimse.as_ref().is_none_or(|d|allowed(d.permissions(calling))) {
+			Ok(())
+		} else {
+			let calling = calling.unwrap_or("unknown AE");
+			warn!("refused {operation} from {calling}");
+			Err(failure(FailureCode::NotAuthorized).comment(format!("{calling} is not allowed to {operation}")))
+		}
+	}
+}
 
 impl dimse::io::FileAccess for Accessor {
 	type Item = Entry;
@@ -33,12 +60,21 @@ impl dimse::io::FileAccess for Accessor {
 	}
 
 	async fn store_file(&mut self, file: FileDicomObject<InMemDicomObject>) -> Status {
-		store_ob(file, &mut LocalSession::create(&db::DB,1)).await
+		self.check("store", |p|p.store)?;
+		let provenance = Provenance::dimse(self.calling_ae.clone(), self.peer.map(|p|p.ip().to_string()));
+		let stored = store_ob(file, &provenance, &mut LocalSession::create(&db::DB,1)).await;
+		if let Ok(RegisterResult::Stored(id)) = &stored {
+			let actor = Actor::dimse(self.calling_ae.as_deref(), self.peer.map(|p|p.ip()));
+			audit::log(&actor, "store", id, json!({})).await;
+		}
+		stored
 			.map_err(|e|failure(FailureCode::ProcessingFailure).comment(e))
 			.map(|_| success().into())
 	}
 
 	async fn lookup<'a>(&self, ident: impl Into<Identifier> + Send) -> Result<BoxStream<'a, Self::Item>, StatusFailure> {
+		// the destination itself is checked by the SCP, which only knows the allowed ones (see [crate::tools::gateway])
+		self.check("retrieve", |p|!p.move_destinations.is_empty())?;
 		let ident = ident.into();
 		// gather stuff we need from ident
 		let instance = ident.contains(tags::SOP_INSTANCE_UID).and_then(|e|e.to_str().ok());
@@ -76,6 +112,7 @@ impl dimse::io::FileAccess for Accessor {
 	}
 
 	async fn find<'a>(&self, ident: impl Into<Identifier> + Send) -> Result<BoxStream<'a, ItemResult<InMemDicomObject>>, StatusFailure> {
+		self.check("query", |p|p.query)?;
 		let ident = ident.into();
 		// let tz_offset = ident.contains(tags::TIMEZONE_OFFSET_FROM_UTC)
 		// 	.map(|e|e.to_str().map(Cow::into_owned)).transpose()
@@ -97,6 +134,12 @@ impl dimse::io::FileAccess for Accessor {
 				search_map.insert(attr.last_tag(),db_key.clone());
 			}
 		}
+		// aggregates named like an attribute (e.g. ModalitiesInStudy) can be queried as well
+		for name in aggregates::configured(table).keys().filter(|_|table != "instances") {
+			if let Some(tag) = dcm::find_tag(name) {
+				search_map.insert(tag, name.clone());
+			}
+		}
 
 		let entries = db::list_entries(table).await.map_err(|e|failure(FailureCode::ProcessingFailure).comment(e))?;
 		let mut ret =vec![];
@@ -110,7 +153,11 @@ impl dimse::io::FileAccess for Accessor {
 				matcher.put(InMemElement::new(t,VR::UI,id.str_key()));
 			}
 			for (tag, db) in &search_map {
-				if let Some(found) = entry.get(db).and_then(|v|v.as_string()){
+				let vr = match StandardDataDictionary::default().by_tag(*tag).map(|e|e.vr) {
+					Some(VirtualVr::Exact(vr)) => vr,
+					_ => VR::LO
+				};
+				if let Some(found) = entry.get(db).and_then(|v|db::dicom_string(v, vr)){
 					matcher.put_str(tag.to_owned(),VR::LO,found);
 				}
 			}
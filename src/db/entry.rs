@@ -6,6 +6,7 @@ use crate::tools::Error::{NotFound, UnexpectedResult};
 use crate::tools::{entries_for_record, reduce_path, Context, Result};
 use byte_unit::Byte;
 use std::path::PathBuf;
+use dicom::core::VR;
 use dicom::object::DefaultDicomObject;
 use crate::dcm::{extract, INSTANCE_TAGS, SERIES_TAGS, STUDY_TAGS};
 use surrealdb::types as db_types;
@@ -30,6 +31,11 @@ impl Entry
 	{
 		self.get(key).and_then(|v|v.as_string()).map(|s| s.as_str())
 	}
+	/// the column as DICOM would encode it (numbers, dates and names aren't stored as strings)
+	pub fn get_text(&self, key:&str, vr:VR) -> Option<String>
+	{
+		self.get(key).and_then(|v|db::dicom_string(v, vr))
+	}
 	pub fn id(&self) -> &RecordId {&self.as_ref()}
 
 	pub async fn get_aggregate(&self) -> Result<AggregateData>
@@ -62,20 +68,20 @@ impl Entry
 	{
 		match self {
 			Instance(_) => {
-				let number=self.get_string("Number").unwrap_or("<-->");
+				let number=self.get_text("Number",VR::IS).unwrap_or("<-->".into());
 				format!("Instance {number}")
 			},
 			Series(_) => {
-				let number=self.get_string("Number").unwrap_or("<-->");
+				let number=self.get_text("Number",VR::IS).unwrap_or("<-->".into());
 				let desc= self.get_string("Description").unwrap_or("<-->");
 				format!("S{number}_{desc}")
 			},
 			Study(_) => {
-				let id=self.get_string("Name").unwrap_or("<-->");
-				let mut date=self.get_string("Date").unwrap_or("<-->");
-				let mut time=self.get_string("Time").unwrap_or("<-->");
-				if date.len()>6 {date=&date[2..];}
-				if time.len()>6 {time=&time[..6]};
+				let id=self.get_text("Name",VR::PN).unwrap_or("<-->".into());
+				let date=self.get_text("Date",VR::DA).unwrap_or("<-->".into());
+				let time=self.get_text("Time",VR::TM).unwrap_or("<-->".into());
+				let date = date.get(2..).filter(|_|date.len()>6).unwrap_or(&date);
+				let time = time.get(..6).filter(|_|time.len()>6).unwrap_or(&time);
 				format!("{id}/{date}_{time}")
 			}
 		}
@@ -1,7 +1,7 @@
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use crate::db::Pickable;
+use crate::db::{Pickable, DB};
 use crate::storage::async_store::compute_md5;
 use crate::tools::{complete_filepath, Context, Error, Result};
 use dicom::object::{from_reader, DefaultDicomObject};
@@ -10,9 +10,25 @@ use serde::{Deserialize, Serialize, Serializer};
 use surrealdb::types as db_types;
 use tokio::task::spawn_blocking;
 use tracing::log::warn;
+use crate::config::Collision;
 use crate::dcm::gen_filepath;
+use dicom::dictionary_std::tags;
 use crate::tools::Error::{DicomError, FileIOError};
 
+/// how many alternative filenames are tried before giving up
+const MAX_COLLISIONS:usize = 1000;
+
+/// "dir/name.ext" => "dir/name<suffix>.ext"
+fn with_suffix(path:&Path, suffix:&str) -> PathBuf
+{
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	let name = match path.extension() {
+		Some(ext) => format!("{stem}{suffix}.{}",ext.to_string_lossy()),
+		None => format!("{stem}{suffix}")
+	};
+	path.with_file_name(name)
+}
+
 struct Md5Proxy<'a,R> where R: Sized
 {
 	context:&'a mut md5::Context,
@@ -48,14 +64,17 @@ pub struct File
 	path:PathBuf,
 	pub owned:bool,
 	md5:String,
-	pub size:u64
+	pub size:u64,
+	/// the path the filename pattern gave, if the file had to be stored elsewhere because that was taken
+	#[serde(default)]
+	pub intended:Option<PathBuf>,
 }
 
 impl File {
 	pub fn new<T>(path:T, md5:md5::Digest, owned:bool, size:u64) -> File where PathBuf:From<T>
 	{
 		let path = PathBuf::from(path);
-		File{path,size, owned, md5:format!("{:x}", md5)}
+		File{path,size, owned, md5:format!("{:x}", md5), intended:None}
 	}
 
 	/// get the complete path of the file
@@ -67,11 +86,59 @@ impl File {
 		else { self.path.to_path_buf() }
 	}
 	pub fn get_md5(&self) -> &str { self.md5.as_str() }
+	/// the same file after it was moved to `path`
+	pub fn moved_to(self, path:PathBuf) -> File { File{path,..self} }
 
 	/// writes a new file taking an object and returning that object plus a file info
 	pub async fn new_from_obj(obj:Arc<DefaultDicomObject>) -> Result<File>{
 		let path=PathBuf::from(gen_filepath(&obj)?);
-		let path = complete_filepath(&path);
+		Self::new_from_obj_avoiding(obj,complete_filepath(&path)).await
+	}
+	/// writes a new file for a newer version of an instance (the version number is added to the filename)
+	pub async fn new_versioned(obj:Arc<DefaultDicomObject>, version:i64) -> Result<File>{
+		let mut path=complete_filepath(&gen_filepath(&obj)?);
+		let extension = match path.extension() {
+			Some(ext) => format!("v{version}.{}",ext.to_string_lossy()),
+			None => format!("v{version}")
+		};
+		path.set_extension(extension);
+		Self::new_from_obj_avoiding(obj,path).await
+	}
+	/// writes a new file at the given (absolute) path or, if that is taken, where "on_collision" from the config says
+	async fn new_from_obj_avoiding(obj:Arc<DefaultDicomObject>, path:PathBuf) -> Result<File>{
+		let strategy = crate::config::get().paths.on_collision;
+		match Self::new_from_obj_at(obj.clone(),path.clone()).await {
+			Err(FileIOError{inner,..}) if inner.kind() == ErrorKind::AlreadyExists && strategy != Collision::Fail => {},
+			result => return result
+		}
+		let uid = obj.element(tags::SOP_INSTANCE_UID).ok()
+			.and_then(|e|e.to_str().ok())
+			.map(|uid|uid.trim_end_matches('\0').to_string())
+			.unwrap_or_default();
+		let hash = format!("{:x}", md5::compute(&uid));
+		let suffixes = (1..=MAX_COLLISIONS).map(|n|match strategy {
+			Collision::UidHash if n == 1 => format!("_{}",&hash[..8]),
+			Collision::UidHash => format!("_{}_{}",&hash[..8],n-1),
+			_ => format!("_{n}"),
+		});
+		for suffix in suffixes {
+			let candidate = with_suffix(&path, &suffix);
+			match Self::new_from_obj_at(obj.clone(),candidate.clone()).await {
+				Err(FileIOError{inner,..}) if inner.kind() == ErrorKind::AlreadyExists => continue,
+				Ok(file) => {
+					warn!("{} is already taken, storing {uid} as {}",path.display(),candidate.display());
+					return Ok(File{intended:Some(path),..file})
+				}
+				Err(e) => return Err(e)
+			}
+		}
+		Err(FileIOError{
+			inner:std::io::Error::new(ErrorKind::AlreadyExists, format!("{MAX_COLLISIONS} alternative filenames are taken as well")),
+			path
+		})
+	}
+	/// writes a new file at the given (absolute) path
+	pub async fn new_from_obj_at(obj:Arc<DefaultDicomObject>, path:PathBuf) -> Result<File>{
 		let p=path.parent().unwrap();
 		tokio::fs::create_dir_all(p).await
 			.context(format!("Failed creating storage path {}",p.display()))?;
@@ -150,6 +217,14 @@ impl File {
 	} 
 }
 
+/// instances that were stored under another filename than the pattern gave because that was taken
+pub async fn list_collisions() -> Result<Vec<db_types::Value>>
+{
+	DB.query("SELECT id, file.path AS path, file.intended AS intended FROM instances WHERE file.intended != NONE")
+		.await?.take::<Vec<db_types::Value>>(0)
+		.context("listing filename collisions")
+}
+
 impl TryFrom<db_types::Value> for File
 {
 	type Error = Error;
@@ -175,6 +250,10 @@ impl TryFrom<File> for db_types::Value
 		ret.insert("owned",file.owned);
 		ret.insert("md5",file.md5);
 		ret.insert("size",file.size);
+		if let Some(intended) = file.intended {
+			let intended = intended.to_str().ok_or(Error::InvalidFilename {name:intended.clone()})?;
+			ret.insert("intended",intended.to_string());
+		}
 		Ok(ret.into())
 	}
 }
@@ -182,7 +261,7 @@ impl TryFrom<File> for db_types::Value
 impl Serialize for File
 {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
-        let mut ser = serializer.serialize_struct("file",3)?;
+        let mut ser = serializer.serialize_struct("file",5)?;
         let file_path = self.path.to_str()
             .ok_or(Error::InvalidFilename {name:self.path.clone()})
             .map_err(serde::ser::Error::custom)?;
@@ -190,6 +269,14 @@ impl Serialize for File
         ser.serialize_field("owned",&self.owned)?;
         ser.serialize_field("md5",self.md5.as_str())?;
         ser.serialize_field("size",&self.size)?;
+        if let Some(intended) = &self.intended {
+            let intended = intended.to_str()
+                .ok_or(Error::InvalidFilename {name:intended.clone()})
+                .map_err(serde::ser::Error::custom)?;
+            ser.serialize_field("intended",intended)?;
+        } else {
+            ser.skip_field("intended")?;
+        }
         ser.end()
     }
 }
@@ -204,6 +291,9 @@ impl TryFrom<db_types::Object> for File
 		let md5 = obj.pick_remove("md5")?.into_string()?;
 		let size = obj.pick_remove("size")
 			.map(|v|if let db_types::Value::Number(num) = v { num.to_int().unwrap_or_default()} else {0})?;
-		Ok(File{path:path.into(),owned,md5,size:size as u64})
+		let intended = obj.remove("intended")
+			.and_then(|v|v.into_string().ok())
+			.map(PathBuf::from);
+		Ok(File{path:path.into(),owned,md5,size:size as u64,intended})
 	}
 }
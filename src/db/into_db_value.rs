@@ -1,15 +1,18 @@
-use chrono::{Local, TimeZone};
+use chrono::{Local, TimeZone, Utc};
 use dicom::core::chrono;
-use dicom::core::value::PreciseDateTime;
-use dicom::core::value::Value::{Primitive, Sequence};
-use dicom::core::{DataDictionary, PrimitiveValue};
+use dicom::core::value::{DicomDate, DicomDateTime, DicomTime, PreciseDateTime};
+use dicom::core::value::Value::{PixelSequence, Primitive, Sequence};
+use dicom::core::{DataDictionary, PrimitiveValue, Tag, VR};
 use dicom::object::mem::InMemElement;
 use dicom::object::{InMemDicomObject, StandardDataDictionary};
 use std::collections::BTreeMap;
 use surrealdb::types as db_types;
-use surrealdb::types::SurrealValue;
+use surrealdb::types::{SurrealValue, ToSql};
 
-fn flatten_iter<T,I>(iter:I) -> db_types::Value
+/// components of a person name group in the order DICOM encodes them
+const PN_COMPONENTS:[&str;5] = ["family","given","middle","prefix","suffix"];
+
+pub(crate) fn flatten_iter<T,I>(iter:I) -> db_types::Value
 	where I:Iterator<Item=T>, T:SurrealValue
 {
 	let mut vec:Vec<db_types::Value> = iter.map(T::into_value).collect();
@@ -20,6 +23,114 @@ fn flatten_iter<T,I>(iter:I) -> db_types::Value
 	}
 }
 
+fn object<const N:usize>(fields:[(&str,db_types::Value);N]) -> db_types::Value
+{
+	let obj:BTreeMap<_,_> = fields.into_iter().map(|(k,v)|(k.to_string(),v)).collect();
+	db_types::Value::Object(obj.into())
+}
+
+/// a value that doesn't fit its VR, kept as it is
+fn invalid(raw:&str) -> db_types::Value
+{
+	object([("invalid",true.into_value()),("raw",raw.to_string().into_value())])
+}
+
+/// binary data is only summarized
+fn binary(vr:VR, size:usize) -> db_types::Value
+{
+	object([("binary",true.into_value()),("vr",vr.to_string().into_value()),("size",size.into_value())])
+}
+
+/// complete dates become datetimes, partial ones are kept as they are encoded (e.g. "2025" or "202501")
+fn date(date:DicomDate) -> db_types::Value
+{
+	match date.to_naive_date() {
+		Ok(d) => d.and_time(chrono::NaiveTime::default()).and_utc().into_value(),
+		Err(_) => date.to_encoded().into_value()
+	}
+}
+
+/// complete times become datetimes (on 1970-01-01), partial ones are kept as they are encoded (e.g. "12" or "1230")
+fn time(time:DicomTime) -> db_types::Value
+{
+	match time.to_naive_time() {
+		Ok(t) => chrono::NaiveDate::default().and_time(t).and_utc().into_value(),
+		Err(_) => time.to_encoded().into_value()
+	}
+}
+
+/// complete datetimes without timezone are taken as local time, partial ones are kept as they are encoded
+fn datetime(datetime:DicomDateTime) -> db_types::Value
+{
+	match datetime.to_precise_datetime() {
+		Ok(PreciseDateTime::Naive(dt)) => Local.from_local_datetime(&dt).earliest()
+			.map_or_else(||dt.and_utc(),|dt|dt.to_utc())
+			.into_value(),
+		Ok(PreciseDateTime::TimeZone(dt)) => dt.to_utc().into_value(),
+		Err(_) => datetime.to_encoded().into_value()
+	}
+}
+
+/// `{family, given, middle, prefix, suffix}` of the alphabetic group plus `ideographic` and `phonetic` groups if there are any
+fn person_name(name:&str) -> db_types::Value
+{
+	let components = |group:&str| -> BTreeMap<String,db_types::Value> {
+		PN_COMPONENTS.iter().zip(group.split('^'))
+			.filter(|(_,c)|!c.trim().is_empty())
+			.map(|(k,c)|(k.to_string(),c.trim().to_string().into_value()))
+			.collect()
+	};
+	let mut groups = name.split('=');
+	let mut obj = components(groups.next().unwrap_or_default());
+	for (key,group) in ["ideographic","phonetic"].into_iter().zip(groups) {
+		let group = components(group);
+		if !group.is_empty() {
+			obj.insert(key.to_string(), db_types::Value::Object(group.into()));
+		}
+	}
+	db_types::Value::Object(obj.into())
+}
+
+/// a single string value interpreted according to the VR
+fn from_str(vr:VR, s:&str) -> db_types::Value
+{
+	let s = s.trim();
+	match vr {
+		VR::DA => PrimitiveValue::from(s).to_date().map_or_else(|_|invalid(s),date),
+		VR::TM => PrimitiveValue::from(s).to_time().map_or_else(|_|invalid(s),time),
+		VR::DT => PrimitiveValue::from(s).to_datetime().map_or_else(|_|invalid(s),datetime),
+		VR::DS => match s.parse::<f64>() {
+			Ok(number) if number.is_finite() => number.into_value(),
+			_ => invalid(s)
+		},
+		VR::IS => s.parse::<i64>().map_or_else(|_|invalid(s),SurrealValue::into_value),
+		VR::PN => person_name(s),
+		_ => s.to_owned().into_value()
+	}
+}
+
+/// the database representation of a primitive value of the given VR (and encoded size)
+fn from_primitive(vr:VR, size:usize, value:PrimitiveValue) -> db_types::Value
+{
+	use PrimitiveValue::*;
+	match (vr,value) {
+		(_,Empty) => db_types::Value::None,
+		(VR::OB | VR::OW | VR::OF | VR::OD | VR::OL | VR::OV | VR::UN, _) => binary(vr, size),
+		(_,Str(s)) => from_str(vr, &s),
+		(_,Strs(s)) => flatten_iter(s.iter().map(|s|from_str(vr, s))),
+		(_,value) => value.into_db_value()
+	}
+}
+
+/// the keyword of the tag or "(gggg,eeee)" if it's not in the dictionary
+pub(crate) fn element_name(tag:Tag) -> String
+{
+	match StandardDataDictionary::default().by_tag(tag) {
+		None => tag.to_string(),
+		Some(found) => found.alias.to_string()
+	}
+}
+
 pub trait IntoDbValue{
 	fn into_db_value(self) -> db_types::Value;
 }
@@ -29,11 +140,7 @@ impl IntoDbValue for InMemDicomObject{
 	fn into_db_value(self) -> db_types::Value {
 		let mut obj = BTreeMap::new();
 		for e in self{
-			let tag = e.header().tag;
-			let name = match StandardDataDictionary::default().by_tag(tag) {
-				None => tag.to_string(),
-				Some(found) => found.alias.to_string()
-			};
+			let name = element_name(e.header().tag);
 			let val = e.into_db_value();
 			obj.insert(name,val);
 		}
@@ -41,34 +148,15 @@ impl IntoDbValue for InMemDicomObject{
 	}
 }
 
+/// without a VR strings are kept as they are
 impl IntoDbValue for PrimitiveValue {
 	fn into_db_value(self) -> db_types::Value {
 		use PrimitiveValue::*;
 		match self {
 			Empty => db_types::Value::None, // no-op
-			Date(dates) => flatten_iter(dates.into_iter().
-				map(|date|
-					date.to_naive_date().expect("Invalid DICOM timestamp")
-						.and_time(chrono::NaiveTime::default())
-						.and_utc()
-				),
-			),
-			Time(times) => flatten_iter(times.into_iter()
-				.map(|time|
-					chrono::NaiveDate::default()
-						.and_time(time.to_naive_time().expect("Invalid DICOM timestamp"))
-						.and_utc()
-				),
-			),
-			DateTime(datetimes) => flatten_iter(datetimes.into_iter()
-				.map(|datetime|
-					match datetime.to_precise_datetime().expect("Invalid DICOM timestamp") {
-						PreciseDateTime::Naive(dt) => 
-							Local.from_local_datetime(&dt).unwrap().to_utc(),
-						PreciseDateTime::TimeZone(dt) => dt.to_utc()
-					}
-				),
-			),
+			Date(dates) => flatten_iter(dates.into_iter().map(date)),
+			Time(times) => flatten_iter(times.into_iter().map(time)),
+			DateTime(datetimes) => flatten_iter(datetimes.into_iter().map(datetime)),
 			Str(s) => s.trim().to_owned().into_value(),
 			Strs(s) => flatten_iter(s.into_iter().map(|s|s.trim().to_owned().into_value())),
 			F32(values) => flatten_iter(values.into_iter()),
@@ -88,11 +176,13 @@ impl IntoDbValue for PrimitiveValue {
 
 impl IntoDbValue for InMemElement{
 	fn into_db_value(self) -> db_types::Value {
+		let vr = self.vr();
+		let size = self.header().len.get().unwrap_or_default() as usize;
 		match self.into_value() {
-			Primitive(p) => p.into_db_value(),
+			Primitive(p) => from_primitive(vr, size, p),
 			Sequence (s)  =>
 				flatten_iter(s.into_items().into_iter().map(InMemDicomObject::into_db_value)),
-			_ => {todo!()}
+			PixelSequence(p) => binary(vr, p.fragments().iter().map(Vec::len).sum()),
 		}
 	}
 }
@@ -103,3 +193,55 @@ impl<T> IntoDbValue for Option<T> where T:IntoDbValue
 		self.map_or(db_types::Value::None,T::into_db_value)
 	}
 }
+
+/// whether the object is a person name as stored by [person_name]
+fn is_person_name(obj:&db_types::Object) -> bool
+{
+	!obj.is_empty() && obj.keys().all(|k|PN_COMPONENTS.contains(&k.as_str()) || k == "ideographic" || k == "phonetic")
+}
+
+/// the value as text to be shown, names and invalid values in their DICOM encoding, multiple values separated by "\\"
+pub fn display_string(value:&db_types::Value) -> String
+{
+	match value {
+		db_types::Value::String(s) => s.to_string(),
+		db_types::Value::Array(a) => a.iter().map(display_string).collect::<Vec<_>>().join("\\"),
+		db_types::Value::Object(o) if is_person_name(o) || o.get("invalid").is_some() =>
+			dicom_string(value, VR::PN).unwrap_or_default(),
+		_ => value.to_sql()
+	}
+}
+
+/// the DICOM encoding of a value stored for the given VR (None for binary data and sequences)
+pub fn dicom_string(value:&db_types::Value, vr:VR) -> Option<String>
+{
+	match value {
+		db_types::Value::String(s) => Some(s.to_string()),
+		db_types::Value::Number(_) => Some(value.to_sql()),
+		db_types::Value::Array(a) => a.iter().map(|v|dicom_string(v, vr)).collect::<Option<Vec<_>>>().map(|v|v.join("\\")),
+		db_types::Value::Object(o) if o.get("invalid").is_some() => o.get("raw").and_then(|raw|dicom_string(raw, vr)),
+		db_types::Value::Object(o) if vr == VR::PN => {
+			let group = |o:&db_types::Object| PN_COMPONENTS.iter()
+				.map(|c|o.get(*c).and_then(|v|v.as_string()).map_or("",|s|s.as_str()))
+				.collect::<Vec<_>>().join("^").trim_end_matches('^').to_string();
+			let other = |key:&str| match o.get(key) {
+				Some(db_types::Value::Object(g)) => Some(group(g)),
+				_ => None
+			};
+			Some(match (other("ideographic"), other("phonetic")) {
+				(None, None) => group(o),
+				(ideographic, None) => format!("{}={}", group(o), ideographic.unwrap_or_default()),
+				(ideographic, Some(phonetic)) => format!("{}={}={phonetic}", group(o), ideographic.unwrap_or_default()),
+			})
+		}
+		_ => {
+			let dt = chrono::DateTime::<Utc>::from_value(value.clone()).ok()?;
+			let format = match vr {
+				VR::DA => "%Y%m%d",
+				VR::TM => "%H%M%S%.f",
+				_ => "%Y%m%d%H%M%S%.f",
+			};
+			Some(dt.format(format).to_string())
+		}
+	}
+}
@@ -0,0 +1,261 @@
+use crate::config::{self, MetadataCfg};
+use crate::db::into_db_value::{element_name, flatten_iter};
+use crate::db::{aggregates, list_entries, Entry, IntoDbValue, RecordId, DB};
+use crate::dcm::{find_tag, AttributeSelector, INSTANCE_TAGS, SERIES_TAGS, STUDY_TAGS};
+use crate::tools::conv::{json_to_value, value_to_json};
+use crate::tools::Error::InvalidTable;
+use crate::tools::{Context, Result};
+use dicom::core::value::Value;
+use dicom::core::Tag;
+use dicom::object::InMemDicomObject;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use surrealdb::method::Transaction;
+use surrealdb::types as db_types;
+use surrealdb::types::SurrealValue;
+use surrealdb::Connection;
+
+/// a tag given by name or as "(gggg,eeee)" where "x" matches any hex digit e.g. "(0029,xxxx)"
+#[derive(Debug,Clone,PartialEq)]
+pub struct TagPattern{
+	text:String,
+	/// value and mask
+	group:(u16,u16),
+	element:(u16,u16),
+}
+
+/// "00x9" => (0x0009, 0xFF0F)
+fn masked(s:&str) -> Option<(u16,u16)>
+{
+	if s.len() != 4 {return None}
+	s.chars().try_fold((0_u16,0_u16),|(value,mask),c| match c {
+		'x' | 'X' => Some((value << 4, mask << 4)),
+		c => Some((value << 4 | c.to_digit(16)? as u16, mask << 4 | 0xF))
+	})
+}
+
+impl TagPattern {
+	pub fn matches(&self, tag:Tag) -> bool
+	{
+		(tag.group() & self.group.1) == self.group.0 && (tag.element() & self.element.1) == self.element.0
+	}
+}
+
+impl FromStr for TagPattern {
+	type Err = String;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		let text = s.trim().to_string();
+		let parts = text.strip_prefix('(').and_then(|t|t.strip_suffix(')')).and_then(|t|t.split_once(','));
+		if let Some((group,element)) = parts && let (Some(group),Some(element)) = (masked(group.trim()),masked(element.trim())) {
+			return Ok(TagPattern{text,group,element})
+		}
+		let tag = find_tag(&text).ok_or(format!("{text} is neither a tag name nor a pattern like (0029,xxxx)"))?;
+		Ok(TagPattern{group:(tag.group(),0xFFFF),element:(tag.element(),0xFFFF),text})
+	}
+}
+
+impl Display for TagPattern {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {f.write_str(&self.text)}
+}
+
+/// builds the metadata document keeping track of its size and what was left out
+struct Builder{
+	cfg:&'static MetadataCfg,
+	size:u64,
+	omitted:Vec<String>,
+}
+
+impl Builder {
+	fn object(&mut self, obj:&InMemDicomObject, parent:&str) -> db_types::Value
+	{
+		let mut ret = BTreeMap::new();
+		for e in obj {
+			let tag = e.header().tag;
+			// pixel data is never part of the metadata
+			if tag.group() == 0x7FE0 || self.cfg.exclude.iter().any(|p|p.matches(tag)) {continue}
+			let name = element_name(tag);
+			let path = if parent.is_empty() {name.clone()} else {format!("{parent}.{name}")};
+			let value = match e.value() {
+				Value::Sequence(seq) => flatten_iter(seq.items().iter().map(|item|self.object(item, &path))),
+				_ if e.header().len.get().is_some_and(|len|u64::from(len) > self.cfg.max_element_size.as_u64()) => {
+					self.omitted.push(path);
+					continue
+				}
+				_ => {
+					let value = e.clone().into_db_value();
+					// sizes of sequences are those of their elements
+					let size = (name.len() + value_to_json(value.clone()).to_string().len()) as u64;
+					if self.size + size > self.cfg.max_size.as_u64() {
+						self.omitted.push(path);
+						continue
+					}
+					self.size += size;
+					value
+				}
+			};
+			if !value.is_none() {
+				ret.insert(name, value);
+			}
+		}
+		db_types::Value::Object(ret.into())
+	}
+}
+
+/// the record in `instance_metadata` holding the metadata of the instance (it has the same key)
+fn metadata_id(instance:&RecordId) -> db_types::RecordId
+{
+	db_types::RecordId::new("instance_metadata", instance.key.clone())
+}
+
+/// Stores `metadata` and `metadata_omitted` (paths of elements that were left out because of the size limits) of an
+/// instance in `instance_metadata` as part of its registration (nothing if disabled in the config).
+///
+/// They are kept apart from the instance, so they are only read if asked for (see [get]).
+pub(crate) async fn store<C>(instance:&RecordId, obj:&InMemDicomObject, transaction:&Transaction<C>) -> Result<()> where C:Connection
+{
+	let cfg = &config::get().metadata;
+	if !cfg.enabled {return Ok(())}
+	let mut builder = Builder{cfg, size:0, omitted:vec![]};
+	let mut content = db_types::Object::default();
+	content.insert("instance", instance.0.clone().into_value());
+	content.insert("metadata", builder.object(obj, ""));
+	if !builder.omitted.is_empty() {
+		content.insert("metadata_omitted", builder.omitted.into_value());
+	}
+	transaction.query("UPSERT $rec CONTENT $content RETURN NONE")
+		.bind(("rec",metadata_id(instance)))
+		.bind(("content",content)).await?
+		.check().context(format!("storing metadata of {instance}"))?;
+	Ok(())
+}
+
+/// `metadata` and `metadata_omitted` of the instance (None if none were stored)
+pub async fn get(instance:&RecordId) -> Result<Option<db_types::Object>>
+{
+	let found = DB.query("SELECT metadata, metadata_omitted FROM ONLY $rec")
+		.bind(("rec",metadata_id(instance))).await?
+		.take::<Option<db_types::Value>>(0)
+		.context(format!("looking up metadata of {instance}"))?;
+	Ok(match found {
+		Some(db_types::Value::Object(obj)) => Some(obj),
+		_ => None
+	})
+}
+
+/// moves metadata stored on the instance records by older versions into `instance_metadata` (once, marked by `meta:metadata`)
+pub(crate) async fn migrate() -> Result<()>
+{
+	DB.query("IF (meta:metadata.version ?? 0) < 1 {
+		FOR $rec IN (SELECT id, metadata, metadata_omitted FROM instances WHERE metadata != NONE) {
+			UPSERT type::record('instance_metadata', record::id($rec.id)) CONTENT {instance:$rec.id, metadata:$rec.metadata, metadata_omitted:$rec.metadata_omitted} RETURN NONE;
+			UPDATE $rec.id UNSET metadata, metadata_omitted RETURN NONE;
+		};
+		UPSERT meta:metadata SET version = 1 RETURN NONE;
+	};").await?
+		.check().context("moving metadata of instances into instance_metadata")?;
+	Ok(())
+}
+
+/// what [rederive] is recorded for in the audit
+pub fn rederive_target() -> RecordId
+{
+	RecordId(db_types::RecordId::new("instance_metadata", "rederive"))
+}
+
+fn ident(name:&str) -> String
+{
+	format!("`{}`", name.replace('`', "\\`"))
+}
+
+/// "Sequence.Element" => "$m.`Sequence`.`Element`"
+fn path(base:&str, name:&str) -> String
+{
+	name.split('.').fold(base.to_string(), |path,step|format!("{path}.{}",ident(step)))
+}
+
+/// entries of the table (instances, series or studies) with instances whose metadata matches all filters
+///
+/// filters are element names (paths into sequences joined by ".") and values the element must be equal to or contain (numbers are compared as numbers)
+pub async fn query(table:&str, filters:&BTreeMap<String,String>) -> Result<Vec<Entry>>
+{
+	let field = match table {
+		"instances" => "instance",
+		"series" => "instance.series",
+		"studies" => "instance.series.study",
+		_ => return Err(InvalidTable{table:table.into()})
+	};
+	let conditions:Vec<_> = std::iter::once("true".to_string())
+		.chain(filters.keys().enumerate().map(|(n,name)|{
+			let element = path("metadata", name);
+			format!("({element} = $values[{n}] OR {element} CONTAINS $values[{n}])")
+		}))
+		.collect();
+	let values:Vec<_> = filters.values()
+		.map(|v|match serde_json::from_str::<serde_json::Value>(v) {
+			Ok(number) if number.is_number() => json_to_value(number),
+			_ => v.clone().into_value()
+		})
+		.collect();
+	let query = format!(
+		"LET $found = array::distinct((SELECT VALUE {field} FROM instance_metadata WHERE {}));\nSELECT * FROM $found;",
+		conditions.join(" AND ")
+	);
+	DB.query(query).bind(("values", values)).await?
+		.take::<Vec<db_types::Value>>(1)
+		.context(format!("querying metadata of {table}"))?
+		.into_iter().map(Entry::try_from).collect()
+}
+
+/// the SurrealQL setting the columns from the metadata in $m and the columns that can't be set that way
+fn assignments(columns:&HashMap<String,Vec<AttributeSelector>>) -> (Vec<String>,Vec<String>)
+{
+	let mut sets = vec![];
+	let mut skipped = vec![];
+	for (name,selectors) in columns {
+		// only plain tags are in the metadata as they are
+		let paths:Option<Vec<_>> = selectors.iter().map(|s|match s {
+			AttributeSelector::Core(c) if *s == AttributeSelector::from(c.last_tag()) => Some(path("$m", &element_name(c.last_tag()))),
+			_ => None
+		}).collect();
+		match paths {
+			Some(paths) => sets.push(format!("{} = {}", ident(name), paths.join(" ?? "))),
+			None => skipped.push(name.clone())
+		}
+	}
+	(sets,skipped)
+}
+
+/// sets the configured columns of all entries from the stored metadata (of their first instance) without reading any file
+///
+/// returns the columns that can't be derived from the metadata (anything but plain tags)
+pub async fn rederive() -> Result<Vec<String>>
+{
+	let mut skipped = vec![];
+	let tables = [
+		("instances", &*INSTANCE_TAGS, "instance_metadata", "$rec.instance", "$rec.metadata"),
+		("series", &*SERIES_TAGS, "series", "$rec", "(SELECT VALUE metadata FROM instance_metadata WHERE instance IN $rec.instances LIMIT 1)[0]"),
+		("studies", &*STUDY_TAGS, "studies", "$rec", "(SELECT VALUE metadata FROM instance_metadata WHERE instance IN array::flatten($rec.series.instances) LIMIT 1)[0]"),
+	];
+	for (table,columns,records,target,metadata) in tables {
+		let (sets,columns_skipped) = assignments(columns);
+		skipped.extend(columns_skipped.into_iter().map(|c|format!("{table}.{c}")));
+		if sets.is_empty() {continue}
+		let query = format!(
+			"FOR $rec IN (SELECT VALUE id FROM {records}) {{ LET $m = {metadata}; IF $m != NONE {{ UPDATE {target} SET {} RETURN NONE; }}; }};",
+			sets.join(", ")
+		);
+		DB.query(query).await
+			.and_then(|r|r.check().map(|_|()))
+			.context(format!("deriving columns of {table} from metadata"))?;
+	}
+	for table in ["series", "studies"] {
+		if aggregates::configured(table).is_empty() {continue}
+		for entry in list_entries(table).await? {
+			aggregates::refresh(entry.id()).await?;
+		}
+	}
+	skipped.sort();
+	Ok(skipped)
+}
@@ -0,0 +1,89 @@
+use crate::db::{aggregates, metadata, search, Entry, RecordId, DB};
+use crate::dcm::{INSTANCE_TAGS, SERIES_TAGS, STUDY_TAGS};
+use crate::tools::correct::columns;
+use crate::tools::{extract_from_dicom, Context, Result};
+use dicom::dictionary_std::tags;
+use std::collections::{BTreeMap, HashSet};
+use surrealdb::types as db_types;
+use tracing::{info, warn};
+
+/// version of the representation of column values, stored as `meta:values`
+///
+/// 1: values are mapped according to their VR (see [crate::db::IntoDbValue]), before everything was stored as string
+pub const VALUES_VERSION:i64 = 1;
+
+async fn values_version() -> Result<i64>
+{
+	DB.query("RETURN meta:values.version ?? 0").await?
+		.take::<Option<i64>>(0)
+		.map(Option::unwrap_or_default)
+		.context("reading the version of the stored values")
+}
+
+async fn merge(id:&RecordId, content:BTreeMap<String,db_types::Value>) -> Result<()>
+{
+	DB.query("UPDATE $rec MERGE $content RETURN NONE")
+		.bind(("rec",id.0.clone()))
+		.bind(("content",content)).await?
+		.check().context(format!("converting the columns of {id}"))?;
+	Ok(())
+}
+
+/// converts the columns of an instance and (if not done yet) of its series and study by extracting them from its file again
+async fn convert(entry:&Entry, done:&mut HashSet<String>, parents:&mut Vec<RecordId>) -> Result<()>
+{
+	let obj = entry.get_file()?.read().await?;
+	merge(entry.id(), columns(&obj, &INSTANCE_TAGS)).await?;
+	let series = RecordId::from_series(extract_from_dicom(&obj, tags::SERIES_INSTANCE_UID)?.as_ref());
+	let study = RecordId::from_study(extract_from_dicom(&obj, tags::STUDY_INSTANCE_UID)?.as_ref());
+	for (id, columns) in [(series, columns(&obj, &SERIES_TAGS)), (study, columns(&obj, &STUDY_TAGS))] {
+		if done.insert(id.to_string()) {
+			merge(&id, columns).await?;
+			parents.push(id);
+		}
+	}
+	Ok(())
+}
+
+/// Columns stored before [VALUES_VERSION] are extracted from the files again (which may take a while, but is only done once).
+/// Instances whose files can't be read keep their columns as they are.
+async fn convert_values() -> Result<()>
+{
+	if values_version().await? >= VALUES_VERSION {return Ok(())}
+	let instances = DB.query("SELECT id, file FROM instances").await?
+		.take::<Vec<db_types::Value>>(0)?
+		.into_iter().map(Entry::try_from)
+		.collect::<Result<Vec<_>>>()
+		.context("listing instances to convert")?;
+	if !instances.is_empty() {
+		info!("converting the columns of {} instances to value version {VALUES_VERSION}", instances.len());
+	}
+	let mut done = HashSet::new();
+	let mut parents = vec![];
+	for entry in &instances {
+		if let Err(e) = convert(entry, &mut done, &mut parents).await {
+			warn!("{} could not be converted, its columns are kept as they are: {e}", entry.id());
+		}
+	}
+	// series before studies as the studies may aggregate series aggregates
+	parents.sort_by_key(|id|id.table.as_str() == "studies");
+	for id in &parents {
+		aggregates::refresh(id).await?;
+	}
+	DB.query("UPSERT meta:values SET version = $version RETURN NONE")
+		.bind(("version",VALUES_VERSION)).await?
+		.check().context("storing the version of the stored values")?;
+	Ok(())
+}
+
+/// Converts data stored by older versions, so new instances don't conflict with the stored ones.
+///
+/// - metadata stored on the instances is moved into its own table
+/// - columns are converted to the current [VALUES_VERSION]
+/// - search terms are collected for series that don't have any yet
+pub async fn migrate() -> Result<()>
+{
+	metadata::migrate().await?;
+	convert_values().await?;
+	search::backfill().await
+}
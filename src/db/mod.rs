@@ -3,10 +3,12 @@ use crate::tools::{Context, Error, Result};
 use byte_unit::Byte;
 use byte_unit::UnitType::Binary;
 pub use entry::Entry;
-pub use file::File;
-pub use into_db_value::IntoDbValue;
+pub use file::{File, list_collisions};
+pub use into_db_value::{dicom_string, display_string, IntoDbValue};
 pub use record::RecordId;
-pub use register::{register_instance,FileInfo};
+pub use register::{register_instance,register_instance_replacement,register_instance_version,FileInfo};
+pub use version::{list_versions, lookup_version, Version};
+pub use provenance::Provenance;
 pub use session::{Session, LocalSession, SharedSession, TransactionGuard};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -25,7 +27,15 @@ mod entry;
 mod file;
 mod record;
 mod session;
+pub(crate) mod version;
+pub mod audit;
+pub mod users;
+pub mod provenance;
 pub mod dimse_access;
+pub mod aggregates;
+pub mod metadata;
+pub mod search;
+pub mod migrate;
 
 #[derive(Deserialize,Debug,SurrealValue)]
 pub struct AggregateData
@@ -44,6 +54,13 @@ pub enum RegisterResult
 	Stored(RecordId),
 	AlreadyStored(RecordId),
 }
+impl RegisterResult
+{
+	pub fn id(&self) -> &RecordId
+	{
+		match self {RegisterResult::Stored(id) | RegisterResult::AlreadyStored(id) => id}
+	}
+}
 pub async fn list_entries<T>(table:T) -> Result<Vec<Entry>> where Resource: From<T>
 {
 	let val = DB.select::<Value>(Resource::from(table)).await?;
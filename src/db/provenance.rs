@@ -0,0 +1,119 @@
+use crate::db::audit::Actor;
+use crate::db::{Entry, DB};
+use crate::tools::Error::UnexpectedResult;
+use crate::tools::{Context, Error, Result};
+use serde::Deserialize;
+use surrealdb::types as db_types;
+use surrealdb::types::SurrealValue;
+
+/// where the data of an instance came from, stored as `provenance` of the instance
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Provenance
+{
+	/// how the data arrived ("dimse", "http", "local" or the import mode "import", "store" or "move")
+	pub channel:String,
+	/// the authenticated user (if any)
+	pub user:Option<String>,
+	/// IP address of the http client or DIMSE peer
+	pub address:Option<String>,
+	/// calling AE title of the DIMSE peer
+	pub calling_ae:Option<String>,
+	/// the glob of filesystem imports
+	pub pattern:Option<String>,
+}
+
+impl Provenance
+{
+	/// objects stored locally (e.g. by tools or tests)
+	pub fn local() -> Provenance {Provenance{channel:"local".into(),..Default::default()}}
+	/// uploads via http
+	pub fn http(actor:&Actor) -> Provenance
+	{
+		Provenance{channel:"http".into(),user:actor.user.clone(),address:actor.source.clone(),..Default::default()}
+	}
+	/// C-STORE of a DIMSE peer
+	pub fn dimse(calling_ae:Option<String>, address:Option<String>) -> Provenance
+	{
+		Provenance{channel:"dimse".into(),calling_ae,address,..Default::default()}
+	}
+	/// filesystem imports via `pattern` (`mode` is "import", "store" or "move")
+	pub fn import(actor:&Actor, mode:&str, pattern:&str) -> Provenance
+	{
+		Provenance{
+			channel:mode.into(),
+			user:actor.user.clone(),
+			address:actor.source.clone(),
+			pattern:Some(pattern.into()),
+			..Default::default()
+		}
+	}
+}
+
+/// filter for [query], all given conditions must match
+#[derive(Deserialize,Default,Clone)]
+pub struct ProvenanceFilter
+{
+	pub channel:Option<String>,
+	pub user:Option<String>,
+	pub address:Option<String>,
+	pub calling_ae:Option<String>,
+	pub pattern:Option<String>,
+	/// maximum number of instances returned (newest first), defaults to 100
+	pub limit:Option<u32>,
+}
+
+/// Lists instances (newest first) whose provenance matches the filter.
+pub async fn query(filter:&ProvenanceFilter) -> Result<Vec<Entry>>
+{
+	let fields = [
+		("channel",&filter.channel), ("user",&filter.user), ("address",&filter.address),
+		("calling_ae",&filter.calling_ae), ("pattern",&filter.pattern)
+	];
+	let conditions:Vec<_> = ["true".to_string()].into_iter()
+		.chain(fields.iter().filter(|(_,v)|v.is_some()).map(|(k,_)|format!("provenance.{k} = ${k}")))
+		.collect();
+	let query = format!("SELECT * FROM instances WHERE {} ORDER BY timestamp DESC LIMIT $limit", conditions.join(" AND "));
+	let mut query = DB.query(query).bind(("limit",filter.limit.unwrap_or(100) as i64));
+	for (k,v) in fields {
+		query = query.bind((k,v.clone()));
+	}
+	query.await?
+		.take::<Vec<db_types::Value>>(0)?
+		.into_iter().map(Entry::try_from)
+		.collect::<Result<Vec<_>>>()
+		.context("querying instances by provenance")
+}
+
+impl From<Provenance> for db_types::Value
+{
+	fn from(provenance: Provenance) -> Self {
+		let mut obj = db_types::Object::default();
+		obj.insert("channel", provenance.channel);
+		obj.insert("user", provenance.user.into_value());
+		obj.insert("address", provenance.address.into_value());
+		obj.insert("calling_ae", provenance.calling_ae.into_value());
+		obj.insert("pattern", provenance.pattern.into_value());
+		db_types::Value::Object(obj)
+	}
+}
+
+impl TryFrom<db_types::Value> for Provenance
+{
+	type Error = Error;
+
+	fn try_from(value: db_types::Value) -> std::result::Result<Self, Self::Error> {
+		let kind = value.kind().to_string();
+		let db_types::Value::Object(mut obj) = value
+			else {return Err(UnexpectedResult {expected:"object".into(),found:kind})};
+		let mut optional_string = |key:&str| obj.remove(key)
+			.filter(|v|!v.is_nullish())
+			.map(|v|v.into_string()).transpose();
+		Ok(Provenance{
+			user:optional_string("user")?,
+			address:optional_string("address")?,
+			calling_ae:optional_string("calling_ae")?,
+			pattern:optional_string("pattern")?,
+			channel:optional_string("channel")?.unwrap_or_default(),
+		})
+	}
+}
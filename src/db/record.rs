@@ -21,6 +21,10 @@ impl RecordId {
 	{
 		RecordId(db_types::RecordId::new("studies",id))
 	}
+	pub fn from_quarantine(id: impl Into<RecordIdKey>) -> RecordId
+	{
+		RecordId(db_types::RecordId::new("quarantine",id))
+	}
 	pub fn str_key(&self) -> String {
 		self.0.key.clone().into_value().into_string().unwrap()
 	}
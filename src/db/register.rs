@@ -1,9 +1,11 @@
 use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
 use std::sync::Arc;
-use crate::db::{if_retry, Entry, File, RecordId, RegisterResult, Session};
+use crate::db::{aggregates, if_retry, metadata, search, version, Entry, File, Provenance, RecordId, RegisterResult, Session};
 use crate::dcm::{INSTANCE_TAGS, SERIES_TAGS, STUDY_TAGS};
-use crate::tools::{extract_from_dicom, Error};
-use crate::{dcm, tools};
+use crate::tools::events::{self, Event};
+use crate::tools::{completeness, extract_from_dicom, routing, Error};
+use crate::{config, dcm, tools};
 use dcm::AttributeSelector;
 use dicom::dictionary_std::tags;
 use dicom::object::DefaultDicomObject;
@@ -16,7 +18,7 @@ use tracing::{debug};
 use crate::tools::Error::{DataConflict, FieldConflict};
 
 #[derive(Default,Debug,Clone,SurrealValue)]
-struct Diff
+pub(crate) struct Diff
 {
 	op:String,
 	path:String,
@@ -52,7 +54,7 @@ fn prepare_content<'a>(
 	obj:&DefaultDicomObject,
 	add_meta:impl IntoIterator<Item=(&'a str, db_types::Value)>,
 	tags:&'a HashMap<String, Vec<AttributeSelector>>
-) -> impl SurrealValue
+) -> BTreeMap<String, db_types::Value>
 {
 	dcm::extract(&obj, &tags).into_iter()
 		.chain(add_meta)
@@ -66,7 +68,7 @@ async fn insert<'a,C>(
 	add_meta:impl IntoIterator<Item=(&'a str,db_types::Value)>,
 	tags:&'a HashMap<String, Vec<AttributeSelector>>,
 	transaction: &Transaction<C>
-) -> tools::Result<bool> where C:Connection
+) -> tools::Result<Option<Entry>> where C:Connection
 {
 	let meta= prepare_content(obj, add_meta, tags).into_value();
 	// use UPSERT so we can get a BEFORE to compare it if data existed, the whole transaction will
@@ -74,24 +76,19 @@ async fn insert<'a,C>(
 	let q = transaction.query("UPSERT ONLY $rec CONTENT $content RETURN BEFORE")
 		.bind(("content",meta)).bind(("rec",record_id.0.clone()));
 
-	if let Some(existing) = q.await?
+	q.await?
 		.take::<Option<db_types::Value>>(0)?
-		.map(Entry::try_from).transpose()?
-	{
-		if existing == *obj {Ok(false)} // @todo should not compare file as this validly might not be there yet
-		else {Err(DataConflict(existing))}
-	} else {
-		Ok(true)
-	}
+		.map(Entry::try_from).transpose()
 }
 
+/// merges the data into the (possibly already existing) record and returns all conflicting fields
 async fn upsert<'a,C>(
 	obj:&DefaultDicomObject,
 	record_id: &RecordId,
 	add_meta:Vec<(&'a str,db_types::Value)>,
 	tags:&HashMap<String,Vec<AttributeSelector>>,
 	transaction: &Transaction<C>
-) -> tools::Result<bool> where C:Connection
+) -> tools::Result<Vec<Diff>> where C:Connection
 {
 	let meta= prepare_content(obj, add_meta, tags);
 	let q = transaction.query("UPSERT ONLY $rec MERGE $content RETURN diff")
@@ -100,13 +97,26 @@ async fn upsert<'a,C>(
 		.filter(|d|d.op!="add")
 		.filter(|d|!d.path.starts_with("/instances")).filter(|d|!d.path.starts_with("/series"))
 		.collect::<Vec<_>>();
-	if diff.is_empty(){
-		Ok(true)
-	} else {
+	if !diff.is_empty(){
 		debug!("Field conflicts in {}:\n{}", record_id, diff.clone().into_value().to_sql_pretty());
-		Err(FieldConflict{ fields: diff.into_iter().map(|d|format!("{}",d.path)).join(":"), id: record_id.clone() })
 	}
+	Ok(diff)
+}
+
+/// differences of the configured columns of an existing entry and a dicom object
+fn column_diff(existing:&Entry, obj:&DefaultDicomObject, tags:&HashMap<String,Vec<AttributeSelector>>) -> Vec<Diff>
+{
+	let mut new:BTreeMap<_,_> = dcm::extract(obj, tags).into_iter().collect();
+	tags.keys().filter_map(|key|{
+		match (existing.get(key), new.remove(key.as_str())) {
+			(Some(old), Some(value)) if *old != value => Some(Diff{op:"replace".into(),path:format!("/{key}"),value}),
+			(None, Some(value)) => Some(Diff{op:"add".into(),path:format!("/{key}"),value}),
+			(Some(old), None) => Some(Diff{op:"remove".into(),path:format!("/{key}"),value:old.clone()}),
+			_ => None
+		}
+	}).collect()
 }
+
 /// Register a dicom object of an instance.
 /// 
 /// If the instance already exists and is equal, no change is done and Ok(false) is returned
@@ -114,25 +124,69 @@ async fn upsert<'a,C>(
 /// ## Arguments 
 /// 
 /// * `obj`: the dicom instance to be registered 
-/// * `add_meta`: additional key value pairs to be added to the entry
-/// * `guard`: optional guard that can be roll back the registration of Drop if not confirmed
+/// * `file_info`: the file of the instance (or if it still has to be stored)
+/// * `provenance`: where the instance came from (stored as `provenance` in the entry)
 /// 
 /// ## returns: 
 /// * Ok(true) if the instance was registered
 /// * Ok(false) if the instance already exists and is equal
 /// * Err(ExistingDifferent(Entry)) if the instance was already registered and different
+///   (unless `versioning` is enabled in the config, see [register_instance_version])
 /// * Error(tools::Error) if another error occurred 
 /// 
 pub async fn register_instance<S>(
 	obj:impl Into<Arc<DefaultDicomObject>>,
 	file_info:&mut FileInfo,
+	provenance:&Provenance,
 	session: &mut S,
 ) -> tools::Result<RegisterResult> where S:Session<Any>
+{
+	let versioning = config::get().store.versioning;
+	register(obj.into(), file_info, provenance, session, versioning, false).await
+}
+
+/// Register a dicom object of an instance creating a new version if it already exists with different data.
+///
+/// Same as [register_instance] with `versioning` enabled in the config.
+/// The previous data of the instance are kept (see [version::list_versions]), differing fields in
+/// series or study are overwritten with the new values.
+pub async fn register_instance_version<S>(
+	obj:impl Into<Arc<DefaultDicomObject>>,
+	file_info:&mut FileInfo,
+	provenance:&Provenance,
+	session: &mut S,
+) -> tools::Result<RegisterResult> where S:Session<Any>
+{
+	register(obj.into(), file_info, provenance, session, true, false).await
+}
+
+/// Register a dicom object of an instance replacing the existing instance with the same UID.
+///
+/// The existing entry is deleted in the same transaction, so it stays as it is if the registration fails.
+/// Differing fields in series or study are overwritten with the new values, no version of the replaced data is kept.
+/// The file of the replaced instance is left alone.
+pub async fn register_instance_replacement<S>(
+	obj:impl Into<Arc<DefaultDicomObject>>,
+	file_info:&mut FileInfo,
+	provenance:&Provenance,
+	session: &mut S,
+) -> tools::Result<RegisterResult> where S:Session<Any>
+{
+	register(obj.into(), file_info, provenance, session, true, true).await
+}
+
+async fn register<S>(
+	obj:Arc<DefaultDicomObject>,
+	file_info:&mut FileInfo,
+	provenance:&Provenance,
+	session: &mut S,
+	versioning:bool,
+	replace:bool
+) -> tools::Result<RegisterResult> where S:Session<Any>
 {
 	let mut res = None;
 	let mut retry = 0;
 	let mut transaction = None;
-	let obj = obj.into();
 	loop {
 		// make sure we have a transaction
 		let t= match &mut transaction {
@@ -147,7 +201,7 @@ pub async fn register_instance<S>(
 		let fall_throu = match if let Some(r) = res.take() {r} // we already have a result, don't need a new one
 			else {
 				retry+=1;
-				_register_instance(obj.clone(), file_info, t).await
+				_register_instance(obj.clone(), file_info, provenance, t, versioning, replace).await
 		}{
 			Err(Error::SurrealError(e)) =>
 				if let Ok(true) = if_retry(&e,&mut retry).await{continue} else { e.into() },
@@ -161,7 +215,12 @@ pub async fn register_instance<S>(
 							transaction.take().unwrap().cancel().await,
 					};
 					match commit_or_cancel { // results of the commit/reset above
-						Ok(_) => return Ok(r), // all good, we're done
+						Ok(_) => { // all good, we're done
+							if let RegisterResult::Stored(id) = &r {
+								stored_event(&obj, id);
+							}
+							return Ok(r)
+						},
 						Err(e) =>{
 							res = Some(Err(e.into())); //darn, whatever the error is, stuff it back in, and handle it in the next loop
 							continue
@@ -178,10 +237,25 @@ pub async fn register_instance<S>(
 
 }
 
+/// emits [Event::InstanceStored] and restarts the completeness timeouts (the UIDs were already checked during registration)
+fn stored_event(obj:&DefaultDicomObject, id:&RecordId)
+{
+	let series = extract_from_dicom(obj, tags::SERIES_INSTANCE_UID);
+	let study = extract_from_dicom(obj, tags::STUDY_INSTANCE_UID);
+	if let (Ok(series), Ok(study)) = (series, study) {
+		let (series, study) = (RecordId::from_series(series.as_ref()), RecordId::from_study(study.as_ref()));
+		completeness::touch(&series, &study);
+		events::emit(Event::InstanceStored {instance:id.clone(),series,study});
+	}
+}
+
 async fn _register_instance<'a,C>(
 	obj:Arc<DefaultDicomObject>,
 	fileinfo:&mut FileInfo,
-	transaction: &Transaction<C>
+	provenance:&Provenance,
+	transaction: &Transaction<C>,
+	versioning:bool,
+	replace:bool
 ) -> tools::Result<RegisterResult> where C:Connection
 {
 	let study_uid = extract_from_dicom(&*obj, tags::STUDY_INSTANCE_UID)?;
@@ -191,7 +265,10 @@ async fn _register_instance<'a,C>(
 	let study_id = RecordId::from_study(study_uid.as_ref());
 	let series_id= RecordId::from_series(series_uid.as_ref());
 	let instance_id = RecordId::from_instance(instance_uid.as_ref());
-	let mut add_meta = vec![("series",series_id.0.to_owned().into_value())];
+	let mut add_meta = vec![
+		("series",series_id.0.to_owned().into_value()),
+		("provenance",provenance.clone().into())
+	];
 
 	match fileinfo{
 		FileInfo::Exists(file)|FileInfo::Stored(Some(file)) => {
@@ -201,28 +278,76 @@ async fn _register_instance<'a,C>(
 	};
 	debug!("registering instance {}",instance_uid);
 
-	if insert(&*obj, &instance_id, add_meta, &INSTANCE_TAGS, &transaction	).await?
-	{ // normal insert, didn't exist before. So make sure its series/study exists (this may also update non-existing entries)
-		upsert(&*obj, &series_id, vec![("study", study_id.0.clone().into_value())], &SERIES_TAGS, &transaction).await?;
-		upsert(&*obj, &study_id, vec![], &STUDY_TAGS, &transaction).await?;
-
-		// everything successfully inserted
-		// now do the file, if it's not there yet
-		if let FileInfo::Store = fileinfo{
-			let file = File::new_from_obj(obj).await?; // storing failed, abort
-			*fileinfo = FileInfo::Stored(Some(file.clone()));
-			transaction.query("UPDATE $rec SET file = $file")
-				.bind(("rec",instance_id.0.clone()))
-				.bind(("file", db_types::Value::try_from(file)?))
-				.await?;
+	if replace {
+		transaction.query("DELETE $rec").bind(("rec",instance_id.0.clone())).await?.check()?;
+	}
+
+	let existing = insert(&*obj, &instance_id, add_meta, &INSTANCE_TAGS, &transaction).await?;
+	metadata::store(&instance_id, &obj, transaction).await?;
+	let mut diff = match &existing {
+		None => vec![], // normal insert, didn't exist before.
+		Some(existing) if !versioning => {
+			return if *existing == *obj {// didn't insert as it's already there. But it's exactly the same, so all good
+				Ok(RegisterResult::AlreadyStored(instance_id))
+			} else {
+				Err(DataConflict(existing.clone()))
+			}
 		}
+		Some(existing) => column_diff(existing, &obj, &INSTANCE_TAGS),
+	};
 
-		// set up the guard with the registered instance, so we can roll back the registration if needed
-		// @todo we could return the transaction so the caller could cancel (or just drop) it, no guard needed
-		Ok(RegisterResult::Stored(instance_id))
+	// make sure its series/study exists (this may also update non-existing entries)
+	let parents = [
+		(&series_id, vec![("study", study_id.0.clone().into_value())], SERIES_TAGS.deref()),
+		(&study_id, vec![], STUDY_TAGS.deref())
+	];
+	for (id, add_meta, tags) in parents {
+		let conflicts = upsert(&*obj, id, add_meta, tags, &transaction).await?;
+		if conflicts.is_empty() {continue}
+		if versioning {
+			diff.extend(conflicts.into_iter().map(|d|Diff{path:format!("/{}{}",id.table,d.path),..d}));
+		} else {
+			return Err(FieldConflict{ fields: conflicts.into_iter().map(|d|d.path).join(":"), id: id.clone() })
+		}
+	}
 
-	} else { // didn't insert as it's already there. But it's exactly the same, so all good
-		Ok(RegisterResult::AlreadyStored(instance_id))
+	// series before study as the study may aggregate series aggregates
+	for id in [&series_id, &study_id] {
+		aggregates::update(id, transaction).await?;
+	}
+	search::update(&search::Terms::new(&obj), &series_id, &study_id, transaction).await?;
+	let stored = Event::InstanceStored {instance:instance_id.clone(), series:series_id.clone(), study:study_id.clone()};
+	routing::enqueue(&stored, &obj, transaction).await?;
+
+	// an existing instance with all data being equal (only possible if versioning)
+	let version = match existing {
+		Some(_) if diff.is_empty() => return Ok(RegisterResult::AlreadyStored(instance_id)),
+		Some(existing) => Some(version::store_version(existing, diff, provenance, transaction).await? + 1),
+		None => None
+	};
+
+	// everything successfully inserted
+	// now do the file, if it's not there yet
+	if let FileInfo::Store = fileinfo{
+		let file = match version {
+			Some(version) => File::new_versioned(obj, version).await?,
+			None => File::new_from_obj(obj).await?, // storing failed, abort
+		};
+		*fileinfo = FileInfo::Stored(Some(file.clone()));
+		transaction.query("UPDATE $rec SET file = $file")
+			.bind(("rec",instance_id.0.clone()))
+			.bind(("file", db_types::Value::try_from(file)?))
+			.await?;
+	}
+	if let Some(version) = version {
+		debug!("registering {instance_id} as version {version}");
+		transaction.query("UPDATE $rec SET version = $version")
+			.bind(("rec",instance_id.0.clone()))
+			.bind(("version", version))
+			.await?;
 	}
 
+	// set up the guard with the registered instance, so we can roll back the registration if needed
+	// @todo we could return the transaction so the caller could cancel (or just drop) it, no guard needed
+	Ok(RegisterResult::Stored(instance_id))
 }
@@ -0,0 +1,250 @@
+use crate::db::{Entry, IntoDbValue, RecordId, DB};
+use crate::dcm::{extract, AttributeSelector, SERIES_TAGS, STUDY_TAGS};
+use crate::tools::{entries_for_record, extract_from_dicom, Context, Result};
+use dicom::core::Tag;
+use dicom::dictionary_std::tags;
+use dicom::object::DefaultDicomObject;
+use std::collections::HashMap;
+use surrealdb::method::Transaction;
+use surrealdb::types as db_types;
+use surrealdb::types::ToSql;
+use surrealdb::Connection;
+use tracing::{info, warn};
+
+/// elements searchable in addition to the configured columns
+const STUDY_ELEMENTS:[Tag;3] = [tags::PATIENT_NAME, tags::PATIENT_ID, tags::STUDY_DESCRIPTION];
+const SERIES_ELEMENTS:[Tag;2] = [tags::SERIES_DESCRIPTION, tags::PROTOCOL_NAME];
+
+/// minimal Jaro-Winkler similarity of words to count as fuzzy match
+const SIMILARITY:f64 = 0.85;
+
+/// version of the search terms, stored as `meta:search` (see [backfill])
+const SEARCH_VERSION:i64 = 1;
+
+/// sets `search` of the study's entry in `study_search` ($study_search) to the terms of the study and its (remaining) series and `words` to its words
+const REFRESH:&str = "
+	IF record::exists($study) {
+		LET $text = array::join(array::distinct(array::flatten([
+			$study_search.terms ?? [],
+			(SELECT VALUE type::record('series_search', record::id(id)).terms ?? [] FROM $study.series WHERE array::len(instances) > 0)
+		])), ' ');
+		UPSERT $study_search SET study = $study, search = $text, words = search::analyze('study_words', $text) RETURN NONE;
+	};";
+
+fn series_search(series:&RecordId) -> db_types::RecordId
+{
+	db_types::RecordId::new("series_search", series.key.clone())
+}
+
+fn study_search(study:&RecordId) -> db_types::RecordId
+{
+	db_types::RecordId::new("study_search", study.key.clone())
+}
+
+/// the words in a value (components of names, numbers as text, nothing for dates and binary data)
+fn words(value:&db_types::Value, into:&mut Vec<String>)
+{
+	match value {
+		db_types::Value::String(s) => into.push(s.to_string()),
+		db_types::Value::Number(_) => into.push(value.to_sql()),
+		db_types::Value::Array(a) => a.iter().for_each(|v|words(v, into)),
+		db_types::Value::Object(o) if o.get("binary").is_none() => o.values().for_each(|v|words(v, into)),
+		_ => {}
+	}
+}
+
+fn terms(obj:&DefaultDicomObject, elements:&[Tag], columns:&HashMap<String,Vec<AttributeSelector>>) -> Vec<String>
+{
+	let mut ret = vec![];
+	for element in elements.iter().filter_map(|tag|obj.element(*tag).ok()) {
+		words(&element.clone().into_db_value(), &mut ret);
+	}
+	for (_,value) in extract(obj, columns) {
+		words(&value, &mut ret);
+	}
+	ret.retain(|w|!w.trim().is_empty());
+	ret.sort();
+	ret.dedup();
+	ret
+}
+
+/// the search terms of the series and the study of an object
+pub(crate) struct Terms{
+	series:Vec<String>,
+	study:Vec<String>,
+}
+
+impl Terms {
+	pub(crate) fn new(obj:&DefaultDicomObject) -> Self
+	{
+		Terms{series:terms(obj, &SERIES_ELEMENTS, &SERIES_TAGS), study:terms(obj, &STUDY_ELEMENTS, &STUDY_TAGS)}
+	}
+}
+
+/// Stores the search terms of a newly registered instance's series and study inside its transaction.
+///
+/// The study is only reindexed if they changed, so usually once per series.
+pub(crate) async fn update<C>(terms:&Terms, series:&RecordId, study:&RecordId, transaction:&Transaction<C>) -> Result<()> where C:Connection
+{
+	let query = format!("
+		IF $series_search.terms != $series_terms OR $study_search.terms != $study_terms {{
+			UPSERT $series_search SET series = $series, terms = $series_terms RETURN NONE;
+			UPSERT $study_search SET study = $study, terms = $study_terms RETURN NONE;{REFRESH}
+		}};"
+	);
+	transaction.query(query)
+		.bind(("series", series.0.clone()))
+		.bind(("study", study.0.clone()))
+		.bind(("series_search", series_search(series)))
+		.bind(("study_search", study_search(study)))
+		.bind(("series_terms", terms.series.clone()))
+		.bind(("study_terms", terms.study.clone()))
+		.await
+		.and_then(|r|r.check().map(|_|()))
+		.context(format!("updating search terms of {study}"))
+}
+
+/// Replaces the search terms of a series and its study inside a transaction (without reindexing the study, see [reindex]).
+pub(crate) async fn replace<C>(terms:&Terms, series:&RecordId, study:&RecordId, transaction:&Transaction<C>) -> Result<()> where C:Connection
+{
+	transaction.query("
+		UPSERT $series_search SET series = $series, terms = $series_terms RETURN NONE;
+		UPSERT $study_search SET study = $study, terms = $study_terms RETURN NONE;")
+		.bind(("series", series.0.clone()))
+		.bind(("study", study.0.clone()))
+		.bind(("series_search", series_search(series)))
+		.bind(("study_search", study_search(study)))
+		.bind(("series_terms", terms.series.clone()))
+		.bind(("study_terms", terms.study.clone()))
+		.await
+		.and_then(|r|r.check().map(|_|()))
+		.context(format!("replacing search terms of {series}"))
+}
+
+/// rebuilds the searchable text of a study inside a transaction (e.g. after series were moved into or out of it)
+pub(crate) async fn reindex<C>(study:&RecordId, transaction:&Transaction<C>) -> Result<()> where C:Connection
+{
+	transaction.query(REFRESH)
+		.bind(("study", study.0.clone()))
+		.bind(("study_search", study_search(study)))
+		.await
+		.and_then(|r|r.check().map(|_|()))
+		.context(format!("updating search terms of {study}"))
+}
+
+/// rebuilds the searchable text of a study from what is left of it (e.g. after series were removed)
+///
+/// the terms of its emptied series are dropped, so they are indexed again if instances are stored into them
+pub async fn refresh(study:&RecordId) -> Result<()>
+{
+	let query = format!("
+		FOR $empty IN (SELECT VALUE id FROM $study.series WHERE array::len(instances) = 0) {{
+			DELETE type::record('series_search', record::id($empty)) RETURN NONE;
+		}};{REFRESH}"
+	);
+	DB.query(query)
+		.bind(("study", study.0.clone()))
+		.bind(("study_search", study_search(study)))
+		.await
+		.and_then(|r|r.check().map(|_|()))
+		.context(format!("updating search terms of {study}"))
+}
+
+async fn index(series:&RecordId) -> Result<()>
+{
+	let Some(instance) = entries_for_record(series, "instances").await?.into_iter().next() else {return Ok(())};
+	let obj = instance.get_file()?.read().await?;
+	let study = RecordId::from_study(extract_from_dicom(&obj, tags::STUDY_INSTANCE_UID)?.as_ref());
+	let transaction = DB.clone().begin().await?;
+	if let Err(e) = update(&Terms::new(&obj), series, &study, &transaction).await {
+		transaction.cancel().await?;
+		return Err(e)
+	}
+	transaction.commit().await?;
+	Ok(())
+}
+
+/// Collects the search terms of series stored by older versions (from the file of their first instance).
+///
+/// Only done once, series whose files can't be read stay unsearchable until their instances are stored again.
+pub(crate) async fn backfill() -> Result<()>
+{
+	let version = DB.query("RETURN meta:search.version ?? 0").await?
+		.take::<Option<i64>>(0)
+		.context("reading the version of the search terms")?
+		.unwrap_or_default();
+	if version >= SEARCH_VERSION {return Ok(())}
+	let series = DB.query("SELECT VALUE id FROM series WHERE !record::exists(type::record('series_search', record::id(id)))").await?
+		.take::<Vec<db_types::RecordId>>(0)
+		.context("listing series without search terms")?;
+	if !series.is_empty() {
+		info!("collecting search terms of {} series", series.len());
+	}
+	for series in series.into_iter().map(RecordId) {
+		if let Err(e) = index(&series).await {
+			warn!("search terms of {series} could not be collected: {e}");
+		}
+	}
+	// search terms were stored on the series and studies before
+	DB.query("
+		REMOVE INDEX IF EXISTS study_search ON studies;
+		UPDATE series, studies UNSET search, search_words, search_terms RETURN NONE;
+		UPSERT meta:search SET version = $version RETURN NONE;")
+		.bind(("version", SEARCH_VERSION)).await?
+		.check().context("storing the version of the search terms")?;
+	Ok(())
+}
+
+/// studies whose patient name, descriptions, protocol names or configured columns contain all words of the text
+/// (or words starting with them), ignoring case and accents, best matches first
+///
+/// if nothing is found that way and `fuzzy` is set, words only similar to those of the text match as well (e.g. typos)
+pub async fn search(text:&str, fuzzy:bool, limit:u32) -> Result<Vec<Entry>>
+{
+	let found = DB.query("SELECT study.* AS study, search::score(1) AS score FROM study_search WHERE search @1@ $text ORDER BY score DESC LIMIT $limit")
+		.bind(("text", text.to_string()))
+		.bind(("limit", limit))
+		.await?
+		.take::<Vec<db_types::Value>>(0)
+		.context(format!("searching for {text}"))?;
+	if !found.is_empty() || !fuzzy {
+		return studies(found)
+	}
+
+	let words = DB.query("RETURN search::analyze('study_words', $text)")
+		.bind(("text", text.to_string()))
+		.await?
+		.take::<Vec<String>>(0)
+		.context(format!("analyzing {text}"))?;
+	if words.is_empty() {return Ok(vec![])}
+	let conditions:Vec<_> = (0..words.len())
+		.map(|n|format!("array::any(words, |$word| string::similarity::jaro_winkler($word, $words[{n}]) >= {SIMILARITY})"))
+		.collect();
+	let query = format!("SELECT study.* AS study FROM study_search WHERE words != NONE AND {} LIMIT $limit", conditions.join(" AND "));
+	let found = DB.query(query)
+		.bind(("words", words))
+		.bind(("limit", limit))
+		.await?
+		.take::<Vec<db_types::Value>>(0)
+		.context(format!("fuzzy searching for {text}"))?;
+	studies(found)
+}
+
+/// the studies of the search hits (with their score if there is one)
+fn studies(hits:Vec<db_types::Value>) -> Result<Vec<Entry>>
+{
+	hits.into_iter()
+		.filter_map(|hit|match hit {
+			db_types::Value::Object(mut hit) => match hit.remove("study") {
+				Some(db_types::Value::Object(mut study)) => {
+					if let Some(score) = hit.remove("score") {
+						study.insert("score", score);
+					}
+					Some(Entry::try_from(db_types::Value::Object(study)))
+				}
+				_ => None // the study was removed meanwhile
+			},
+			_ => None
+		})
+		.collect()
+}
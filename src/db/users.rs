@@ -0,0 +1,61 @@
+use crate::config::Role;
+use crate::db::{Pickable, RecordId, DB};
+use crate::tools::Error::{IdNotFound, UnexpectedResult};
+use crate::tools::{Context, Result};
+use std::str::FromStr;
+use surrealdb::types as db_types;
+
+pub fn user_id(name:&str) -> RecordId
+{
+	RecordId(db_types::RecordId::new("users",name))
+}
+
+fn parse_role(role:String) -> Result<Role>
+{
+	Role::from_str(role.as_str()).map_err(|e|UnexpectedResult {expected:"role".into(),found:e})
+}
+
+/// creates a user or changes its password and role (the password is stored as argon2 hash)
+pub async fn set_user(name:&str, password:&str, role:Role) -> Result<()>
+{
+	DB.query("UPSERT $rec SET password = crypto::argon2::generate($password), role = $role")
+		.bind(("rec",user_id(name).0))
+		.bind(("password",password.to_string()))
+		.bind(("role",role.to_string()))
+		.await.context(format!("setting user {name}"))?;
+	Ok(())
+}
+
+pub async fn remove_user(name:&str) -> Result<()>
+{
+	DB.delete::<Option<db_types::Value>>(user_id(name).0).await
+		.context(format!("removing user {name}"))?
+		.ok_or(IdNotFound {id:name.to_string()})?;
+	Ok(())
+}
+
+/// all users with their roles
+pub async fn list_users() -> Result<Vec<(String,Role)>>
+{
+	DB.query("SELECT id, role FROM users").await?
+		.take::<Vec<db_types::Value>>(0)?
+		.into_iter().map(|mut user|{
+			let name = RecordId(user.pick_remove("id")?.into_record()?).str_key();
+			Ok((name,parse_role(user.pick_remove("role")?.into_string()?)?))
+		})
+		.collect::<Result<Vec<_>>>()
+		.context("listing users")
+}
+
+/// returns the role of the user if it exists and the password matches
+pub async fn check_user(name:&str, password:&str) -> Result<Option<Role>>
+{
+	DB.query("SELECT VALUE role FROM $rec WHERE crypto::argon2::compare(password, $password)")
+		.bind(("rec",user_id(name).0))
+		.bind(("password",password.to_string()))
+		.await?
+		.take::<Vec<String>>(0)?
+		.into_iter().next()
+		.map(parse_role).transpose()
+		.context(format!("checking password of {name}"))
+}
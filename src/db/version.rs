@@ -0,0 +1,140 @@
+use crate::db::register::Diff;
+use crate::db::{Entry, File, Pickable, Provenance, RecordId, DB};
+use crate::tools::Error::UnexpectedResult;
+use crate::tools::{Context, Error, Result};
+use serde_json::json;
+use surrealdb::method::Transaction;
+use surrealdb::types as db_types;
+use surrealdb::types::SurrealValue;
+use surrealdb::Connection;
+
+/// A previous version of an instance.
+pub struct Version
+{
+	pub version:i64,
+	/// when it was replaced by the next version
+	pub replaced:db_types::Value,
+	/// what was changed by the next version
+	pub diff:db_types::Value,
+	/// who (and from where) sent the next version (not known for versions stored before it was recorded)
+	pub replaced_by:Option<Provenance>,
+	/// the instance as it was
+	pub entry:Entry,
+}
+
+fn version_id(instance:&RecordId, version:i64) -> RecordId
+{
+	RecordId::from(("instance_versions",[instance.0.key.clone().into_value(),version.into_value()].as_slice()))
+}
+
+/// version number of the data (entries without an explicit version are the first)
+fn version_number(version:Option<&db_types::Value>) -> i64
+{
+	match version {
+		Some(db_types::Value::Number(num)) => num.to_int().unwrap_or(1),
+		_ => 1
+	}
+}
+
+/// stores the existing data of an instance as previous version replaced by data from `provenance`, returns its version number
+pub(super) async fn store_version<C>(existing:Entry, diff:Vec<Diff>, provenance:&Provenance, transaction: &Transaction<C>) -> Result<i64> where C:Connection
+{
+	let version = version_number(existing.get("version"));
+	let id = existing.id().clone();
+	let data:&db_types::Object = existing.as_ref();
+	let mut content = db_types::Object::default();
+	content.insert("instance", id.0.clone().into_value());
+	content.insert("version", version);
+	content.insert("diff", diff.into_value());
+	content.insert("replaced_by", db_types::Value::from(provenance.clone()));
+	content.insert("data", data.clone());
+	transaction.query("CREATE $rec CONTENT $content")
+		.bind(("rec",version_id(&id, version).0))
+		.bind(("content",content))
+		.await
+		.context(format!("storing version {version} of {id}"))?;
+	Ok(version)
+}
+
+/// list all previous versions of an instance (oldest first)
+pub async fn list_versions(id:&RecordId) -> Result<Vec<Version>>
+{
+	DB.query("SELECT * FROM instance_versions WHERE instance = $rec ORDER BY version")
+		.bind(("rec",id.0.clone())).await?
+		.take::<Vec<db_types::Value>>(0)?
+		.into_iter().map(Version::try_from)
+		.collect::<Result<Vec<_>>>()
+		.context(format!("listing versions of {id}"))
+}
+
+pub async fn lookup_version(id:&RecordId, version:i64) -> Result<Option<Version>>
+{
+	let ctx = format!("looking up version {version} of {id}");
+	DB.select::<Option<db_types::Value>>(version_id(id, version).0).await.context(ctx.clone())?
+		.map(Version::try_from).transpose().context(ctx)
+}
+
+/// removes all previous versions of an instance including their files
+pub(crate) async fn remove_versions(id:&RecordId) -> Result<()>
+{
+	let removed = DB.query("DELETE instance_versions WHERE instance = $rec RETURN BEFORE")
+		.bind(("rec",id.0.clone())).await?
+		.take::<Vec<db_types::Value>>(0)?;
+	for version in removed {
+		Version::try_from(version)?.entry.get_file()?.remove().await?;
+	}
+	Ok(())
+}
+
+impl Version
+{
+	pub fn get_file(&self) -> Result<File> {self.entry.get_file()}
+}
+
+impl TryFrom<db_types::Value> for Version
+{
+	type Error = Error;
+
+	fn try_from(value: db_types::Value) -> std::result::Result<Self, Self::Error> {
+		let kind = value.kind().to_string();
+		match value {
+			db_types::Value::Object(obj) => obj.try_into(),
+			_ => Err(UnexpectedResult {expected:"object".into(),found:kind})
+		}.context("parsing database object as instance version")
+	}
+}
+
+impl TryFrom<db_types::Object> for Version
+{
+	type Error = Error;
+
+	fn try_from(mut obj: db_types::Object) -> std::result::Result<Self, Self::Error> {
+		let instance = RecordId(obj.pick_remove("instance")?.into_record()?);
+		let data = match obj.pick_remove("data")? {
+			db_types::Value::Object(data) => data,
+			v => return Err(UnexpectedResult {expected:"object".into(),found:v.kind().to_string()})
+		};
+		Ok(Version{
+			version: version_number(obj.get("version")),
+			replaced: obj.remove("replaced").unwrap_or_default(),
+			diff: obj.remove("diff").unwrap_or_default(),
+			replaced_by: obj.remove("replaced_by")
+				.filter(|v|!v.is_nullish())
+				.map(Provenance::try_from).transpose()?,
+			entry: Entry::Instance((instance,data)),
+		})
+	}
+}
+
+impl From<Version> for serde_json::Value
+{
+	fn from(version: Version) -> Self {
+		json!({
+			"version":version.version,
+			"replaced":crate::tools::conv::value_to_json(version.replaced),
+			"diff":crate::tools::conv::value_to_json(version.diff),
+			"replaced_by":version.replaced_by.map(|p|crate::tools::conv::value_to_json(p.into())),
+			"data":serde_json::Value::from(version.entry),
+		})
+	}
+}
@@ -1,24 +1,255 @@
-use crate::{config, db, tools};
+use crate::{config, db};
+use crate::tools::csa;
+use crate::tools::computed::Expression;
+use crate::tools::decoders::{self, DecodedHeader};
+use crate::tools::filename::{Pattern, Source};
 use crate::db::IntoDbValue;
-use crate::tools::Context;
 use dicom::core::header::HasLength;
-use dicom::core::{DataDictionary, Tag};
-use dicom::object::{DefaultDicomObject, StandardDataDictionary};
+use dicom::core::{DataDictionary, PrimitiveValue, Tag};
+use dicom::dictionary_std::tags;
+use dicom::object::mem::InMemElement;
+use dicom::object::{DefaultDicomObject, InMemDicomObject, StandardDataDictionary};
 use itertools::Itertools;
 use std::collections::HashMap;
-use std::fmt::Write;
 use std::ops::Deref;
 use std::str::FromStr;
 use std::sync::LazyLock;
-use strfmt::{strfmt_map, FmtError};
 use surrealdb::types as db_types;
+use tracing::warn;
 
 #[derive(Debug,Clone,Hash,PartialEq,Eq)]
 pub enum AttributeSelector{
 	Core(dicom::core::ops::AttributeSelector),
-	CSA {
-		base: dicom::core::ops::AttributeSelector,
+	/// path containing private tags identified by their creator e.g. `(0019,"GEMS_ACQU_01",0C)`
+	Private(Vec<PathStep>),
+	/// element of a vendor header stored in `base` (see [crate::tools::decoders]) e.g. `(0029,1010)#CSA.NumberOfImagesInMosaic`
+	Header {
+		base: Box<AttributeSelector>,
+		decoder: String,
 		element: String,
+	},
+	Derived(Derived),
+	/// value computed from other elements (see [crate::tools::computed]) e.g. `=age(PatientBirthDate, StudyDate)`
+	Computed(Box<Expression>),
+}
+
+#[derive(Debug,Clone,Hash,PartialEq,Eq)]
+pub enum PathTag{
+	Standard(Tag),
+	/// the element `offset` in the block reserved by `creator` in `group`
+	Private{group:u16, creator:String, offset:u8},
+}
+
+/// step of a [AttributeSelector::Private] path
+#[derive(Debug,Clone,Hash,PartialEq,Eq)]
+pub struct PathStep{
+	pub tag:PathTag,
+	/// the sequence item to go into if this isn't the last step (defaults to the first)
+	pub item:Option<u32>,
+}
+
+impl PathTag {
+	/// the actual tag in `obj` (None if the private creator isn't there)
+	pub fn resolve(&self, obj:&InMemDicomObject) -> Option<Tag>
+	{
+		match self {
+			PathTag::Standard(tag) => Some(*tag),
+			PathTag::Private {group,creator,offset} => (0x10..=0xFF_u16)
+				.find(|block|obj.element(Tag(*group,*block)).ok()
+					.and_then(|e|e.to_str().ok())
+					.is_some_and(|c|c.trim_end_matches(['\0',' ']).eq_ignore_ascii_case(creator))
+				)
+				.map(|block|Tag(*group, block << 8 | *offset as u16))
+		}
+	}
+}
+
+impl std::fmt::Display for PathStep {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match &self.tag {
+			PathTag::Standard(tag) => write!(f, "({:04X},{:04X})", tag.0, tag.1)?,
+			PathTag::Private {group,creator,offset} => write!(f, r#"({group:04X},"{creator}",{offset:02X})"#)?,
+		}
+		match self.item {
+			Some(item) => write!(f, "[{item}]"),
+			None => Ok(())
+		}
+	}
+}
+
+fn parse_hex(s:&str) -> Result<u16,String>
+{
+	u16::from_str_radix(s.trim(), 16).map_err(|_|format!("{s} is not a hexadecimal number"))
+}
+
+/// parses the first step of a private path and returns the rest
+fn parse_step(s:&str) -> Result<(PathStep,&str),String>
+{
+	let (tag, rest) = if let Some(inner) = s.strip_prefix('(') {
+		let (group, rest) = inner.split_once(',').ok_or(format!("{s} lacks the element"))?;
+		let group = parse_hex(group)?;
+		let rest = rest.trim_start();
+		if let Some(quoted) = rest.strip_prefix('"') {
+			let (creator, rest) = quoted.split_once('"').ok_or(format!("{s} has an unterminated private creator"))?;
+			let rest = rest.trim_start().strip_prefix(',').ok_or(format!("{s} lacks the element offset"))?;
+			let (offset, rest) = rest.split_once(')').ok_or(format!("{s} lacks the closing bracket"))?;
+			(PathTag::Private {group, creator:creator.to_string(), offset:u8::try_from(parse_hex(offset)?).map_err(|_|format!("{offset} is not a valid element offset"))?}, rest)
+		} else {
+			let (element, rest) = rest.split_once(')').ok_or(format!("{s} lacks the closing bracket"))?;
+			(PathTag::Standard(Tag(group, parse_hex(element)?)), rest)
+		}
+	} else {
+		let end = s.find(['.','[']).unwrap_or(s.len());
+		let tag = find_tag(&s[..end]).ok_or(format!("{} is not a known tag", &s[..end]))?;
+		(PathTag::Standard(tag), &s[end..])
+	};
+	let (item, rest) = match rest.strip_prefix('[') {
+		Some(rest) => {
+			let (item, rest) = rest.split_once(']').ok_or(format!("{s} lacks the closing bracket of the item"))?;
+			(Some(item.trim().parse().map_err(|_|format!("{item} is not a valid item number"))?), rest)
+		}
+		None => (None, rest)
+	};
+	Ok((PathStep{tag,item}, rest))
+}
+
+impl AttributeSelector {
+	/// the selected element (None for derived, computed values and header elements)
+	pub fn element<'a>(&self, obj:&'a InMemDicomObject) -> Option<&'a InMemElement>
+	{
+		match self {
+			AttributeSelector::Core(selector) => obj.entry_at(selector.clone()).ok(),
+			AttributeSelector::Private(path) => {
+				let (last, steps) = path.split_last()?;
+				let mut obj = obj;
+				for step in steps {
+					let seq = obj.element(step.tag.resolve(obj)?).ok()?;
+					obj = seq.items()?.get(step.item.unwrap_or(0) as usize)?;
+				}
+				obj.element(last.tag.resolve(obj)?).ok()
+			}
+			AttributeSelector::Header {..} | AttributeSelector::Derived(_) | AttributeSelector::Computed(_) => None
+		}
+	}
+}
+
+impl std::fmt::Display for AttributeSelector {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			AttributeSelector::Core(c) => write!(f, "{c}"),
+			AttributeSelector::Private(path) => write!(f, "{}", path.iter().join(".")),
+			AttributeSelector::Header {base, decoder, element} => write!(f, "{base}#{decoder}.{element}"),
+			AttributeSelector::Derived(derived) => write!(f, "#{derived}"),
+			AttributeSelector::Computed(expression) => write!(f, "={expression}"),
+		}
+	}
+}
+
+impl FromStr for AttributeSelector {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		if let Some(expression) = s.strip_prefix('=') {
+			return Expression::from_str(expression).map(|e|AttributeSelector::Computed(Box::new(e)))
+		}
+		if let Some(name) = s.strip_prefix('#') {
+			return Derived::from_str(name).map(AttributeSelector::Derived)
+		}
+		// a '#' inside a private creator doesn't start a header element
+		if let Some((base, header)) = s.rsplit_once('#').filter(|(_,h)|!h.contains(['"',')'])) {
+			let (decoder, element) = header.split_once('.').ok_or(format!("{s} should be <base>#<decoder>.<element>"))?;
+			if !decoders::is_known(decoder) {
+				return Err(format!("{decoder} is not a known header decoder"))
+			}
+			let base = AttributeSelector::from_str(base)?;
+			if !matches!(base, AttributeSelector::Core(_) | AttributeSelector::Private(_)) {
+				return Err(format!("{base} can't contain a header"))
+			}
+			return Ok(AttributeSelector::Header {base:Box::new(base), decoder:decoder.to_string(), element:element.to_string()})
+		}
+		if s.contains('"') {
+			let mut path = vec![];
+			let mut rest = s;
+			loop {
+				let (step, remaining) = parse_step(rest)?;
+				path.push(step);
+				if remaining.is_empty() {break}
+				rest = remaining.strip_prefix('.').ok_or(format!("unexpected {remaining} in {s}"))?;
+			}
+			return Ok(AttributeSelector::Private(path))
+		}
+		StandardDataDictionary::default().parse_selector(s)
+			.map(AttributeSelector::Core)
+			.map_err(|e|e.to_string())
+	}
+}
+
+/// values derived from (vendor specific) tags, selected as `#<name>` in the config
+#[derive(Debug,Clone,Copy,Hash,PartialEq,Eq)]
+pub enum Derived{
+	/// acquisition time of each slice of a mosaic in ms (CSA MosaicRefAcqTimes)
+	SliceTiming,
+	/// CSA B_value or DiffusionBValue
+	BValue,
+	/// CSA DiffusionGradientDirection or DiffusionGradientOrientation
+	DiffusionGradientDirection,
+	/// "i" or "j" for InPlanePhaseEncodingDirection ROW or COL, followed by "-" if CSA PhaseEncodingDirectionPositive is 0
+	PhaseEncodingDirection,
+}
+
+impl Derived {
+	const ALL:[Derived;4] = [Derived::SliceTiming, Derived::BValue, Derived::DiffusionGradientDirection, Derived::PhaseEncodingDirection];
+
+	pub fn name(&self) -> &'static str
+	{
+		match self {
+			Derived::SliceTiming => "SliceTiming",
+			Derived::BValue => "BValue",
+			Derived::DiffusionGradientDirection => "DiffusionGradientDirection",
+			Derived::PhaseEncodingDirection => "PhaseEncodingDirection",
+		}
+	}
+
+	/// `header` gets elements of vendor headers
+	fn extract<F>(&self, obj:&DefaultDicomObject, mut header:F) -> Option<db_types::Value>
+		where F:FnMut(&AttributeSelector, &str, &str) -> Option<PrimitiveValue>
+	{
+		let image = &*csa::IMAGE_SELECTOR;
+		let mut csa = |element:&str| header(image, "CSA", element);
+		let standard = |tag| obj.element(tag).ok().map(|e|e.clone().into_db_value());
+		match self {
+			Derived::SliceTiming => csa("MosaicRefAcqTimes").map(IntoDbValue::into_db_value),
+			Derived::BValue => csa("B_value").map(IntoDbValue::into_db_value)
+				.or_else(||standard(tags::DIFFUSION_B_VALUE)),
+			Derived::DiffusionGradientDirection => csa("DiffusionGradientDirection").map(IntoDbValue::into_db_value)
+				.or_else(||standard(tags::DIFFUSION_GRADIENT_ORIENTATION)),
+			Derived::PhaseEncodingDirection => {
+				let axis = match obj.element(tags::IN_PLANE_PHASE_ENCODING_DIRECTION).ok()?.to_str().ok()?.trim() {
+					"ROW" => "i",
+					"COL" => "j",
+					_ => return None
+				};
+				let positive = csa("PhaseEncodingDirectionPositive").and_then(|v|v.to_int::<i32>().ok());
+				Some(match positive {
+					Some(0) => format!("{axis}-"),
+					_ => axis.to_string()
+				}.into())
+			}
+		}
+	}
+}
+
+impl std::fmt::Display for Derived {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for Derived {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Derived::ALL.into_iter().find(|d|d.name() == s)
+			.ok_or_else(||format!("{s} is not a derived value (known are {})",Derived::ALL.iter().join(", ")))
 	}
 }
 
@@ -69,21 +300,60 @@ pub fn get_attr_list(table:db::Table, must_have:Vec<(&str,Vec<Tag>)>) -> HashMap
 	attrs
 }
 
+/// gets selected values from one object, decoding every vendor header only once
+struct Extractor<'o>{
+	obj:&'o DefaultDicomObject,
+	headers:HashMap<(AttributeSelector,String),Option<Box<dyn DecodedHeader>>>,
+}
+
+impl<'o> Extractor<'o> {
+	fn new(obj:&'o DefaultDicomObject) -> Self {Extractor{obj, headers:HashMap::new()}}
+
+	fn header(&mut self, base:&AttributeSelector, decoder:&str, element:&str) -> Option<PrimitiveValue>
+	{
+		let obj = self.obj;
+		self.headers.entry((base.clone(),decoder.to_string()))
+			.or_insert_with(||
+				decoders::get(decoder)?.decode(base.element(obj)?)
+					.inspect_err(|e|warn!("Ignoring {decoder} header {base}: {e}")).ok()
+			).as_ref()?
+			.value(element)
+			.inspect_err(|e|warn!("Ignoring {decoder} element {element}: {e}")).ok().flatten()
+	}
+
+	/// the selected primitive value (None for derived values and sequences)
+	fn primitive(&mut self, s:&AttributeSelector) -> Option<PrimitiveValue>
+	{
+		match s {
+			AttributeSelector::Core(_) | AttributeSelector::Private(_) =>
+				s.element(self.obj)?.value().primitive().cloned(),
+			AttributeSelector::Header{ base, decoder, element } => self.header(base, decoder, element),
+			AttributeSelector::Computed(expression) => expression.evaluate(&mut |s:&AttributeSelector|self.primitive(s)),
+			AttributeSelector::Derived(_) => None,
+		}
+	}
+
+	fn value(&mut self, s:&AttributeSelector) -> Option<db_types::Value>
+	{
+		match s {
+			AttributeSelector::Core(_) | AttributeSelector::Private(_) =>
+				s.element(self.obj).map(|e|e.clone().into_db_value()),
+			AttributeSelector::Derived(derived) => {
+				let obj = self.obj;
+				derived.extract(obj, |base:&AttributeSelector, decoder:&str, element:&str| self.header(base, decoder, element))
+			}
+			_ => self.primitive(s).map(IntoDbValue::into_db_value)
+		}
+	}
+}
+
 pub fn extract<'a>(obj: &DefaultDicomObject, requested:&'a HashMap<String,Vec<AttributeSelector>>) -> Vec<(&'a str, db_types::Value)>
 {
+	let mut extractor = Extractor::new(obj);
 	requested.iter().map(|(k,selectors)|(
 		k.deref(),
 		selectors.iter()
-			.find_map(|s| {
-				match s {
-					AttributeSelector::Core(selector) => 
-						obj.entry_at(selector.clone()).map(|e|e.clone().into_db_value()).ok(),
-					AttributeSelector::CSA{ base, element } => 
-						obj.entry_at(base.clone()).ok()
-							.and_then(|b|tools::csa::extract_csa(b, element).unwrap())
-							.map(|e|e.into_db_value()),					
-				}
-			})
+			.find_map(|s| extractor.value(s))
 			.unwrap_or_default()
 		)
 	)
@@ -91,43 +361,23 @@ pub fn extract<'a>(obj: &DefaultDicomObject, requested:&'a HashMap<String,Vec<At
 	.collect()
 }
 
-pub fn gen_filepath(obj:&DefaultDicomObject) -> crate::tools::Result<String>
+/// the configured column `name` of any table
+fn column(name:&str) -> Option<&'static Vec<AttributeSelector>>
 {
-	let pattern = config::get().paths.filename_pattern.as_str();
-	strfmt_map(pattern,|f| format_filepath(f, &obj))
-		.context(format!("generating filename using pattern '{pattern}'"))
+	[&*INSTANCE_TAGS, &*SERIES_TAGS, &*STUDY_TAGS].into_iter().find_map(|t|t.get(name))
 }
 
-fn format_filepath(mut f:strfmt::Formatter, obj:&DefaultDicomObject) -> strfmt::Result<()>
+pub static FILENAME_PATTERN: LazyLock<Pattern> = LazyLock::new(||
+	Pattern::parse(&config::get().paths.filename_pattern, &|name|column(name).is_some())
+		.expect("filename_pattern is checked when loading the config")
+);
+
+pub fn gen_filepath(obj:&DefaultDicomObject) -> crate::tools::Result<String>
 {
-	let key = find_tag(f.key).ok_or(FmtError::KeyError(format!(r#"Tag "{}" is not known"#,f.key)))?;
-	let val = obj.element_opt(key).unwrap();
-	if val.is_none(){
-		return f.write_str("__none__").map_err(|e|FmtError::Invalid(e.to_string()))
-	}
-	let val = val.unwrap();
-	if val.is_empty(){
-		return f.write_str("__empty__").map_err(|e|FmtError::Invalid(e.to_string()))
-	}
-	let val=val.to_str().map_err(|e|FmtError::Invalid(e.to_string()))?
-		.trim_matches(' ').replace(['/',' '],"_");
-	let mut val=val.deref();
-	if let Some(width)=f.width()
-	{
-		match f.align()
-		{
-			strfmt::Alignment::Left => {//"<" -> shrink from the right side
-				if val.len()>width{
-					val= &val[..width];
-				}
-			}
-			strfmt::Alignment::Right => {//">" -> shrink from the left side
-				if val.len()>width{
-					val= &val[val.len() - width..];
-				}
-			}
-			_ => {}
-		}
-	}
-	f.write_str(val).map_err(|e|FmtError::Invalid(e.to_string()))
+	let mut extractor = Extractor::new(obj);
+	Ok(FILENAME_PATTERN.render(|source|match source {
+		Source::Selector(s) => extractor.primitive(s),
+		Source::Column(name) => column(name)?.iter().find_map(|s|extractor.primitive(s)),
+		Source::Literal(text) => Some(PrimitiveValue::from(text.as_str())),
+	}))
 }
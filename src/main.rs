@@ -1,13 +1,12 @@
 mod cli;
 
-use dicom::ul::FullAeAddr;
 use futures::StreamExt;
 use rudicom::tools::import::import_glob_as_text;
 use tokio::net::TcpListener;
-use tokio::sync::watch;
 use tracing::info;
 use crate::cli::Commands;
 use rudicom::db::DB;
+use rudicom::db::audit::Actor;
 use rudicom::db;
 use rudicom::tools::import::ImportConfig;
 use rudicom::config;
@@ -58,6 +57,12 @@ async fn main() -> Result<(),String>
 		Commands::Server{address} => {
 			DB.query(include_str!("db/init.surql")).await
 				.map_err(|e|format!("database initialisation failed: {e}"))?;
+			db::migrate::migrate().await
+				.map_err(|e|format!("converting stored data failed: {e}"))?;
+
+			rudicom::tools::completeness::resume().await
+				.map_err(|e|format!("resuming completeness detection failed: {e}"))?;
+			rudicom::tools::routing::resume();
 
 			let inf= server::server_info().await;
 			info!("database version is {}",inf.db_version);
@@ -66,24 +71,14 @@ async fn main() -> Result<(),String>
 			let mut set= tokio::task::JoinSet::new();
 			if let Some(dimse) = &config::get().dimse{
 				// DICOM DIMSE
-				let scp_bind = FullAeAddr::new(
-					&dimse.aet,
-					TcpListener::bind(&dimse.address).await
-						.map_err(|e|format!("Binding to {} failed: {e}",dimse.address))?
-				);
-				let scp_peers = dimse.peers.iter()
-					.map(|(k,v)|FullAeAddr::new(k.clone(),v.clone()));
-
-				let (signal_tx, signal_rx) = watch::channel(());
-				tokio::spawn(async move {
-					rudicom::tools::shutdown_signal().await;
-					tracing::debug!("Telling SCP to shutdown");
-					drop(signal_rx);
-				});
+				let listener = TcpListener::bind(&dimse.address).await
+					.map_err(|e|format!("Binding to {} failed: {e}",dimse.address))?;
+				if dimse.tls.is_some() {
+					info!("DICOM TLS enabled on {}",dimse.address);
+				}
+				let title = format!("SCP server {}@{}",dimse.aet,dimse.address);
 				set.spawn(async {
-					let title = format!("SCP server {}@{}",scp_bind.ae_title(),scp_bind.socket_addr().local_addr().unwrap().to_string());
-					dimse::serve(scp_bind,scp_peers,signal_tx,db::dimse_access::Accessor{}).await
-						.map(|_|title).map_err(|d|d.into())
+					rudicom::tools::gateway::serve(listener).await.map(|_|title)
 				});
 			}
 
@@ -104,8 +99,10 @@ async fn main() -> Result<(),String>
 			let config = ImportConfig{ echo: echo_imported, echo_existing };
 			DB.query(include_str!("db/init.surql")).await
 				.map_err(|e|format!("database initialisation failed: {e}"))?;
+			db::migrate::migrate().await
+				.map_err(|e|format!("converting stored data failed: {e}"))?;
 			for glob in pattern {
-				let stream = import_glob_as_text(&glob, config.clone(), mode.clone())
+				let stream = import_glob_as_text(&glob, config.clone(), mode.clone(), Actor::local(), None)
 					.map_err(|e|format!("Importing {glob} failed:{e}"))?;
 				//filter doesn't do unpin, so we have to nail it down here
 				let mut stream = Box::pin(stream);
@@ -123,6 +120,21 @@ async fn main() -> Result<(),String>
 			DB.import(&file).await
 				.map_err(|e|format!("Importing {} failed {e}",file.display()))?
 		}
+		Commands::SetUser { name, role } => {
+			DB.query(include_str!("db/init.surql")).await
+				.map_err(|e|format!("database initialisation failed: {e}"))?;
+			let mut password = String::new();
+			std::io::stdin().read_line(&mut password)
+				.map_err(|e|format!("Reading password failed: {e}"))?;
+			let password = password.trim_end_matches(['\r','\n']);
+			if password.is_empty() {
+				Err("The password must not be empty")?
+			}
+			db::users::set_user(&name, password, role).await
+				.map_err(|e|format!("Setting user {name} failed: {e}"))?;
+			db::audit::log(&Actor::local(), "set_user", &db::users::user_id(&name), serde_json::json!({"role":role})).await;
+			info!("User {name} set as {role}");
+		}
 		Commands::WriteConfig { .. } => {unreachable!()}
 	}
 	Ok(())
@@ -0,0 +1,192 @@
+use crate::config::{JwtCfg, Role};
+use crate::db::audit::{self, Actor};
+use crate::db::users;
+use crate::server::http_error::{HttpError, IntoHttpError};
+use crate::tools::Error::{Forbidden, Unauthorized};
+use crate::tools::Result;
+use crate::config;
+use axum::extract::{Path, Request};
+use axum::http::{header, HeaderMap, HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::Json;
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// the authenticated user of a request
+#[derive(Clone,Debug)]
+pub struct Identity
+{
+	/// None for anonymous requests
+	pub user:Option<String>,
+	pub role:Role,
+}
+
+pub(super) fn router() -> axum::Router
+{
+	axum::Router::new()
+		.route("/users",get(list_users).post(set_user))
+		.route("/users/{name}",delete(remove_user))
+}
+
+/// the role needed for a request
+fn required_role(method:&Method, path:&str) -> Role
+{
+	if path == "/tools/backup" || path.ends_with("/audit") || path == "/api/users" || path.starts_with("/api/users/") {
+		Role::Admin
+	} else if method == Method::GET || method == Method::HEAD {
+		Role::ReadOnly
+	} else if method == Method::POST && ["/api/instances","/tools/import","/tools/store"].contains(&path) {
+		Role::Uploader
+	} else {
+		Role::Admin
+	}
+}
+
+/// middleware authenticating every request and rejecting those without the role needed for the route
+pub(super) async fn authorize(mut req:Request, next:Next) -> Response
+{
+	let headers = req.headers().clone();
+	let identity = match identify(&headers).await {
+		Ok(Some(identity)) => identity,
+		Ok(None) => match config::get().auth.anonymous {
+			Some(role) => Identity{user:None,role},
+			None => return unauthorized(Unauthorized {reason:"no credentials given".into()}, &headers)
+		},
+		Err(e) => return unauthorized(e, &headers)
+	};
+	let required = required_role(req.method(), req.uri().path());
+	if identity.role < required {
+		return HttpError::new(Forbidden {required:required.to_string()}, &headers).into_response();
+	}
+	req.extensions_mut().insert(identity);
+	next.run(req).await
+}
+
+fn unauthorized(error:crate::tools::Error, headers:&HeaderMap) -> Response
+{
+	let mut response = HttpError::new(error, headers).into_response();
+	response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(r#"Basic realm="rudicom""#));
+	response
+}
+
+async fn identify(headers:&HeaderMap) -> Result<Option<Identity>>
+{
+	let Some(authorization) = headers.get(header::AUTHORIZATION) else {return Ok(None)};
+	let authorization = authorization.to_str()
+		.map_err(|_|Unauthorized {reason:"invalid authorization header".into()})?;
+	if let Some(token) = authorization.strip_prefix("Bearer ") {
+		bearer(token.trim()).map(Some)
+	} else if let Some(credentials) = authorization.strip_prefix("Basic ") {
+		basic(credentials.trim()).await.map(Some)
+	} else {
+		Err(Unauthorized {reason:"unsupported authorization scheme".into()})
+	}
+}
+
+/// static API tokens from the config, or JWTs if configured
+fn bearer(token:&str) -> Result<Identity>
+{
+	let auth = &config::get().auth;
+	if let Some(known) = auth.tokens.get(token) {
+		return Ok(Identity{user:Some(known.user.clone()),role:known.role});
+	}
+	match &auth.jwt {
+		Some(jwt) => validate_jwt(jwt, token),
+		None => Err(Unauthorized {reason:"unknown token".into()})
+	}
+}
+
+/// HTTP basic authentication against the users table
+async fn basic(credentials:&str) -> Result<Identity>
+{
+	let invalid = || Unauthorized {reason:"invalid basic credentials".into()};
+	let decoded = base64::engine::general_purpose::STANDARD.decode(credentials).map_err(|_|invalid())?;
+	let decoded = String::from_utf8(decoded).map_err(|_|invalid())?;
+	let (user, password) = decoded.split_once(':').ok_or_else(invalid)?;
+	match users::check_user(user, password).await? {
+		Some(role) => Ok(Identity{user:Some(user.to_string()),role}),
+		None => Err(Unauthorized {reason:format!("wrong user name or password for {user}")})
+	}
+}
+
+/// key and validation of JWTs, read only once (changes of `[auth.jwt]` or the key file need a restart)
+static JWT:OnceLock<std::result::Result<(DecodingKey,Validation),String>> = OnceLock::new();
+
+fn load_jwt(cfg:&JwtCfg) -> std::result::Result<(DecodingKey,Validation),String>
+{
+	let algorithm = Algorithm::from_str(&cfg.algorithm)
+		.map_err(|e|format!("invalid JWT algorithm {}: {e}",cfg.algorithm))?;
+	let key = std::fs::read(&cfg.key)
+		.map_err(|e|format!("failed reading JWT key {}: {e}",cfg.key.display()))?;
+	let key = match algorithm {
+		Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(DecodingKey::from_secret(&key)),
+		Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&key),
+		Algorithm::EdDSA => DecodingKey::from_ed_pem(&key),
+		_ => DecodingKey::from_rsa_pem(&key),
+	}.map_err(|e|format!("invalid JWT key {}: {e}",cfg.key.display()))?;
+	let mut validation = Validation::new(algorithm);
+	if let Some(issuer) = &cfg.issuer {
+		validation.set_issuer(&[issuer]);
+	}
+	match &cfg.audience {
+		Some(audience) => validation.set_audience(&[audience]),
+		None => validation.validate_aud = false,
+	}
+	Ok((key,validation))
+}
+
+fn validate_jwt(cfg:&JwtCfg, token:&str) -> Result<Identity>
+{
+	let (key, validation) = JWT.get_or_init(||load_jwt(cfg)).as_ref()
+		.map_err(|e|Unauthorized {reason:e.clone()})?;
+	let claims = jsonwebtoken::decode::<serde_json::Map<String,serde_json::Value>>(token, key, validation)
+		.map_err(|e|Unauthorized {reason:format!("invalid JWT: {e}")})?
+		.claims;
+	let role_claim = cfg.role_claim.as_deref().unwrap_or("role");
+	let role = match claims.get(role_claim) {
+		Some(serde_json::Value::String(role)) => Role::from_str(role).ok(),
+		Some(serde_json::Value::Array(roles)) => roles.iter()
+			.filter_map(|r|r.as_str()).filter_map(|r|Role::from_str(r).ok())
+			.max(),
+		_ => None
+	}.ok_or(Unauthorized {reason:format!(r#"JWT has no valid role in "{role_claim}""#)})?;
+	let user = ["preferred_username","sub"].iter()
+		.find_map(|c|claims.get(*c).and_then(|u|u.as_str()))
+		.map(str::to_string);
+	Ok(Identity{user,role})
+}
+
+#[derive(Deserialize)]
+pub struct NewUser {
+	name: String,
+	password: String,
+	role: Role,
+}
+
+async fn list_users(headers: HeaderMap) -> std::result::Result<Response, HttpError>
+{
+	let list:Vec<_> = users::list_users().await.into_http_error(&headers)?
+		.into_iter().map(|(name,role)|json!({"name":name,"role":role}))
+		.collect();
+	Ok(Json(list).into_response())
+}
+
+async fn set_user(headers: HeaderMap,actor:Actor,Json(user):Json<NewUser>) -> std::result::Result<(), HttpError>
+{
+	users::set_user(&user.name, &user.password, user.role).await.into_http_error(&headers)?;
+	audit::log(&actor, "set_user", &users::user_id(&user.name), json!({"role":user.role})).await;
+	Ok(())
+}
+
+async fn remove_user(headers: HeaderMap,actor:Actor,Path(name):Path<String>) -> std::result::Result<(), HttpError>
+{
+	users::remove_user(&name).await.into_http_error(&headers)?;
+	audit::log(&actor, "remove_user", &users::user_id(&name), json!({})).await;
+	Ok(())
+}
@@ -0,0 +1,46 @@
+use crate::tools::events::{self, Event};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::routing::get;
+use axum_extra::extract::OptionalQuery;
+use futures::Stream;
+use serde::Deserialize;
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+pub(super) fn router() -> axum::Router
+{
+	axum::Router::new().route("/events",get(stream_events))
+}
+
+#[derive(Deserialize)]
+struct EventFilter
+{
+	/// comma separated list of event names
+	events:String,
+}
+
+/// server-sent events stream of all (or the requested) events emitted from now on
+async fn stream_events(OptionalQuery(filter):OptionalQuery<EventFilter>) -> Sse<impl Stream<Item=Result<sse::Event,Infallible>>>
+{
+	let wanted:Option<Vec<String>> = filter.map(|f|f.events.split(',').map(|e|e.trim().to_string()).collect());
+	let stream = futures::stream::unfold((events::subscribe(),wanted), |(mut receiver,wanted)| async move {
+		loop {
+			let event:Event = match receiver.recv().await {
+				Ok(event) => event,
+				Err(RecvError::Lagged(missed)) => {
+					warn!("event stream lagging, {missed} events were skipped");
+					continue
+				}
+				Err(RecvError::Closed) => return None
+			};
+			if wanted.as_ref().is_none_or(|w|w.iter().any(|w|w == event.name())) {
+				let sse = sse::Event::default()
+					.event(event.name())
+					.data(serde_json::Value::from(&event).to_string());
+				return Some((Ok(sse),(receiver,wanted)))
+			}
+		}
+	});
+	Sse::new(stream).keep_alive(KeepAlive::default())
+}
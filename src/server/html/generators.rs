@@ -1,6 +1,8 @@
 use byte_unit::Byte;
 use byte_unit::UnitType::Binary;
+use dicom::core::VR;
 use html::content::Navigation;
+use html::forms::Form;
 use html::inline_text::Anchor;
 use html::root::{Body, Html};
 use html::tables::builders::TableCellBuilder;
@@ -9,7 +11,9 @@ use std::collections::BTreeMap;
 use surrealdb::types as db_types;
 use surrealdb::types::{SurrealValue, ToSql};
 use crate::db;
-use crate::db::{find_down_tree, Entry};
+use crate::db::{find_down_tree, Entry, RecordId};
+use crate::tools::csa::{self, CsaHeader};
+use crate::tools::quarantine::Quarantined;
 use crate::tools::{entries_for_record, Context, Result};
 
 impl Entry {
@@ -64,6 +68,23 @@ fn table_from_map(map:BTreeMap<String, db_types::Value>) -> Table{
 	table_builder.build()
 }
 
+fn table_from_csa(header:&CsaHeader) -> Table
+{
+	let mut table_builder = Table::builder();
+	table_builder.table_row(|r|["Name","VR","VM","Values"].into_iter()
+		.fold(r,|r,h|r.table_header(|c|c.text(h)))
+	);
+	for e in header.elements.iter().filter(|e|!e.values.is_empty()) {
+		table_builder.table_row(|r|r
+			.table_cell(|c|c.text(e.name.clone()))
+			.table_cell(|c|c.text(e.vr.clone()))
+			.table_cell(|c|c.text(e.vm.to_string()))
+			.table_cell(|c|c.text(e.values.join("\\")))
+		);
+	}
+	table_builder.build()
+}
+
 pub async fn table_from_objects(
 	objs:Vec<Entry>,
 	id_name:String,
@@ -99,7 +120,7 @@ pub async fn table_from_objects(
 			let mut cellbuilder=TableCell::builder();
 			if let Some(value) = item
 			{
-				cellbuilder.text(value.as_string().cloned().unwrap_or(value.to_sql()));
+				cellbuilder.text(db::display_string(&value));
 			} else {cellbuilder.text("----------");}
 			row_builder.push(cellbuilder.build());
 		}
@@ -117,11 +138,39 @@ pub async fn entry_page(entry:Entry) -> Result<Html>
 	// @todo this may be very expensive, maybe find a better way
 	let common_path= entry.get_path().await?;
 	builder.heading_1(|h|h.text(name.to_owned()));
+	let csa = match &entry {
+		Entry::Instance(_) => Some(csa::headers(&entry.get_file()?.read().await?)),
+		_ => None
+	};
 	match entry {
 		Entry::Instance((id,mut instance)) => {
 			instance.remove("series");
+			let provenance = instance.remove("provenance");
+			let metadata = db::metadata::get(&id).await?.and_then(|mut m|m.remove("metadata"));
 			builder.heading_2(|h|h.text("Attributes"))
 				.push(table_from_map(instance.into_inner()));
+			if let Some(db_types::Value::Object(provenance)) = provenance {
+				let provenance = provenance.into_inner().into_iter()
+					.filter(|(_,v)|!v.is_nullish())
+					.collect();
+				builder.heading_2(|h|h.text("Provenance"))
+					.push(table_from_map(provenance));
+			}
+			if let Some(db_types::Value::Object(metadata)) = metadata {
+				builder.heading_2(|h|h.text("Metadata"))
+					.push(table_from_map(metadata.into_inner()));
+			}
+			match csa {
+				Some(Ok(headers)) => for (name,header) in headers {
+					builder.heading_2(|h|h.text(format!("CSA {name} header")))
+						.push(table_from_csa(&header));
+				},
+				Some(Err(e)) => {
+					builder.heading_2(|h|h.text("CSA header"))
+						.paragraph(|p|p.text(format!("Failed to parse: {e}")));
+				},
+				None => {}
+			}
 			builder.heading_2(|h|h.text("Image"))
 				.paragraph(|p|
 					p.image(|i|i.src(format!("/api/instances/{}/png",id.str_key())))
@@ -130,13 +179,13 @@ pub async fn entry_page(entry:Entry) -> Result<Html>
 		Entry::Series((id,mut series)) => {
 			series.remove("study");
 			series.remove("instances");
+			series.remove("search_terms");
 			builder.heading_2(|h|h.text("Attributes")).push(table_from_map(series.into_inner()));
 			let mut instances= entries_for_record(&id,"instances").await?;
 			instances.sort_by_key(|s|s
-				.get_string("number")
-				.map_or(0,|s|s
-					.parse::<u64>().unwrap_or(0)
-				)
+				.get_text("Number",VR::IS)
+				.and_then(|n|n.parse::<u64>().ok())
+				.unwrap_or(0)
 			);
 
 			builder
@@ -156,6 +205,9 @@ pub async fn entry_page(entry:Entry) -> Result<Html>
 		}
 		Entry::Study((id,mut study)) => {
 			study.remove("series");
+			for field in ["search_terms", "search", "search_words"] {
+				study.remove(field);
+			}
 			builder.heading_2(|h|h.text("Attributes")).push(table_from_map(study.into_inner()));
 
 			let mut series= entries_for_record(&id, "series").await?;
@@ -171,11 +223,13 @@ pub async fn entry_page(entry:Entry) -> Result<Html>
 				.paragraph(|p|p.text(common_path.display().to_string()));
 
 			series.sort_by_key(|s|s
-				.get_string("Number").expect("missing Number in series")
-				.parse::<u64>().expect("Number in Series is not a number")
+				.get_text("Number",VR::IS)
+				.and_then(|n|n.parse::<u64>().ok())
+				.unwrap_or(0)
 			);
 
 			let keys= crate::config::get().series_tags.keys().cloned()
+				.chain(crate::config::get().series_aggregates.keys().cloned())
 				.chain(["Instances","Size"].map(str::to_string)).collect();
 			let series_text = format!("{} series",series.len());
 			let series_table = table_from_objects(series, "Name".into(), keys, vec![]).await?;
@@ -186,6 +240,66 @@ pub async fn entry_page(entry:Entry) -> Result<Html>
 	Ok(wrap_body(builder.build(), name))
 }
 
+fn action_form(id:&RecordId, action:&str) -> Form
+{
+	Form::builder()
+		.action(format!("{}/{action}",id.str_path()))
+		.method("post")
+		.button(|b|b.text(action.to_string()))
+		.build()
+}
+
+pub fn search_form(text:Option<&str>) -> Form
+{
+	Form::builder()
+		.action("/html/studies")
+		.method("get")
+		.input(|i|i
+			.type_("search")
+			.name("search")
+			.placeholder("patient, description, protocol ...")
+			.value(text.unwrap_or_default().to_string())
+		)
+		.button(|b|b.text("search"))
+		.build()
+}
+
+pub fn quarantine_page(entries:Vec<Quarantined>) -> Html
+{
+	let mut table_builder = Table::builder();
+	table_builder.table_row(|r|
+		["SOPInstanceUID","Reason","Existing","Timestamp","File",""].into_iter()
+			.fold(r,|r,key|r.table_header(|c|c.text(key)))
+	);
+	for entry in entries
+	{
+		table_builder.table_row(|r|{r
+			.table_cell(|c|c.text(entry.uid.clone()))
+			.table_cell(|c|c.text(entry.message.clone()))
+			.table_cell(|c|match &entry.existing {
+				Some(id) => c.anchor(|a|a
+					.href(format!("/html/{}/{}",id.table,id.str_key()))
+					.text(id.to_string())
+				),
+				None => c.text("----------")
+			})
+			.table_cell(|c|c.text(entry.timestamp.to_sql()))
+			.table_cell(|c|c.anchor(|a|a
+				.href(format!("{}/file",entry.id.str_path()))
+				.text(entry.file.get_path().display().to_string())
+			))
+			.table_cell(|c|
+				["overwrite","accept","discard"].into_iter()
+					.fold(c,|c,action|c.push(action_form(&entry.id,action)))
+			)
+		});
+	}
+	let mut builder = Body::builder();
+	builder.heading_1(|h|h.text("Quarantine"));
+	builder.push(table_builder.build());
+	wrap_body(builder.build(), "Quarantine")
+}
+
 pub fn wrap_body<T>(body:Body, title:T) -> Html where T:Into<std::borrow::Cow<'static, str>>
 {
 	Html::builder().lang("en")
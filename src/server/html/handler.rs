@@ -18,9 +18,13 @@ use std::collections::BTreeMap;
 use tokio::task::JoinSet;
 use tracing::warn;
 
+/// maximum number of studies listed for a search
+const SEARCH_LIMIT:u32 = 200;
+
 #[derive(Deserialize)]
 pub struct ListingConfig {
 	filter:Option<String>,
+	search:Option<String>,
 	sort_by:Option<String>,
 	#[serde(default)]
 	sort_reverse:bool
@@ -31,27 +35,35 @@ pub async fn get_studies_html(headers: HeaderMap,Query(config): Query<ListingCon
 {
 	let keys:Vec<_> = crate::config::get().study_tags.keys().cloned()
 		.chain(["Date", "Time"].map(String::from))
+		.chain(crate::config::get().study_aggregates.keys().cloned())
 		.unique()//make sure there are no duplicates
 		.collect();
 
-	let mut studies = list_entries("studies").await.into_http_error(&headers)?;
+	let search = config.search.filter(|s|!s.trim().is_empty());
+	let mut studies = match &search {
+		Some(text) => db::search::search(text, true, SEARCH_LIMIT).await,
+		None => list_entries("studies").await
+	}.into_http_error(&headers)?;
 
 	if let Some(filter) = config.filter
 	{
 		studies.retain(|e|e.name().find(filter.as_str()).is_some());
 	}
 
-	let sortby = config.sort_by.unwrap_or("Date".to_string());
-	studies.sort_by(|e1,e2|
-		match (e1.get(&sortby),e2.get(&sortby)) {
-			(Some(v1),Some(v2)) => if config.sort_reverse {
-				v1.partial_cmp(v2).unwrap_or(Ordering::Equal)
-			} else {
-				v2.partial_cmp(v1).unwrap_or(Ordering::Equal)
-			},
-			_ => Ordering::Equal
-		}
-	);
+	// search results stay ordered by relevance unless asked otherwise
+	let sortby = config.sort_by.or(search.is_none().then(||"Date".to_string()));
+	if let Some(sortby) = sortby {
+		studies.sort_by(|e1,e2|
+			match (e1.get(&sortby),e2.get(&sortby)) {
+				(Some(v1),Some(v2)) => if config.sort_reverse {
+					v1.partial_cmp(v2).unwrap_or(Ordering::Equal)
+				} else {
+					v2.partial_cmp(v1).unwrap_or(Ordering::Equal)
+				},
+				_ => Ordering::Equal
+			}
+		);
+	}
 
 	let mut aggregate_collector = JoinSet::new();
 	for study in studies.clone() {
@@ -90,6 +102,7 @@ pub async fn get_studies_html(headers: HeaderMap,Query(config): Query<ListingCon
 
 	let mut builder = Body::builder();
 	builder.heading_1(|h|h.text("Studies"));
+	builder.push(generators::search_form(search.as_deref()));
 	builder.push(table);
 	Ok(axum::response::Html(generators::wrap_body(builder.build(), "Studies").to_string()))
 }
@@ -107,3 +120,9 @@ pub async fn get_entry_html(headers: HeaderMap,Path((table,id)):Path<(String,Str
 	}
 }
 
+
+pub async fn get_quarantine_html(headers: HeaderMap) -> Result<axum::response::Html<String>, HttpError>
+{
+	let entries = crate::tools::quarantine::list().await.into_http_error(&headers)?;
+	Ok(axum::response::Html(generators::quarantine_page(entries).to_string()))
+}
@@ -7,5 +7,6 @@ pub fn router() -> axum::Router
 {
 	axum::Router::new()
 		.route("/studies",get(handler::get_studies_html))
+		.route("/quarantine",get(handler::get_quarantine_html))
 		.route("/{table}/{id}",get(handler::get_entry_html))
 }
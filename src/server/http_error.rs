@@ -37,7 +37,10 @@ impl InnerHttpError
 						ErrorKind::StorageFull | ErrorKind::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
 						_ => StatusCode::INTERNAL_SERVER_ERROR,
 					}
-				tools::Error::DataConflict(_)|tools::Error::FieldConflict {..} => StatusCode::CONFLICT,
+				tools::Error::DataConflict(_)|tools::Error::FieldConflict {..}|tools::Error::Quarantined {..} => StatusCode::CONFLICT,
+				tools::Error::InvalidChange {..} | tools::Error::InvalidFilter {..} => StatusCode::BAD_REQUEST,
+				tools::Error::Unauthorized {..} => StatusCode::UNAUTHORIZED,
+				tools::Error::Forbidden {..} | tools::Error::PathNotAllowed {..} => StatusCode::FORBIDDEN,
 				_ => StatusCode::INTERNAL_SERVER_ERROR
 			});
 		error_code.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
@@ -55,6 +58,7 @@ impl InnerHttpError
 			InnerHttpError::Internal(e) => {
 				match e {
 					tools::Error::IdNotFound {id} => tracing::debug!("{id} reported as not found"),
+					tools::Error::Unauthorized {..} | tools::Error::Forbidden {..} => tracing::info!("{e}"),
 					_ => tracing::error!("internal error {} reported (root cause '{}')", e, e.root_cause()),
 				}
 			}
@@ -3,6 +3,8 @@ use axum::extract::Query;
 use axum::http::HeaderMap;
 use axum::response::{IntoResponse, Response};
 use serde_json::json;
+use crate::config;
+use crate::db::audit::Actor;
 use crate::server::http_error::{HttpError, IntoHttpError};
 use futures::StreamExt;
 use crate::server::json::{get_mime, is_json};
@@ -12,23 +14,23 @@ use crate::tools::import::{import_glob, import_glob_as_text, ImportConfig, Impor
 pub(super) fn router() -> axum::Router
 {
     axum::Router::new()
-        .route("/import",post(|headers,config,pattern|import(headers,config,ImportMode::Import,pattern)))
-		.route("/store",post(|headers,config,pattern|import(headers,config,ImportMode::Store,pattern)))
-		.route("/move",post(|headers,config,pattern|import(headers,config,ImportMode::Move,pattern)))
+        .route("/import",post(|headers,actor,config,pattern|import(headers,actor,config,ImportMode::Import,pattern)))
+		.route("/store",post(|headers,actor,config,pattern|import(headers,actor,config,ImportMode::Store,pattern)))
+		.route("/move",post(|headers,actor,config,pattern|import(headers,actor,config,ImportMode::Move,pattern)))
 }
 
-async fn import(headers: HeaderMap,Query(config): Query<ImportConfig>,mode:ImportMode, pattern:String) -> Result<Response, HttpError>
+async fn import(headers: HeaderMap,actor:Actor,Query(config): Query<ImportConfig>,mode:ImportMode, pattern:String) -> Result<Response, HttpError>
 {
 	let wants_json = get_mime(&headers).map_or(false,|m|is_json(&m));
 	if wants_json {
-		import_json(headers, config, mode, pattern).await
+		import_json(headers, actor, config, mode, pattern).await
 	} else {
-		import_text(headers, config, mode, pattern).await
+		import_text(headers, actor, config, mode, pattern).await
 	}
 }
-async fn import_text(headers: HeaderMap,config: ImportConfig, mode:ImportMode, pattern:String) -> Result<Response, HttpError>
+async fn import_text(headers: HeaderMap,actor:Actor,config: ImportConfig, mode:ImportMode, pattern:String) -> Result<Response, HttpError>
 {
-	let stream= import_glob_as_text(pattern,config,mode).into_http_error(&headers)?
+	let stream= import_glob_as_text(pattern,config,mode,actor,Some(&config::get().paths.import_roots)).into_http_error(&headers)?
 		.map(|r|match r {
 			Ok(s) => s+"\n",
 			Err(e) => format!("Import task panicked:{e}")
@@ -36,10 +38,10 @@ async fn import_text(headers: HeaderMap,config: ImportConfig, mode:ImportMode, p
 	Ok(axum_streams::StreamBodyAs::text(stream).into_response())
 }
 
-async fn import_json(headers: HeaderMap,config:ImportConfig,mode:ImportMode, pattern:String) -> Result<Response, HttpError>
+async fn import_json(headers: HeaderMap,actor:Actor,config:ImportConfig,mode:ImportMode, pattern:String) -> Result<Response, HttpError>
 {
 
-	let stream=import_glob(pattern,config,mode).into_http_error(&headers)?
+	let stream=import_glob(pattern,config,mode,actor,Some(&config::get().paths.import_roots)).into_http_error(&headers)?
 		.map(|r|match r {
 			Ok(s) => serde_json::to_value(s)
 					.unwrap_or_else(|e|json!({"error":"serialisation failed","cause":format!("{e}")})),
@@ -1,15 +1,19 @@
 use std::collections::{BTreeMap, HashMap};
 use crate::db;
+use crate::db::audit::{self, Actor, AuditFilter};
+use crate::db::provenance::{self, ProvenanceFilter};
 use crate::server::http_error::{HttpError, IntoHttpError};
 use crate::tools::Error::IdNotFound;
-use crate::tools::{entries_for_record, Context};
-use axum::extract::{FromRequest, Path, Request};
+use crate::tools::{csa, entries_for_record, Context};
+use axum::extract::{FromRequest, Path, Query, Request};
 use axum::extract::rejection::{FormRejection, JsonRejection};
 use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Json;
 use mime::Mime;
+use serde::Deserialize;
+use serde_json::json;
 use crate::server::lookup_or;
 use crate::tools::conv::value_to_json;
 use surrealdb::types as db_types;
@@ -22,8 +26,21 @@ pub(super) fn router() -> axum::Router
         .route("/{table}/{id}",get(query_entry))
 		.route("/{table}/{id}/col/{name}",get(get_value).post(set_value).delete(delete_value))
 		.route("/{table}/{id}/parents",get(get_entry_parents))
+		.route("/{table}/{id}/audit",get(query_entry_audit))
+		.route("/audit",get(query_audit))
+		.route("/provenance",get(query_provenance))
+		.route("/routing",get(query_routing))
+		.route("/routing/retry",axum::routing::post(retry_routing))
+		.route("/collisions",get(query_collisions))
+		.route("/search",get(search_studies))
+		.route("/metadata/rederive",axum::routing::post(rederive_from_metadata))
+		.route("/metadata/{table}",get(query_metadata))
 		.route("/{table}/{id}/instances",get(query_instances))
 		.route("/{table}/{id}/series",get(query_series))
+		.route("/instances/{id}/versions",get(query_versions))
+		.route("/instances/{id}/csa",get(query_csa))
+		.route("/instances/{id}/metadata",get(query_instance_metadata))
+		.route("/instances/{id}/versions/{version}",get(query_version))
 }
 
 pub fn get_mime(headers: &HeaderMap<HeaderValue>) -> Option<Mime>
@@ -95,6 +112,39 @@ async fn query_series(headers: HeaderMap,Path(path):Path<(String, String)>) -> R
 	let instances:Vec<_> = entries_for_record(entry.id(),"series").await.into_http_error(&headers)?;
 	Ok(Json(serde_json::Value::from(instances)).into_response())
 }
+async fn query_versions(headers: HeaderMap,Path(id):Path<String>) -> Result<Response, HttpError>
+{
+	let entry = lookup_or(&("instances".into(),id)).await.into_http_error(&headers)?;
+	let versions:Vec<_> = db::list_versions(entry.id()).await.into_http_error(&headers)?
+		.into_iter().map(serde_json::Value::from)
+		.collect();
+	Ok(Json(versions).into_response())
+}
+async fn query_csa(headers: HeaderMap,Path(id):Path<String>) -> Result<Response, HttpError>
+{
+	let entry = lookup_or(&("instances".into(),id)).await.into_http_error(&headers)?;
+	let obj = entry.get_file().into_http_error(&headers)?.read().await.into_http_error(&headers)?;
+	let parsed:serde_json::Map<_,_> = csa::headers(&obj).into_http_error(&headers)?
+		.iter().map(|(name,header)|(name.to_string(),serde_json::Value::from(header)))
+		.collect();
+	Ok(Json(parsed).into_response())
+}
+async fn query_instance_metadata(headers: HeaderMap,Path(id):Path<String>) -> Result<Response, HttpError>
+{
+	let entry = lookup_or(&("instances".into(),id.clone())).await.into_http_error(&headers)?;
+	db::metadata::get(entry.id()).await
+		.and_then(|m|m.ok_or(IdNotFound {id:format!("metadata of {id}")}))
+		.map(|m|Json(value_to_json(db_types::Value::Object(m))).into_response())
+		.into_http_error(&headers)
+}
+async fn query_version(headers: HeaderMap,Path((id,version)):Path<(String,i64)>) -> Result<Response, HttpError>
+{
+	let entry = lookup_or(&("instances".into(),id.clone())).await.into_http_error(&headers)?;
+	db::lookup_version(entry.id(),version).await
+		.and_then(|v|v.ok_or(IdNotFound {id:format!("version {version} of {id}")}))
+		.map(|v|Json(serde_json::Value::from(v)).into_response())
+		.into_http_error(&headers)
+}
 async fn query_table(headers: HeaderMap,Path(table):Path<String>) -> Result<Response, HttpError>
 {
 	let qry = db::list_entries(table).await.into_http_error(&headers)?;
@@ -136,18 +186,94 @@ async fn get_value(headers: HeaderMap,Path((table,uid,name)):Path<(String, Strin
 	Ok(Json(value_to_json(value)).into_response())
 }
 
-async fn set_value(headers: HeaderMap,Path((table,uid,name)):Path<(String, String, String)>,content:Content) -> Result<Response, HttpError>
+async fn set_value(headers: HeaderMap,actor:Actor,Path((table,uid,name)):Path<(String, String, String)>,content:Content) -> Result<Response, HttpError>
 {
 	let entry = lookup_or(&(table,uid)).await.into_http_error(&headers)?;
-	db::set_value(entry.id().0.to_owned(),name,content.0).await
-		.map(|v|(StatusCode::ACCEPTED,Json(value_to_json(v))).into_response())
-		.into_http_error(&headers)
+	let diff = json!({
+		"column":name,
+		"before":entry.get(name.as_str()).cloned().map(value_to_json),
+		"after":value_to_json(content.0.clone()),
+	});
+	let updated = db::set_value(entry.id().0.to_owned(),name,content.0).await.into_http_error(&headers)?;
+	audit::log(&actor, "set_value", entry.id(), diff).await;
+	Ok((StatusCode::ACCEPTED,Json(value_to_json(updated))).into_response())
 }
 
-async fn delete_value(headers: HeaderMap,Path((table,uid,name)):Path<(String, String, String)>) -> Result<Response, HttpError>
+async fn delete_value(headers: HeaderMap,actor:Actor,Path((table,uid,name)):Path<(String, String, String)>) -> Result<Response, HttpError>
 {
 	let entry = lookup_or(&(table,uid)).await.into_http_error(&headers)?;
-	db::delete_value(entry.id().0.to_owned(),name).await
-		.map(|v|(StatusCode::ACCEPTED,Json(value_to_json(v))).into_response())
-		.into_http_error(&headers)
+	let diff = json!({
+		"column":name,
+		"before":entry.get(name.as_str()).cloned().map(value_to_json),
+	});
+	let updated = db::delete_value(entry.id().0.to_owned(),name).await.into_http_error(&headers)?;
+	audit::log(&actor, "delete_value", entry.id(), diff).await;
+	Ok((StatusCode::ACCEPTED,Json(value_to_json(updated))).into_response())
+}
+
+async fn query_audit(headers: HeaderMap,Query(filter):Query<AuditFilter>) -> Result<Json<Vec<serde_json::Value>>, HttpError>
+{
+	let entries = audit::query(None, &filter).await.into_http_error(&headers)?;
+	Ok(Json(entries.iter().map(serde_json::Value::from).collect()))
+}
+
+async fn query_provenance(headers: HeaderMap,Query(filter):Query<ProvenanceFilter>) -> Result<Response, HttpError>
+{
+	let instances = provenance::query(&filter).await.into_http_error(&headers)?;
+	Ok(Json(serde_json::Value::from(instances)).into_response())
+}
+
+async fn query_routing(headers: HeaderMap) -> Result<Json<Vec<serde_json::Value>>, HttpError>
+{
+	let queue = crate::tools::routing::queue().await.into_http_error(&headers)?;
+	Ok(Json(queue.into_iter().map(value_to_json).collect()))
+}
+
+async fn retry_routing(headers: HeaderMap) -> Result<StatusCode, HttpError>
+{
+	crate::tools::routing::retry_failed().await.into_http_error(&headers)?;
+	Ok(StatusCode::OK)
+}
+
+async fn query_collisions(headers: HeaderMap) -> Result<Json<Vec<serde_json::Value>>, HttpError>
+{
+	let collisions = db::list_collisions().await.into_http_error(&headers)?;
+	Ok(Json(collisions.into_iter().map(value_to_json).collect()))
+}
+
+async fn query_metadata(headers: HeaderMap,Path(table):Path<String>,Query(filters):Query<BTreeMap<String,String>>) -> Result<Response, HttpError>
+{
+	let found = db::metadata::query(&table, &filters).await.into_http_error(&headers)?;
+	Ok(Json(serde_json::Value::from(found)).into_response())
+}
+
+#[derive(Deserialize)]
+struct SearchQuery
+{
+	q:String,
+	/// fall back to similar words if nothing matches, defaults to true
+	fuzzy:Option<bool>,
+	/// maximum number of studies returned, defaults to 50
+	limit:Option<u32>,
+}
+
+async fn search_studies(headers: HeaderMap,Query(query):Query<SearchQuery>) -> Result<Response, HttpError>
+{
+	let found = db::search::search(&query.q, query.fuzzy.unwrap_or(true), query.limit.unwrap_or(50))
+		.await.into_http_error(&headers)?;
+	Ok(Json(serde_json::Value::from(found)).into_response())
+}
+
+async fn rederive_from_metadata(headers: HeaderMap,actor:Actor) -> Result<Json<serde_json::Value>, HttpError>
+{
+	let skipped = db::metadata::rederive().await.into_http_error(&headers)?;
+	audit::log(&actor, "rederive", &db::metadata::rederive_target(), json!({"skipped":skipped})).await;
+	Ok(Json(json!({"skipped":skipped})))
+}
+
+async fn query_entry_audit(headers: HeaderMap,Path(path):Path<(String, String)>,Query(filter):Query<AuditFilter>) -> Result<Json<Vec<serde_json::Value>>, HttpError>
+{
+	let entry = lookup_or(&path).await.into_http_error(&headers)?;
+	let entries = audit::query(Some(entry.id()), &filter).await.into_http_error(&headers)?;
+	Ok(Json(entries.iter().map(serde_json::Value::from).collect()))
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
+use std::convert::Infallible;
 use std::net::SocketAddr;
 use axum::{Json, Router};
 use axum::body::Body;
-use axum::extract::DefaultBodyLimit;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, FromRequestParts};
 use axum::http::HeaderMap;
+use axum::http::request::Parts;
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use serde::Serialize;
@@ -10,9 +12,11 @@ use tokio::net::TcpListener;
 use tracing;
 use crate::{config, db};
 use crate::db::DB;
+use crate::db::audit::Actor;
 use crate::server::http_error::{HttpError, IntoHttpError};
 use crate::tools::Error::IdNotFound;
 use crate::tools::Result;
+use crate::tools::tls::{self, TlsListener};
 
 #[cfg(feature = "html")]
 mod html;
@@ -20,6 +24,9 @@ mod json;
 mod import;
 mod other;
 mod http_error;
+mod quarantine;
+mod auth;
+mod events;
 
 #[derive(Serialize,Clone)]
 pub struct Info
@@ -45,9 +52,28 @@ pub async fn backup(headers: HeaderMap) -> std::result::Result<Response, HttpErr
 }
 
 pub async fn serve(listener:TcpListener) -> Result<()>
+{
+	let app = app().await.into_make_service_with_connect_info::<SocketAddr>();
+	// run it
+	match &config::get().tls {
+		Some(tls) => {
+			tracing::info!("http server listening on https://{}", listener.local_addr()?);
+			axum::serve(TlsListener::new(listener, tls::acceptor(tls)?)?, app)
+				.with_graceful_shutdown(super::tools::shutdown_signal())
+				.await
+		}
+		None => {
+			tracing::info!("http server listening on http://{}", listener.local_addr()?);
+			axum::serve(listener, app)
+				.with_graceful_shutdown(super::tools::shutdown_signal())
+				.await
+		}
+	}.map_err(|e|e.into())
+}
+
+async fn app() -> Router
 {
 	let inf=server_info().await;
-	tracing::info!("http server listening on http://{}", listener.local_addr()?);
 
 	// build our application with a route
 	let mut app = Router::new();
@@ -55,6 +81,9 @@ pub async fn serve(listener:TcpListener) -> Result<()>
 		.nest("/api", other::router()
 			.route("/info", get(||async {Json(inf)}))
 			.merge(json::router())
+			.merge(quarantine::router())
+			.merge(auth::router())
+			.merge(events::router())
 		)
 		.nest("/tools",import::router()
 			.route("/backup", get(backup))
@@ -65,11 +94,19 @@ pub async fn serve(listener:TcpListener) -> Result<()>
 	{
 		app = app.nest("/html", html::router());
 	}
+	app.layer(axum::middleware::from_fn(auth::authorize))
+}
 
-	// run it
-	axum::serve(listener,app.into_make_service_with_connect_info::<SocketAddr>())
-		.with_graceful_shutdown(super::tools::shutdown_signal())
-		.await.map_err(|e|e.into())
+impl<S> FromRequestParts<S> for Actor where S: Send + Sync
+{
+	type Rejection = Infallible;
+
+	async fn from_request_parts(parts: &mut Parts, _state: &S) -> std::result::Result<Self, Self::Rejection> {
+		let mut actor = parts.extensions.get::<ConnectInfo<SocketAddr>>()
+			.map_or_else(Actor::default,|ConnectInfo(addr)|Actor::from_addr(addr));
+		actor.user = parts.extensions.get::<auth::Identity>().and_then(|i|i.user.clone());
+		Ok(actor)
+	}
 }
 
 pub async fn lookup_or(rec:&(String, String)) -> Result<db::Entry>
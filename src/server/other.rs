@@ -1,9 +1,11 @@
 use std::collections::HashMap;
-use crate::db::{Entry, LocalSession, RegisterResult, Session, DB};
+use crate::db::audit::{self, Actor};
+use crate::db::{Entry, LocalSession, Provenance, RecordId, RegisterResult, Session, DB};
 use crate::server::http_error::{HttpError, InnerHttpError, IntoHttpError};
 use crate::server::lookup_or;
+use crate::tools::mosaic;
 use crate::tools::tar::{make_tar, TarStream};
-use crate::tools::{get_instance_dicom, lookup_instance_file,remove::remove,verify::verify_entry, Error};
+use crate::tools::{get_instance_dicom, lookup_instance_file,remove::remove,reorganize,verify::verify_entry, Error};
 use crate::tools::{Context, Error::DicomError};
 use crate::db;
 use axum::body::{Body, Bytes};
@@ -41,7 +43,12 @@ pub(super) fn router() -> axum::Router
 		.route("/{table}/{id}/tar",get(get_tar))
 		.route("/{table}/{id}/tar/{suffix}",get(get_tar_comp))
 		.route("/{table}/{id}/filepath",get(filepath))
+		.route("/{table}/{id}/correct",post(correct))
+		.route("/series/{id}/move",post(move_series))
+		.route("/studies/{id}/merge",post(merge_studies))
+		.route("/studies/{id}/split",post(split_study))
         .route("/instances/{id}/file",get(get_instance_file))
+        .route("/instances/{id}/versions/{version}/file",get(get_version_file))
         .route("/instances/{id}/png",get(get_instance_png));
     #[cfg(feature="dicom-json")]
     {
@@ -55,10 +62,53 @@ async fn get_statistics(headers: HeaderMap) -> Result<Json<db::Stats>, HttpError
 	db::statistics().await.map(Json).into_http_error(&headers)
 }
 
-async fn del_entry(headers: HeaderMap,Path(path):Path<(String, String)>) -> Result<(), HttpError>
+async fn del_entry(headers: HeaderMap,actor:Actor,Path(path):Path<(String, String)>) -> Result<(), HttpError>
 {
 	let entry = lookup_or(&path).await.into_http_error(&headers)?;
-	remove(entry.id()).await.into_http_error(&headers)
+	remove(entry.id()).await.into_http_error(&headers)?;
+	let id = entry.id().clone();
+	audit::log(&actor, "delete", &id, json!({"before":serde_json::Value::from(entry)})).await;
+	Ok(())
+}
+
+async fn correct(headers: HeaderMap,actor:Actor,Path(path):Path<(String, String)>,Json(changes):Json<HashMap<String,Option<String>>>) -> Result<Json<Vec<String>>, HttpError>
+{
+	let entry = lookup_or(&path).await.into_http_error(&headers)?;
+	let changes = changes.into_iter()
+		.map(|(name,value)| crate::dcm::find_tag(&name).map(|tag|(tag,value))
+			.ok_or(InnerHttpError::BadRequest {message:format!(r#"Tag "{name}" is not known"#)}))
+		.collect::<Result<Vec<_>,_>>().into_http_error(&headers)?;
+	let corrected = crate::tools::correct::correct(entry.id(), changes, &actor).await.into_http_error(&headers)?;
+	Ok(Json(corrected.iter().map(RecordId::str_path).collect()))
+}
+
+#[derive(Deserialize)]
+pub struct MoveTarget {
+	study: String,
+}
+
+#[derive(Deserialize)]
+pub struct SplitSeries {
+	series: Vec<String>,
+}
+
+async fn move_series(headers: HeaderMap,actor:Actor,Path(id):Path<String>,Json(target):Json<MoveTarget>) -> Result<Json<Vec<String>>, HttpError>
+{
+	let moved = reorganize::move_series(&RecordId::from_series(id), &RecordId::from_study(target.study), &actor).await.into_http_error(&headers)?;
+	Ok(Json(moved.iter().map(RecordId::str_path).collect()))
+}
+
+async fn merge_studies(headers: HeaderMap,actor:Actor,Path(id):Path<String>,Json(source):Json<MoveTarget>) -> Result<Json<Vec<String>>, HttpError>
+{
+	let moved = reorganize::merge_studies(&RecordId::from_study(id), &RecordId::from_study(source.study), &actor).await.into_http_error(&headers)?;
+	Ok(Json(moved.iter().map(RecordId::str_path).collect()))
+}
+
+async fn split_study(headers: HeaderMap,actor:Actor,Path(id):Path<String>,Json(split):Json<SplitSeries>) -> Result<Json<String>, HttpError>
+{
+	let series:Vec<_> = split.series.into_iter().map(RecordId::from_series).collect();
+	let study = reorganize::split_study(&RecordId::from_study(id), &series, &actor).await.into_http_error(&headers)?;
+	Ok(Json(study.str_path()))
 }
 
 async fn verify(headers: HeaderMap,Path(path):Path<(String, String)>) -> Result<Response, HttpError>
@@ -89,6 +139,7 @@ async fn filepath(headers: HeaderMap,Path(path):Path<(String, String)>) -> Resul
 async fn store_instance(
 	headers: HeaderMap,
 	ConnectInfo(addr): ConnectInfo<SocketAddr>,
+	actor: Actor,
 	State(sessions): State<Arc<Mutex<HashMap<IpAddr,LocalSession<Any>>>>>,
 	payload:Result<Bytes,BytesRejection>
 ) -> Result<Response, HttpError> {
@@ -101,14 +152,17 @@ async fn store_instance(
 	let obj= from_reader(Cursor::new(bytes)).map_err(|e|DicomError(e.into())).into_http_error(&headers)?;
 	let mut sessions = sessions.lock().await;
 	let session = sessions.entry(addr.ip()).or_insert_with(|| LocalSession::create(&DB,1));
-	match store_ob(obj, session).await {
-		Ok(RegisterResult::Stored(id)) => Ok((StatusCode::CREATED,
-			Json(json!({
-				"Status":"Success",
-				"Path":id.str_path(),
-				"id":id.str_key(),
-			}))
-		).into_response()),
+	match store_ob(obj, &Provenance::http(&actor), session).await {
+		Ok(RegisterResult::Stored(id)) => {
+			audit::log(&actor, "store", &id, json!({})).await;
+			Ok((StatusCode::CREATED,
+				Json(json!({
+					"Status":"Success",
+					"Path":id.str_path(),
+					"id":id.str_key(),
+				}))
+			).into_response())
+		},
 		Ok(RegisterResult::AlreadyStored(id)) => Ok((StatusCode::FOUND,
 			Json(json!({
 				"Status":"AlreadyStored",
@@ -137,6 +191,16 @@ async fn store_instance(
 				}))
 			).into_response())
 		}
+		Err(Error::Quarantined {id, reason}) => {
+			Ok((
+				StatusCode::ACCEPTED,
+				Json(json!({
+					"Status":"Quarantined",
+					"QuarantinePath":id.str_path(),
+					"Reason":reason.to_string(),
+				}))
+			).into_response())
+		}
 		Err(e) => Err(HttpError::new(e, &headers))
 	}
 }
@@ -164,6 +228,27 @@ async fn get_instance_file(headers: HeaderMap,Path(id):Path<String>) -> Result<R
 	}
 }
 
+async fn get_version_file(headers: HeaderMap,Path((id,version)):Path<(String,i64)>) -> Result<Response, HttpError>
+{
+	let entry = lookup_or(&("instances".into(),id.clone())).await.into_http_error(&headers)?;
+	let file = db::lookup_version(entry.id(),version).await
+		.and_then(|v|v.ok_or(Error::IdNotFound {id:format!("version {version} of {id}")}))
+		.and_then(|v|v.get_file())
+		.into_http_error(&headers)?;
+	let filename=file.get_path().file_name()
+		.map(|o|o.to_string_lossy().to_string())
+		.unwrap_or(format!("Mr.{id}.v{version}.ima"));
+	let file= tokio::fs::File::open(file.get_path()).await.into_http_error(&headers)?;
+	Ok((
+		StatusCode::OK,
+		[
+			(header::CONTENT_TYPE, "application/dicom"),
+			(header::CONTENT_DISPOSITION, format!(r#"attachment; filename="{filename}""#).as_str())
+		],
+		AsyncReadBody::new(file)
+	).into_response())
+}
+
 #[cfg(feature = "dicom-json")]
 async fn get_instance_json_ext(headers: HeaderMap,Path(id):Path<String>) -> Result<Response, HttpError>
 {
@@ -179,10 +264,13 @@ async fn get_instance_json_ext(headers: HeaderMap,Path(id):Path<String>) -> Resu
 	}
 }
 
-#[derive(Deserialize)]
-pub struct ImageSize {
-	width: u32,
-	height: u32,
+#[derive(Deserialize,Default)]
+pub struct ImageQuery {
+	/// the image is scaled down to fit width and height (if both are given)
+	width: Option<u32>,
+	height: Option<u32>,
+	/// slice of a Siemens mosaic
+	slice: Option<u32>,
 }
 
 async fn get_tar(headers: HeaderMap,Path(path):Path<(String, String)>) -> Result<Response, HttpError>
@@ -235,7 +323,7 @@ async fn get_tar_impl(entry: Entry,suffix:String) -> Result<Response, InnerHttpE
 	).into_response())
 }
 
-async fn get_instance_png(headers: HeaderMap,Path(id):Path<String>, OptionalQuery(size): OptionalQuery<ImageSize>) -> Result<Response, HttpError>
+async fn get_instance_png(headers: HeaderMap,Path(id):Path<String>, OptionalQuery(query): OptionalQuery<ImageQuery>) -> Result<Response, HttpError>
 {
 	let ctx = format!("decoding pixel data of {id}");
 	let not_found = format!("Instance {} not found", id);
@@ -249,8 +337,17 @@ async fn get_instance_png(headers: HeaderMap,Path(id):Path<String>, OptionalQuer
 			.and_then(|p|p.to_dynamic_image(0))
 			.map_err(|e|DicomError(e.into()))
 			.context(ctx).into_http_error(&headers)?;
-		if let Some(size) = size{
-			image=image.thumbnail(size.width,size.height);
+		let query = query.unwrap_or_default();
+		if let Some(slice) = query.slice {
+			let sliced = mosaic::number_of_slices(&obj)
+				.and_then(|slices|mosaic::slice(&image, slices, slice));
+			match sliced {
+				Some(sliced) => image = sliced,
+				None => return Ok((StatusCode::BAD_REQUEST, format!("The instance has no slice {slice}")).into_response())
+			}
+		}
+		if let (Some(width),Some(height)) = (query.width,query.height) {
+			image=image.thumbnail(width,height);
 		}
 		image.write_to(&mut buffer, ImageFormat::Png).expect("Unexpectedly failed to write png data to memory buffer");
 
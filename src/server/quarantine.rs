@@ -0,0 +1,91 @@
+use crate::db::audit::{self, Actor};
+use crate::db::{RecordId, RegisterResult};
+use crate::server::http_error::{HttpError, IntoHttpError};
+use crate::tools::quarantine;
+use crate::tools::Error::IdNotFound;
+use axum::extract::Path;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Json;
+use axum_extra::body::AsyncReadBody;
+use serde_json::json;
+
+pub(super) fn router() -> axum::Router
+{
+	axum::Router::new()
+		.route("/quarantine",get(list))
+		.route("/quarantine/{id}",get(get_entry).delete(discard))
+		.route("/quarantine/{id}/file",get(get_file))
+		.route("/quarantine/{id}/discard",post(discard))
+		.route("/quarantine/{id}/overwrite",post(overwrite))
+		.route("/quarantine/{id}/accept",post(accept))
+}
+
+async fn list(headers: HeaderMap) -> Result<Response, HttpError>
+{
+	let list:Vec<_> = quarantine::list().await.into_http_error(&headers)?
+		.iter().map(serde_json::Value::from).collect();
+	Ok(Json(list).into_response())
+}
+
+async fn get_entry(headers: HeaderMap,Path(id):Path<String>) -> Result<Response, HttpError>
+{
+	let found = quarantine::lookup(&RecordId::from_quarantine(id.clone())).await
+		.and_then(|q|q.ok_or(IdNotFound {id}))
+		.into_http_error(&headers)?;
+	Ok(Json(serde_json::Value::from(&found)).into_response())
+}
+
+async fn get_file(headers: HeaderMap,Path(id):Path<String>) -> Result<Response, HttpError>
+{
+	let found = quarantine::lookup(&RecordId::from_quarantine(id.clone())).await
+		.and_then(|q|q.ok_or(IdNotFound {id:id.clone()}))
+		.into_http_error(&headers)?;
+	let file= tokio::fs::File::open(found.file.get_path()).await.into_http_error(&headers)?;
+	Ok((
+		StatusCode::OK,
+		[
+			(header::CONTENT_TYPE, "application/dicom"),
+			(header::CONTENT_DISPOSITION, format!(r#"attachment; filename="{}.dcm""#,found.uid).as_str())
+		],
+		AsyncReadBody::new(file)
+	).into_response())
+}
+
+async fn discard(headers: HeaderMap,actor:Actor,Path(id):Path<String>) -> Result<(), HttpError>
+{
+	let id = RecordId::from_quarantine(id);
+	quarantine::discard(&id).await.into_http_error(&headers)?;
+	audit::log(&actor, "quarantine_discard", &id, json!({})).await;
+	Ok(())
+}
+
+fn registered_response(registered:RegisterResult) -> Response
+{
+	let (status,id) = match registered {
+		RegisterResult::Stored(id) => ("Success",id),
+		RegisterResult::AlreadyStored(id) => ("AlreadyStored",id),
+	};
+	(StatusCode::OK, Json(json!({
+		"Status":status,
+		"Path":id.str_path(),
+		"id":id.str_key(),
+	}))).into_response()
+}
+
+async fn overwrite(headers: HeaderMap,actor:Actor,Path(id):Path<String>) -> Result<Response, HttpError>
+{
+	let id = RecordId::from_quarantine(id);
+	let registered = quarantine::overwrite(&id).await.into_http_error(&headers)?;
+	audit::log(&actor, "quarantine_overwrite", registered.id(), json!({"quarantined":id.to_string()})).await;
+	Ok(registered_response(registered))
+}
+
+async fn accept(headers: HeaderMap,actor:Actor,Path(id):Path<String>) -> Result<Response, HttpError>
+{
+	let id = RecordId::from_quarantine(id);
+	let registered = quarantine::accept(&id).await.into_http_error(&headers)?;
+	audit::log(&actor, "quarantine_accept", registered.id(), json!({"quarantined":id.to_string()})).await;
+	Ok(registered_response(registered))
+}
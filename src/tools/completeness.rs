@@ -0,0 +1,143 @@
+use crate::config;
+use crate::db::{RecordId, DB};
+use crate::tools::events::{self, Event};
+use crate::tools::{entries_for_record, mosaic, Context, Result};
+use dicom::dictionary_std::tags;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use surrealdb::types as db_types;
+use tracing::{info, warn};
+
+/// the `state` of series and studies that still get new instances
+pub const RECEIVING:&str = "receiving";
+/// the `state` of series and studies that didn't get new instances within the timeout
+pub const COMPLETE:&str = "complete";
+/// the `state` of series with less instances than expected (or studies with such series)
+pub const INCOMPLETE:&str = "incomplete";
+
+/// series and studies that got new instances and when
+static PENDING:LazyLock<Mutex<HashMap<String,(RecordId,Instant)>>> = LazyLock::new(Default::default);
+static WATCHER:OnceLock<()> = OnceLock::new();
+
+/// Restarts the completeness timeout of the series and study (and marks them as receiving).
+pub(crate) fn touch(series:&RecordId, study:&RecordId)
+{
+	let mut pending = PENDING.lock().unwrap();
+	for id in [series, study] {
+		if pending.insert(id.to_string(), (id.clone(), Instant::now())).is_none() {
+			tokio::spawn(set_state(id.clone(), RECEIVING));
+		}
+	}
+	WATCHER.get_or_init(||{tokio::spawn(watch());});
+}
+
+/// Restarts the timeouts of all series and studies that were still receiving (e.g. when the server was stopped).
+pub async fn resume() -> Result<()>
+{
+	let receiving = DB.query("SELECT VALUE id FROM series, studies WHERE state = $state")
+		.bind(("state",RECEIVING)).await?
+		.take::<Vec<db_types::RecordId>>(0)
+		.context("looking for series and studies still receiving")?;
+	let mut pending = PENDING.lock().unwrap();
+	for id in receiving.into_iter().map(RecordId) {
+		pending.insert(id.to_string(), (id, Instant::now()));
+	}
+	if !pending.is_empty() {
+		WATCHER.get_or_init(||{tokio::spawn(watch());});
+	}
+	Ok(())
+}
+
+async fn set_state(id:RecordId, state:&'static str)
+{
+	let updated = DB.query("UPDATE $rec SET state = $state")
+		.bind(("rec",id.0.clone()))
+		.bind(("state",state)).await;
+	if let Err(e) = updated {
+		warn!("Failed to set state of {id} to {state}: {e}");
+	}
+}
+
+/// finishes series and studies whose timeout expired
+async fn watch()
+{
+	let cfg = &config::get().completeness;
+	let timeout = |id:&RecordId| Duration::from_secs(match id.table.as_str() {
+		"series" => cfg.series_timeout,
+		_ => cfg.study_timeout,
+	});
+	let mut interval = tokio::time::interval(Duration::from_secs(1));
+	loop {
+		interval.tick().await;
+		let mut expired:Vec<_> = {
+			let mut pending = PENDING.lock().unwrap();
+			let keys:Vec<_> = pending.iter()
+				.filter(|(_,(id,last))|last.elapsed() >= timeout(id))
+				.map(|(k,_)|k.clone()).collect();
+			keys.into_iter().filter_map(|k|pending.remove(&k)).map(|(id,_)|id).collect()
+		};
+		// series first, so the study sees their state
+		expired.sort_by_key(|id|id.table.as_str() != "series");
+		for id in expired {
+			let finished = match id.table.as_str() {
+				"series" => finish_series(&id).await,
+				_ => finish_study(&id).await,
+			};
+			if let Err(e) = finished {
+				warn!("Failed to check completeness of {id}: {e}");
+			}
+		}
+	}
+}
+
+/// the number of instances a series should have according to its first instance
+///
+/// For mosaics ImagesInAcquisition counts the slices, which are stored NumberOfImagesInMosaic per instance.
+async fn expected_instances(series:&RecordId) -> Result<Option<usize>>
+{
+	let Some(instance) = entries_for_record(series, "instances").await?.into_iter().next() else {return Ok(None)};
+	let obj = instance.get_file()?.read().await?;
+	let images = obj.element(tags::IMAGES_IN_ACQUISITION).ok()
+		.and_then(|e|e.to_int::<usize>().ok());
+	Ok(images.map(|images|match mosaic::number_of_slices(&obj) {
+		Some(mosaic) => images.div_ceil(mosaic as usize),
+		None => images
+	}))
+}
+
+async fn finish_series(series:&RecordId) -> Result<()>
+{
+	let Some(entry) = crate::db::lookup(series).await? else {return Ok(())}; // deleted in the meantime
+	let mut state = COMPLETE;
+	if config::get().completeness.check_counts {
+		let count = entries_for_record(series, "instances").await?.len();
+		if let Some(expected) = expected_instances(series).await? && count < expected {
+			warn!("{series} has only {count} of {expected} instances, marking it incomplete");
+			state = INCOMPLETE;
+		}
+	}
+	set_state(series.clone(), state).await;
+	info!("{series} is {state}");
+	if state == COMPLETE {
+		let study = entry.get("study").and_then(|s|s.clone().into_record().ok()).map(RecordId);
+		if let Some(study) = study {
+			events::emit(Event::SeriesComplete {series:series.clone(), study});
+		}
+	}
+	Ok(())
+}
+
+async fn finish_study(study:&RecordId) -> Result<()>
+{
+	if crate::db::lookup(study).await?.is_none() {return Ok(())} // deleted in the meantime
+	let incomplete = entries_for_record(study, "series").await?.iter()
+		.any(|s|s.get_string("state").is_some_and(|s|s == INCOMPLETE));
+	let state = if incomplete {INCOMPLETE} else {COMPLETE};
+	set_state(study.clone(), state).await;
+	info!("{study} is {state}");
+	if state == COMPLETE {
+		events::emit(Event::StudyComplete {study:study.clone()});
+	}
+	Ok(())
+}
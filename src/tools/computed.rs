@@ -0,0 +1,227 @@
+use crate::dcm::AttributeSelector;
+use dicom::core::chrono::Datelike;
+use dicom::core::PrimitiveValue;
+use itertools::Itertools;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+
+/// (unit, dimension, factor to the base unit of the dimension)
+const UNITS:&[(&str,&str,f64)] = &[
+	("s","time",1.0), ("ms","time",1e-3), ("us","time",1e-6), ("min","time",60.0), ("h","time",3600.0),
+	("m","length",1.0), ("cm","length",1e-2), ("mm","length",1e-3), ("um","length",1e-6),
+	("Hz","frequency",1.0), ("kHz","frequency",1e3), ("MHz","frequency",1e6),
+	("T","field",1.0), ("mT","field",1e-3), ("G","field",1e-4),
+	("kg","mass",1.0), ("g","mass",1e-3), ("lb","mass",0.45359237),
+	("rad","angle",1.0), ("deg","angle",std::f64::consts::PI/180.0),
+];
+
+static REGEXES:LazyLock<Mutex<HashMap<String,Regex>>> = LazyLock::new(Default::default);
+
+#[derive(Debug,Clone,Copy,Hash,PartialEq,Eq)]
+pub enum Function{
+	/// `age(birth, date)` full years between two dates
+	Age,
+	/// `concat(a, b, ...)` the values as text, missing ones are left out
+	Concat,
+	/// `capture(value, 'regex')` the first capture group (or the whole match if there is none)
+	Capture,
+	/// `convert(value, 'from', 'to')` numbers converted between units of the same dimension
+	Convert,
+}
+
+/// an expression of a computed column (selected as `=<expression>`)
+#[derive(Debug,Clone,Hash,PartialEq,Eq)]
+pub enum Expression{
+	Selector(AttributeSelector),
+	/// `'text'` or a number
+	Literal(String),
+	Call(Function, Vec<Expression>),
+}
+
+impl Function {
+	const ALL:[Function;4] = [Function::Age, Function::Concat, Function::Capture, Function::Convert];
+
+	pub fn name(&self) -> &'static str
+	{
+		match self {
+			Function::Age => "age",
+			Function::Concat => "concat",
+			Function::Capture => "capture",
+			Function::Convert => "convert",
+		}
+	}
+
+	/// checks number of arguments and those that have to be literals
+	fn check(&self, args:&[Expression]) -> Result<(),String>
+	{
+		let literal = |i:usize| match args.get(i) {
+			Some(Expression::Literal(l)) => Ok(l.as_str()),
+			_ => Err(format!("argument {} of {self} must be a literal", i+1))
+		};
+		let count = |n:usize| if args.len() == n {Ok(())} else {Err(format!("{self} takes {n} arguments"))};
+		match self {
+			Function::Age => count(2),
+			Function::Concat => if args.is_empty() {Err(format!("{self} needs arguments"))} else {Ok(())},
+			Function::Capture => {
+				count(2)?;
+				regex(literal(1)?).map(|_|())
+			}
+			Function::Convert => {
+				count(3)?;
+				let (from, to) = (unit(literal(1)?)?, unit(literal(2)?)?);
+				if from.1 != to.1 {
+					return Err(format!("can't convert {} into {}", from.0, to.0))
+				}
+				Ok(())
+			}
+		}
+	}
+
+	fn evaluate(&self, args:Vec<Option<PrimitiveValue>>) -> Option<PrimitiveValue>
+	{
+		let text = |v:&Option<PrimitiveValue>| v.as_ref().map(|v|v.to_str().trim().to_string());
+		match self {
+			Function::Age => {
+				let birth = args[0].as_ref()?.to_date().ok()?.to_naive_date().ok()?;
+				let date = args[1].as_ref()?.to_date().ok()?.to_naive_date().ok()?;
+				let before_birthday = (date.month(), date.day()) < (birth.month(), birth.day());
+				let age = date.year() - birth.year() - i32::from(before_birthday);
+				(age >= 0).then(||PrimitiveValue::from(age))
+			}
+			Function::Concat => {
+				let parts:Vec<_> = args.iter().filter_map(text).collect();
+				(!parts.is_empty()).then(||PrimitiveValue::from(parts.concat()))
+			}
+			Function::Capture => {
+				let regex = regex(&text(&args[1])?).ok()?;
+				let value = text(&args[0])?;
+				let captures = regex.captures(&value)?;
+				let found = captures.get(1).or(captures.get(0))?;
+				Some(PrimitiveValue::from(found.as_str()))
+			}
+			Function::Convert => {
+				let (from, to) = (unit(&text(&args[1])?).ok()?, unit(&text(&args[2])?).ok()?);
+				let values = args[0].as_ref()?.to_multi_float64().ok()?;
+				Some(PrimitiveValue::F64(values.into_iter().map(|v|v * from.2 / to.2).collect()))
+			}
+		}
+	}
+}
+
+fn unit(name:&str) -> Result<&'static (&'static str,&'static str,f64),String>
+{
+	UNITS.iter().find(|u|u.0 == name)
+		.ok_or_else(||format!("{name} is not a known unit (known are {})", UNITS.iter().map(|u|u.0).join(", ")))
+}
+
+fn regex(pattern:&str) -> Result<Regex,String>
+{
+	let mut cache = REGEXES.lock().unwrap();
+	if let Some(found) = cache.get(pattern) {
+		return Ok(found.clone())
+	}
+	let regex = Regex::new(pattern).map_err(|e|e.to_string())?;
+	cache.insert(pattern.to_string(), regex.clone());
+	Ok(regex)
+}
+
+impl Expression {
+	/// `lookup` provides the values of selectors
+	pub fn evaluate(&self, lookup:&mut dyn FnMut(&AttributeSelector) -> Option<PrimitiveValue>) -> Option<PrimitiveValue>
+	{
+		match self {
+			Expression::Selector(s) => lookup(s),
+			Expression::Literal(l) => Some(PrimitiveValue::from(l.as_str())),
+			Expression::Call(function, args) => {
+				let args = args.iter().map(|a|a.evaluate(lookup)).collect();
+				function.evaluate(args)
+			}
+		}
+	}
+}
+
+/// the position of the first "," or ")" that isn't inside brackets or a private creator
+fn token_end(s:&str) -> usize
+{
+	let (mut depth, mut quoted) = (0, false);
+	for (i,c) in s.char_indices() {
+		match c {
+			'"' => quoted = !quoted,
+			_ if quoted => {}
+			'(' | '[' => depth += 1,
+			')' | ']' if depth > 0 => depth -= 1,
+			',' | ')' => return i,
+			_ => {}
+		}
+	}
+	s.len()
+}
+
+/// parses the first expression in `s` and returns the rest
+fn parse(s:&str) -> Result<(Expression,&str),String>
+{
+	let s = s.trim_start();
+	if let Some(rest) = s.strip_prefix('\'') {
+		let (literal, rest) = rest.split_once('\'').ok_or(format!("unterminated text in {s}"))?;
+		return Ok((Expression::Literal(literal.to_string()), rest))
+	}
+	let name_end = s.find(|c:char|!c.is_ascii_alphanumeric() && c != '_').unwrap_or(s.len());
+	if let Some(mut rest) = s[name_end..].strip_prefix('(')
+		&& let Some(function) = Function::ALL.into_iter().find(|f|f.name() == &s[..name_end])
+	{
+		let mut args = vec![];
+		loop {
+			let (arg, remaining) = parse(rest)?;
+			args.push(arg);
+			let remaining = remaining.trim_start();
+			if let Some(remaining) = remaining.strip_prefix(',') {
+				rest = remaining;
+			} else if let Some(remaining) = remaining.strip_prefix(')') {
+				function.check(&args)?;
+				return Ok((Expression::Call(function, args), remaining))
+			} else {
+				return Err(format!("{function} lacks its closing bracket"))
+			}
+		}
+	}
+	let end = token_end(s);
+	let token = s[..end].trim();
+	if token.parse::<f64>().is_ok() {
+		return Ok((Expression::Literal(token.to_string()), &s[end..]))
+	}
+	match AttributeSelector::from_str(token)? {
+		AttributeSelector::Derived(_) | AttributeSelector::Computed(_) => Err(format!("{token} can't be used in an expression")),
+		selector => Ok((Expression::Selector(selector), &s[end..]))
+	}
+}
+
+impl FromStr for Expression {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (expression, rest) = parse(s)?;
+		if !rest.trim().is_empty() {
+			return Err(format!("unexpected {rest} after {expression}"))
+		}
+		Ok(expression)
+	}
+}
+
+impl Display for Function {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl Display for Expression {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Expression::Selector(s) => write!(f, "{s}"),
+			Expression::Literal(l) if l.parse::<f64>().is_ok() => f.write_str(l),
+			Expression::Literal(l) => write!(f, "'{l}'"),
+			Expression::Call(function, args) => write!(f, "{function}({})", args.iter().join(", ")),
+		}
+	}
+}
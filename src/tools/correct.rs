@@ -0,0 +1,414 @@
+use crate::db::audit::Actor;
+use crate::db::{aggregates, audit, find_down_tree, search, Entry, File, LocalSession, RecordId, Session, DB};
+use crate::dcm::{extract, gen_filepath, AttributeSelector, INSTANCE_TAGS, SERIES_TAGS, STUDY_TAGS};
+use crate::tools::Error::{FileIOError, InvalidChange, ParseError};
+use crate::tools::{entries_for_record, extract_from_dicom, Context, Error, Result};
+use dicom::core::dictionary::VirtualVr;
+use dicom::core::value::C;
+use dicom::core::{DataDictionary, DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::{tags, StandardDataDictionary};
+use dicom::object::DefaultDicomObject;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use surrealdb::method::Transaction;
+use surrealdb::types as db_types;
+use surrealdb::types::SurrealValue;
+use surrealdb::Connection;
+use tracing::{info, warn};
+
+/// A dicom object that was rewritten into a staging file, waiting for the database to be updated
+pub(crate) struct Rewritten
+{
+	pub id:RecordId,
+	pub series:RecordId,
+	pub study:RecordId,
+	old:File,
+	new:File,
+	/// where the rewritten file was written to (the new file itself if it gets another name than the old one)
+	staging:PathBuf,
+	columns:[BTreeMap<String, db_types::Value>;3],
+	terms:search::Terms,
+}
+
+/// all configured columns, those not found in the object are NONE (so they are removed when merged)
+pub(crate) fn columns(obj:&DefaultDicomObject, tags:&HashMap<String,Vec<AttributeSelector>>) -> BTreeMap<String, db_types::Value>
+{
+	let mut ret:BTreeMap<_,_> = tags.keys().map(|k|(k.clone(),db_types::Value::None)).collect();
+	ret.extend(extract(obj, tags).into_iter().map(|(k,v)|(k.to_string(),v)));
+	ret
+}
+
+async fn rewrite<F>(entry:&Entry, edit:&F) -> Result<Rewritten> where F:Fn(&mut DefaultDicomObject) -> Result<()>
+{
+	let old = entry.get_file()?;
+	let mut obj = old.read().await?;
+	let pattern = gen_filepath(&obj)?;
+	edit(&mut obj)?;
+
+	let study = RecordId::from_study(extract_from_dicom(&obj, tags::STUDY_INSTANCE_UID)?.as_ref());
+	let series = RecordId::from_series(extract_from_dicom(&obj, tags::SERIES_INSTANCE_UID)?.as_ref());
+	let mut columns = [columns(&obj,&INSTANCE_TAGS), columns(&obj,&SERIES_TAGS), columns(&obj,&STUDY_TAGS)];
+	columns[0].insert("series".into(), series.0.clone().into_value());
+	columns[1].insert("study".into(), study.0.clone().into_value());
+	let terms = search::Terms::new(&obj);
+
+	let obj = Arc::new(obj);
+	let (new, staging) = if old.owned && gen_filepath(&obj)? == pattern {
+		// the file keeps its name (which may have a version number or collision suffix), it is replaced when placed
+		let target = old.get_path();
+		let mut staging = target.clone().into_os_string();
+		staging.push(".rewrite");
+		let staging = PathBuf::from(staging);
+		let mut new = File::new_from_obj_at(obj, staging.clone()).await?.moved_to(target);
+		new.intended = old.intended.clone();
+		(new, staging)
+	} else {
+		// the file gets a new name, so it's written there right away avoiding collisions like new files do
+		let new = File::new_from_obj(obj).await?;
+		let staging = new.get_path();
+		(new, staging)
+	};
+	Ok(Rewritten{id:entry.id().clone(),series,study,old,new,staging,columns,terms})
+}
+
+/// Reads the files of all given instances, edits them and writes the result into staging files.
+///
+/// If anything fails, all staging files written so far are removed again.
+pub(crate) async fn rewrite_all<F>(instances:&[Entry], edit:F) -> Result<Vec<Rewritten>> where F:Fn(&mut DefaultDicomObject) -> Result<()>
+{
+	let mut ret = Vec::with_capacity(instances.len());
+	for entry in instances {
+		match rewrite(entry, &edit).await.context(format!("rewriting {}",entry.id())) {
+			Ok(r) => ret.push(r),
+			Err(e) => {
+				discard(ret).await;
+				return Err(e);
+			}
+		}
+	}
+	Ok(ret)
+}
+
+/// the series and studies the rewritten instances belong to before their records are updated
+async fn former_parents<C>(rewritten:&[Rewritten], transaction:&Transaction<C>) -> Result<Vec<RecordId>> where C:Connection
+{
+	let ids:Vec<_> = rewritten.iter().map(|r|r.id.0.clone()).collect();
+	let parents = transaction.query("LET $series = array::distinct((SELECT VALUE series FROM $ids)); \
+		RETURN array::concat($series, array::distinct((SELECT VALUE study FROM $series WHERE study != NONE)))")
+		.bind(("ids",ids)).await?
+		.take::<Vec<db_types::RecordId>>(1)
+		.context("looking up the series and studies of the rewritten instances")?;
+	Ok(parents.into_iter().map(RecordId).collect())
+}
+
+/// Updates the records of rewritten instances and their series and studies (which are created if necessary).
+///
+/// The aggregates and search terms of the series and studies they belonged to before and after are recalculated.
+pub(crate) async fn update_records<C>(rewritten:&[Rewritten], transaction:&Transaction<C>) -> Result<()> where C:Connection
+{
+	let mut refresh = former_parents(rewritten, transaction).await?;
+	let mut parents = BTreeMap::new();
+	let mut searched = BTreeMap::new();
+	for r in rewritten {
+		let mut content = r.columns[0].clone();
+		content.insert("file".into(), db_types::Value::try_from(r.new.clone())?);
+		transaction.query("UPDATE $rec MERGE $content")
+			.bind(("rec",r.id.0.clone()))
+			.bind(("content",content))
+			.await.context(format!("updating {}",r.id))?;
+		parents.entry(r.series.to_string()).or_insert((&r.series,&r.columns[1]));
+		parents.entry(r.study.to_string()).or_insert((&r.study,&r.columns[2]));
+		searched.entry(r.series.to_string()).or_insert(r);
+	}
+	for (id, content) in parents.into_values() {
+		transaction.query("UPSERT $rec MERGE $content")
+			.bind(("rec",id.0.clone()))
+			.bind(("content",content.clone()))
+			.await.context(format!("updating {id}"))?;
+		if !refresh.contains(id) {
+			refresh.push(id.clone());
+		}
+	}
+	// series before studies as the studies may aggregate series aggregates
+	refresh.sort_by_key(|id|id.table.as_str() == "studies");
+	for id in &refresh {
+		aggregates::update(id, transaction).await?;
+	}
+	for r in searched.into_values() {
+		search::replace(&r.terms, &r.series, &r.study, transaction).await?;
+	}
+	for study in refresh.iter().filter(|id|id.table.as_str() == "studies") {
+		search::reindex(study, transaction).await?;
+	}
+	Ok(())
+}
+
+/// a staging file moved into place before the commit, and the replaced file it had to move aside
+struct Placed
+{
+	staging:PathBuf,
+	target:PathBuf,
+	aside:Option<PathBuf>,
+}
+
+/// moves the staging file into place, failing if anything but the file it replaces is already there
+async fn place_one(r:&Rewritten) -> Result<Placed>
+{
+	let target = r.new.get_path();
+	// written at its new name already
+	if r.staging == target {
+		return Ok(Placed{staging:r.staging.clone(),target,aside:None})
+	}
+	let mut aside = None;
+	if tokio::fs::try_exists(&target).await.unwrap_or(true) {
+		if !(r.old.owned && r.old.get_path() == target) {
+			return Err(FileIOError {
+				inner:std::io::Error::new(ErrorKind::AlreadyExists, "the target of the rewritten file already exists"),
+				path:target
+			})
+		}
+		let mut replaced = target.clone().into_os_string();
+		replaced.push(".replaced");
+		let replaced = PathBuf::from(replaced);
+		tokio::fs::rename(&target, &replaced).await
+			.context(format!("moving {} aside",target.display()))?;
+		aside = Some(replaced);
+	}
+	if let Err(e) = tokio::fs::rename(&r.staging, &target).await {
+		if let Some(aside) = &aside && let Err(e) = tokio::fs::rename(aside, &target).await {
+			warn!("Failed to restore {}: {e}",target.display());
+		}
+		return Err(e).context(format!("moving {} to {}",r.staging.display(),target.display()))
+	}
+	Ok(Placed{staging:r.staging.clone(),target,aside})
+}
+
+/// moves all staging files into place (undoing everything if one of them fails)
+async fn place(rewritten:&[Rewritten]) -> Result<Vec<Placed>>
+{
+	let mut placed = Vec::with_capacity(rewritten.len());
+	for r in rewritten {
+		match place_one(r).await {
+			Ok(p) => placed.push(p),
+			Err(e) => {
+				unplace(placed).await;
+				return Err(e);
+			}
+		}
+	}
+	Ok(placed)
+}
+
+/// moves placed files back into staging and the replaced files back into place
+async fn unplace(placed:Vec<Placed>)
+{
+	for p in placed.into_iter().rev().filter(|p|p.target != p.staging) {
+		if let Err(e) = tokio::fs::rename(&p.target, &p.staging).await {
+			warn!("Failed to move {} back to {}: {e}",p.target.display(),p.staging.display());
+		}
+		if let Some(aside) = p.aside && let Err(e) = tokio::fs::rename(&aside, &p.target).await {
+			warn!("Failed to restore {}: {e}",p.target.display());
+		}
+	}
+}
+
+/// Updates the database for the rewritten objects and records `action` on `target` in the audit table (all in one transaction).
+///
+/// The staging files are moved into place before the transaction is committed. If anything fails the moves are
+/// undone and the staging files are removed, otherwise the replaced files are removed.
+/// returns: the ids of all rewritten instances
+pub(crate) async fn apply(rewritten:Vec<Rewritten>, actor:&Actor, action:&str, target:&RecordId, diff:db_types::Value) -> Result<Vec<RecordId>>
+{
+	let mut session = LocalSession::create(&DB,1);
+	let transaction = session.begin().await?;
+	let prepared = match update_records(&rewritten, &transaction).await
+		.and(audit::record(&transaction, actor, action, target, diff).await)
+	{
+		Ok(entry) => place(&rewritten).await.map(|placed|(entry,placed)),
+		Err(e) => Err(e),
+	};
+	let updated = match prepared {
+		Ok((entry,placed)) => match transaction.commit().await {
+			Ok(_) => Ok((entry,placed)),
+			Err(e) => {
+				unplace(placed).await;
+				Err(e.into())
+			}
+		},
+		Err(e) => transaction.cancel().await.map_err(Error::from).and(Err(e)),
+	};
+	let placed = match updated {
+		Ok((entry,placed)) => {
+			audit::export(&entry).await;
+			placed
+		}
+		Err(e) => {
+			discard(rewritten).await;
+			return Err(e);
+		}
+	};
+	let ids = rewritten.iter().map(|r|r.id.clone()).collect();
+	finish(rewritten, placed).await?;
+	Ok(ids)
+}
+
+/// removes the staging files after a failure
+pub(crate) async fn discard(rewritten:Vec<Rewritten>)
+{
+	for r in rewritten {
+		if let Err(e) = tokio::fs::remove_file(&r.staging).await {
+			warn!("Failed to remove staging file {}: {e}",r.staging.display());
+		}
+	}
+}
+
+/// removes the replaced files (if owned) after the commit
+async fn finish(rewritten:Vec<Rewritten>, placed:Vec<Placed>) -> Result<()>
+{
+	for aside in placed.into_iter().filter_map(|p|p.aside) {
+		tokio::fs::remove_file(&aside).await
+			.context(format!("deleting {}",aside.display()))?;
+	}
+	for r in rewritten {
+		if r.old.owned && r.old.get_path() != r.new.get_path() {
+			r.old.remove().await?;
+		}
+	}
+	Ok(())
+}
+
+fn parse<T>(tag:Tag, values:&str) -> Result<C<T>> where T:FromStr, T::Err:std::error::Error + Send + Sync + 'static
+{
+	values.split('\\')
+		.map(|v|T::from_str(v.trim()).map_err(|e|ParseError{to_parse:v.to_string(),source:Box::new(e)}))
+		.collect::<Result<C<T>>>()
+		.context(format!("parsing new value for {tag}"))
+}
+
+/// creates the value for a string given for a correction (multiple values are separated by "\")
+fn parse_value(tag:Tag, vr:VR, value:&str) -> Result<PrimitiveValue>
+{
+	Ok(match vr {
+		VR::AE | VR::AS | VR::CS | VR::DA | VR::DS | VR::DT | VR::IS | VR::LO | VR::PN | VR::SH | VR::TM | VR::UI =>
+			PrimitiveValue::Strs(value.split('\\').map(str::to_string).collect()),
+		VR::LT | VR::ST | VR::UT | VR::UC | VR::UR => PrimitiveValue::Str(value.to_string()),
+		VR::US => PrimitiveValue::U16(parse(tag,value)?),
+		VR::SS => PrimitiveValue::I16(parse(tag,value)?),
+		VR::UL => PrimitiveValue::U32(parse(tag,value)?),
+		VR::SL => PrimitiveValue::I32(parse(tag,value)?),
+		VR::UV => PrimitiveValue::U64(parse(tag,value)?),
+		VR::SV => PrimitiveValue::I64(parse(tag,value)?),
+		VR::FL => PrimitiveValue::F32(parse(tag,value)?),
+		VR::FD => PrimitiveValue::F64(parse(tag,value)?),
+		_ => return Err(InvalidChange {tag:tag.to_string(),reason:format!("values of VR {} cannot be set",vr.to_string())})
+	})
+}
+
+fn tag_name(tag:Tag) -> String
+{
+	StandardDataDictionary::default().by_tag(tag)
+		.map_or(tag.to_string(),|e|e.alias.to_string())
+}
+
+/// Sets (or removes if value is None) the given tag in the object.
+///
+/// The VR is taken from an already existing element or from the standard dictionary.
+pub(crate) fn set_tag(obj:&mut DefaultDicomObject, tag:Tag, value:Option<&str>) -> Result<()>
+{
+	if let Some(value) = value {
+		let vr = match obj.element(tag).map(|e|e.vr()).ok() {
+			Some(vr) => vr,
+			None => match StandardDataDictionary::default().by_tag(tag).map(|e|e.vr) {
+				Some(VirtualVr::Exact(vr)) => vr,
+				_ => return Err(InvalidChange {tag:tag.to_string(),reason:"its VR is not known".into()})
+			}
+		};
+		obj.put(DataElement::new(tag,vr,parse_value(tag,vr,value)?));
+	} else {
+		obj.remove_element(tag);
+	}
+	Ok(())
+}
+
+/// standard tags of the study (besides those of the patient) and of the series that have to be the same in all their instances
+const STUDY_LEVEL:[Tag;6] = [
+	tags::STUDY_DATE, tags::STUDY_TIME, tags::STUDY_ID, tags::ACCESSION_NUMBER, tags::STUDY_DESCRIPTION, tags::REFERRING_PHYSICIAN_NAME
+];
+const SERIES_LEVEL:[Tag;7] = [
+	tags::SERIES_DATE, tags::SERIES_TIME, tags::SERIES_NUMBER, tags::SERIES_DESCRIPTION, tags::MODALITY, tags::PROTOCOL_NAME, tags::BODY_PART_EXAMINED
+];
+
+fn in_columns(tag:Tag, columns:&HashMap<String,Vec<AttributeSelector>>) -> bool
+{
+	columns.values().flatten().any(|s|*s == AttributeSelector::from(tag))
+}
+
+/// the study or series of the entry if the changes affect tags of the study or series, so all their instances get corrected
+async fn scope(id:&RecordId, changes:&[(Tag,Option<String>)]) -> Result<RecordId>
+{
+	let study_level = changes.iter().any(|(tag,_)|tag.group() == 0x0010 || STUDY_LEVEL.contains(tag) || in_columns(*tag, &STUDY_TAGS));
+	let series_level = changes.iter().any(|(tag,_)|SERIES_LEVEL.contains(tag) || in_columns(*tag, &SERIES_TAGS));
+	let table = match (study_level, series_level) {
+		(true, _) => "studies",
+		(false, true) => "series",
+		_ => return Ok(id.clone())
+	};
+	Ok(find_down_tree(id).await?.into_iter()
+		.find(|p|p.table.as_str() == table)
+		.unwrap_or_else(||id.clone()))
+}
+
+/// Corrects tags in all instances of an entry.
+///
+/// Changes of tags of the patient, study or series are applied to all instances of the study or series (even if `id` is only part of it).
+/// The files of all instances are rewritten (owned copies are created for files that are not owned),
+/// their database records, series and study are updated in one transaction which also adds a
+/// "correct" entry in the audit table.
+///
+/// ## Arguments
+///
+/// * `id`: the instance, series or study to be corrected
+/// * `changes`: tags to be set to the given value (multiple values are separated by "\") or to be removed if None
+/// * `actor`: who requested the correction (for the audit)
+///
+/// returns: the ids of all corrected instances
+pub async fn correct(id:&RecordId, changes:Vec<(Tag,Option<String>)>, actor:&Actor) -> Result<Vec<RecordId>>
+{
+	for (tag,_) in &changes {
+		if [tags::STUDY_INSTANCE_UID, tags::SERIES_INSTANCE_UID, tags::SOP_INSTANCE_UID].contains(tag) {
+			return Err(InvalidChange {tag:tag_name(*tag),reason:"UIDs cannot be corrected, move the entry instead".into()})
+		}
+	}
+	let scope = scope(id, &changes).await?;
+	if scope != *id {
+		info!("correcting all instances of {scope} as the changes apply to all of them");
+	}
+	let instances = entries_for_record(&scope, "instances").await?;
+	let before = Mutex::new(BTreeMap::<Tag,BTreeSet<String>>::new());
+	let rewritten = rewrite_all(&instances, |obj| {
+		for (tag,value) in &changes {
+			if let Some(old) = obj.element(*tag).ok().and_then(|e|e.to_str().ok()) {
+				before.lock().unwrap().entry(*tag).or_default().insert(old.to_string());
+			}
+			set_tag(obj, *tag, value.as_deref())?;
+		}
+		Ok(())
+	}).await?;
+
+	let before = before.into_inner().unwrap();
+	let diff:serde_json::Map<_,_> = changes.iter()
+		.map(|(tag,value)|(tag_name(*tag),json!({
+			"before":before.get(tag).map(|b|b.iter().collect::<Vec<_>>()).unwrap_or_default(),
+			"after":value,
+		})))
+		.collect();
+	let diff = crate::tools::conv::json_to_value(diff.into());
+
+	let ids = apply(rewritten, actor, "correct", id, diff).await.context(format!("correcting {id}"))?;
+	info!("corrected {} instances of {id}", ids.len());
+	Ok(ids)
+}
@@ -1,86 +1,200 @@
 use super::error::Result;
+use dicom::core::value::C;
+use dicom::core::{PrimitiveValue, Tag};
 use dicom::object::mem::InMemElement;
-use std::io::{Cursor, Read};
+use dicom::object::InMemDicomObject;
+use serde_json::json;
 use std::str::FromStr;
-use dicom::core::PrimitiveValue;
-use dicom::core::value::C;
-use tokio_util::bytes::Buf;
-use tracing::{debug, trace};
+use std::sync::LazyLock;
+use crate::dcm::{AttributeSelector, PathStep, PathTag};
 use crate::tools::Error::{DicomError, ParseError};
 
-pub fn extract_csa(el:&InMemElement, name:&str) -> Result<Option<PrimitiveValue>>{
-	let bytes = el.to_bytes().map_err(|e|DicomError(e.into()))?;
-	let mut cursor= Cursor::new(bytes);
-	cursor.advance(0x10);
-	while cursor.remaining() > 0x40 {
-		if let Some(entry) = extract_csaentry(&mut cursor, name)?
-		{
-			return Ok(Some(entry));
-		}
+/// private creator of the CSA headers
+pub const CREATOR:&str = "SIEMENS CSA HEADER";
+/// usual location of the CSA Image Header Info of Siemens (if [CREATOR] is in (0029,0010))
+pub const IMAGE_HEADER:Tag = Tag(0x0029,0x1010);
+/// usual location of the CSA Series Header Info of Siemens (if [CREATOR] is in (0029,0010))
+pub const SERIES_HEADER:Tag = Tag(0x0029,0x1020);
+
+fn selector(offset:u8) -> AttributeSelector
+{
+	AttributeSelector::Private(vec![PathStep{tag:PathTag::Private {group:0x0029, creator:CREATOR.into(), offset}, item:None}])
+}
+/// CSA Image Header Info wherever its creator put it
+pub static IMAGE_SELECTOR:LazyLock<AttributeSelector> = LazyLock::new(||selector(0x10));
+/// CSA Series Header Info wherever its creator put it
+pub static SERIES_SELECTOR:LazyLock<AttributeSelector> = LazyLock::new(||selector(0x20));
+
+/// one element of a CSA header
+#[derive(Debug,Clone,PartialEq)]
+pub struct CsaElement
+{
+	pub name:String,
+	pub vr:String,
+	pub vm:i32,
+	/// the non-empty items as strings
+	pub values:Vec<String>,
+}
+
+/// a parsed CSA1 or CSA2 header
+#[derive(Debug,Clone,PartialEq)]
+pub struct CsaHeader
+{
+	/// 1 or 2
+	pub version:u8,
+	pub elements:Vec<CsaElement>,
+}
+
+fn invalid(reason:String) -> crate::tools::Error
+{
+	ParseError {to_parse:"CSA header".into(), source:reason.into()}
+}
+
+/// bounds checked little endian reading
+struct Reader<'a>
+{
+	data:&'a [u8],
+	pos:usize,
+}
+
+impl<'a> Reader<'a>
+{
+	fn bytes(&mut self, len:usize) -> Result<&'a [u8]>
+	{
+		let end = self.pos.checked_add(len).filter(|end|*end <= self.data.len())
+			.ok_or_else(||invalid(format!("{len} bytes at {} exceed its length of {}",self.pos,self.data.len())))?;
+		let bytes = &self.data[self.pos..end];
+		self.pos = end;
+		Ok(bytes)
+	}
+	fn i32(&mut self) -> Result<i32>
+	{
+		Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+	}
+	fn u32(&mut self) -> Result<u32>
+	{
+		Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+	}
+	/// zero terminated string in a field of `len` bytes
+	fn string(&mut self, len:usize) -> Result<String>
+	{
+		let bytes = self.bytes(len)?;
+		let bytes = &bytes[..bytes.iter().position(|&b|b == 0).unwrap_or(bytes.len())];
+		Ok(String::from_utf8_lossy(bytes).trim().to_string())
 	}
-	Ok(None)
 }
 
-fn extract_csaentry<T:AsRef<[u8]>>(cursor:&mut Cursor<T>, lookup:&str) -> Result<Option<PrimitiveValue>>{
-	let name = get_string(cursor, 0x40).expect("Invalid Name in CSA");
-	let _vm = cursor.get_u32_le();
-	let vr = get_string(cursor, 0x4).expect("Invalid Name in CSA");
-	let _syngodt = cursor.get_i32_le();
-	let nitems = cursor.get_i32_le();
-	let _77 = cursor.get_i32_le();
-	let skip = name != lookup;
-
-	if nitems == 0 { return Ok(None); }
-	for _n in 0..nitems {
-		let mut len = cursor.get_i32_le();
-		cursor.advance(12); //whatever
-		if len == 0 {continue}
-		len = ((len+3) / 4) * 4; // padding
-		if !skip {
-			let insert = get_string(cursor, len as usize).expect("Invalid Value in CSA");
-			let words = insert.split_ascii_whitespace().collect::<Vec<_>>();
-			if words.is_empty() {
-				debug!("Skipping empty string for CSA entry ");
-				continue
-			} else {
-				let val = parse_csa_values(words, vr.as_str())?;
-				debug!("Found scalar entry {name}:{val} in CSA header");
-				return Ok(Some(val));
+impl CsaHeader
+{
+	pub fn parse(data:&[u8]) -> Result<CsaHeader>
+	{
+		let mut reader = Reader{data, pos:0};
+		let version = if data.starts_with(b"SV10") {
+			reader.bytes(8)?; // "SV10" and 4 unused bytes
+			2
+		} else {1};
+		let count = reader.u32()?;
+		if count == 0 || count > 128 {
+			return Err(invalid(format!("{count} is not a plausible number of elements")));
+		}
+		reader.u32()?; // unused (77)
+		let mut elements = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let name = reader.string(64)?;
+			let vm = reader.i32()?;
+			let vr = reader.string(4)?;
+			reader.i32()?; // SyngoDT
+			let items = reader.i32()?;
+			reader.i32()?; // unused (77 or 205)
+			if !(0..=512).contains(&items) {
+				return Err(invalid(format!("{items} is not a plausible number of items for {name}")));
 			}
-		} else {
-			trace!("Skipping item {_n} in {name} as its not what we're looking for");
-			cursor.advance(len as usize);
+			let mut values = vec![];
+			for item in 0..items {
+				let lengths = [reader.i32()?, reader.i32()?, reader.i32()?, reader.i32()?];
+				// CSA1 stores the length offset by the number of elements
+				let len = if version == 1 {lengths[0] - count as i32} else {lengths[1]};
+				let len = usize::try_from(len).map_err(|_|invalid(format!("item {item} of {name} has a negative length")))?;
+				let value = reader.string(len)?;
+				reader.bytes(len.next_multiple_of(4) - len)?; // padding
+				// items beyond the VM are left overs
+				if (vm == 0 || item < vm) && !value.is_empty() {
+					values.push(value);
+				}
+			}
+			elements.push(CsaElement{name, vr, vm, values});
 		}
+		Ok(CsaHeader{version, elements})
+	}
+
+	pub fn from_element(el:&InMemElement) -> Result<CsaHeader>
+	{
+		let bytes = el.to_bytes().map_err(|e|DicomError(e.into()))?;
+		Self::parse(&bytes)
+	}
+
+	pub fn get(&self, name:&str) -> Option<&CsaElement>
+	{
+		self.elements.iter().find(|e|e.name == name)
+	}
+
+	/// the typed value of the element (None if it doesn't exist or has no values)
+	pub fn value(&self, name:&str) -> Result<Option<PrimitiveValue>>
+	{
+		self.get(name).map_or(Ok(None), CsaElement::value)
+	}
+}
+
+impl CsaElement
+{
+	/// the values converted according to the VR (None if there are none)
+	pub fn value(&self) -> Result<Option<PrimitiveValue>>
+	{
+		if self.values.is_empty() {return Ok(None)}
+		let values = self.values.iter().map(String::as_str);
+		Ok(Some(match self.vr.as_str() {
+			"IS"|"SL" => PrimitiveValue::I32(convert_vec(values,i32::from_str)?),
+			"UL" => PrimitiveValue::U32(convert_vec(values,u32::from_str)?),
+			"OD"|"DS"|"FD"|"FL" => PrimitiveValue::F64(convert_vec(values,f64::from_str)?),
+			"US"|"OW" => PrimitiveValue::U16(convert_vec(values,u16::from_str)?),
+			"SS" => PrimitiveValue::I16(convert_vec(values,i16::from_str)?),
+			_ => PrimitiveValue::Strs(values.map(str::to_string).collect())
+		}))
 	}
-	Ok(None)
 }
 
-fn convert_vec<T,P,E>(words:Vec<&str>, proc:P) -> Result<C<T>>
+fn convert_vec<'a,T,P,E>(words:impl Iterator<Item=&'a str>, proc:P) -> Result<C<T>>
 	where
 		E:std::error::Error + Send + Sync + 'static,
 		P:Fn(&str) -> std::result::Result<T,E>,
 {
-	words.into_iter()
-		.map(|w|proc(w).map_err(|e|ParseError { to_parse:w.to_string() , source: Box::new(e) }))
+	words
+		.map(|w|proc(w.trim()).map_err(|e|ParseError { to_parse:w.to_string() , source: Box::new(e) }))
 		.collect::<std::result::Result<Vec<_>,_>>()
 		.map(|v|v.into())
 }
-fn parse_csa_values(values: Vec<&str>, vr: &str) -> Result<PrimitiveValue> {
-	Ok(match vr {
-		"IS"|"SL" => PrimitiveValue::I32(convert_vec(values,i32::from_str)?),
-		"UL" => PrimitiveValue::U32(convert_vec(values,u32::from_str)?),
-		"CS"|"LO"|"SH"|"UN"|"ST"|"UT"|"LT"|"UR" => PrimitiveValue::Strs(convert_vec(values,String::from_str)?),
-		"OD"|"DS"|"FD" => PrimitiveValue::F64(convert_vec(values,f64::from_str)?),
-		"US"|"OW" => PrimitiveValue::U16(convert_vec(values,u16::from_str)?),
-		"SS" => PrimitiveValue::I16(convert_vec(values,i16::from_str)?),
-		_ => panic!("Don't know how to parse CSA entry")
-	})
+
+impl From<&CsaHeader> for serde_json::Value
+{
+	fn from(header: &CsaHeader) -> Self {
+		let elements:Vec<_> = header.elements.iter()
+			.map(|e|json!({"name":e.name,"vr":e.vr,"vm":e.vm,"values":e.values}))
+			.collect();
+		json!({"version":header.version,"elements":elements})
+	}
 }
 
-fn get_string<T:AsRef<[u8]>>(cursor:&mut Cursor<T>, size:usize) -> Result<String>{
-	let mut name = Vec::new();
-	name.resize(size, 0);
-	cursor.read(&mut name)?;
-	name.truncate(name.iter().position(|&b| b==0).expect("Invalid String in CSA"));
-	Ok(String::from_utf8(name).expect("Invalid String in CSA"))
-}
\ No newline at end of file
+/// the CSA image and series headers of the object (if it has them)
+pub fn headers(obj:&InMemDicomObject) -> Result<Vec<(&'static str, CsaHeader)>>
+{
+	[("image",&*IMAGE_SELECTOR), ("series",&*SERIES_SELECTOR)].into_iter()
+		.filter_map(|(name,selector)|selector.element(obj).map(|e|(name,e)))
+		.map(|(name,e)|CsaHeader::from_element(e).map(|h|(name,h)))
+		.collect()
+}
+
+/// Parses the CSA header in `el` and gets the value of `name` (better use [CsaHeader] for more than one value).
+pub fn extract_csa(el:&InMemElement, name:&str) -> Result<Option<PrimitiveValue>>
+{
+	CsaHeader::from_element(el)?.value(name)
+}
@@ -0,0 +1,56 @@
+use crate::tools::csa::CsaHeader;
+use crate::tools::Result;
+use dicom::core::PrimitiveValue;
+use dicom::object::mem::InMemElement;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, RwLock};
+
+/// a parsed vendor header
+pub trait DecodedHeader: Send + Sync
+{
+	/// the value of the named element (None if it doesn't exist or is empty)
+	fn value(&self, element:&str) -> Result<Option<PrimitiveValue>>;
+}
+
+/// decodes vendor headers stored in (private) elements, selected as `<base>#<NAME>.<element>`
+pub trait HeaderDecoder: Send + Sync
+{
+	/// parses the header, the result is used for all elements selected from it
+	fn decode(&self, el:&InMemElement) -> Result<Box<dyn DecodedHeader>>;
+}
+
+impl DecodedHeader for CsaHeader
+{
+	fn value(&self, element:&str) -> Result<Option<PrimitiveValue>> {CsaHeader::value(self, element)}
+}
+
+/// Siemens CSA1 / CSA2 headers
+pub struct CsaDecoder;
+
+impl HeaderDecoder for CsaDecoder
+{
+	fn decode(&self, el:&InMemElement) -> Result<Box<dyn DecodedHeader>>
+	{
+		Ok(Box::new(CsaHeader::from_element(el)?))
+	}
+}
+
+static DECODERS:LazyLock<RwLock<HashMap<String,Arc<dyn HeaderDecoder>>>> = LazyLock::new(||
+	RwLock::new(HashMap::from([("CSA".to_string(), Arc::new(CsaDecoder) as Arc<dyn HeaderDecoder>)]))
+);
+
+/// Makes `decoder` available as `#<name>.` in selectors (must happen before the config is loaded).
+pub fn register(name:&str, decoder:Arc<dyn HeaderDecoder>)
+{
+	DECODERS.write().unwrap().insert(name.to_string(), decoder);
+}
+
+pub fn get(name:&str) -> Option<Arc<dyn HeaderDecoder>>
+{
+	DECODERS.read().unwrap().get(name).cloned()
+}
+
+pub fn is_known(name:&str) -> bool
+{
+	DECODERS.read().unwrap().contains_key(name)
+}
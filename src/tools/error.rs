@@ -94,8 +94,8 @@ pub enum Error
 	#[error(transparent)]
 	IoError(#[from] std::io::Error),
 
-	#[error("string formatting error {0}")]
-	StrFmtError(#[from] strfmt::FmtError),
+	#[error("TLS error {0}")]
+	TlsError(#[from] tokio_rustls::rustls::Error),
 
 	#[error("filename {name} is invalid")]
 	InvalidFilename{name:std::path::PathBuf},
@@ -151,6 +151,22 @@ pub enum Error
 	FieldConflict{fields:String,id:RecordId},
 	#[error("Entry {existing_id} already exists with different data")]
 	Md5Conflict {existing_md5:String, my_md5:String, existing_id:RecordId},
+	#[error("{tag} cannot be changed ({reason})")]
+	InvalidChange{tag:String, reason:String},
+	#[error("Invalid filter {name} ({reason})")]
+	InvalidFilter{name:String, reason:String},
+	#[error("{path} is not allowed for import ({reason})")]
+	PathNotAllowed{path:PathBuf, reason:String},
+	#[error("Authentication failed ({reason})")]
+	Unauthorized{reason:String},
+	#[error("Role {required} is needed for this request")]
+	Forbidden{required:String},
+	#[error("Object was quarantined as {id} ({reason})")]
+	Quarantined{id:RecordId, reason:Box<Error>},
+	#[error("Forwarding to {destination} failed ({reason})")]
+	ForwardFailed{destination:String, reason:String},
+	#[error("Hook {hook} failed ({reason})")]
+	HookFailed{hook:String, reason:String},
 	#[error("Python error {0}")]
 	PythonErr(#[from]PyErr),
 }
@@ -0,0 +1,105 @@
+use crate::config::WebhookCfg;
+use crate::db::RecordId;
+use crate::config;
+use crate::tools::{hooks, routing};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// Events emitted on changes of the stored data.
+#[derive(Clone,Debug)]
+pub enum Event
+{
+	InstanceStored{instance:RecordId, series:RecordId, study:RecordId},
+	/// the series was marked complete (see [crate::tools::completeness])
+	SeriesComplete{series:RecordId, study:RecordId},
+	/// the study was marked complete (see [crate::tools::completeness])
+	StudyComplete{study:RecordId},
+	StudyDeleted{study:RecordId},
+}
+
+impl Event
+{
+	pub fn name(&self) -> &'static str
+	{
+		match self {
+			Event::InstanceStored {..} => "instance_stored",
+			Event::SeriesComplete {..} => "series_complete",
+			Event::StudyComplete {..} => "study_complete",
+			Event::StudyDeleted {..} => "study_deleted",
+		}
+	}
+}
+
+impl From<&Event> for serde_json::Value
+{
+	fn from(event: &Event) -> Self {
+		let ids = match event {
+			Event::InstanceStored {instance,series,study} => json!({"instance":instance.str_path(),"series":series.str_path(),"study":study.str_path()}),
+			Event::SeriesComplete {series,study} => json!({"series":series.str_path(),"study":study.str_path()}),
+			Event::StudyComplete {study} | Event::StudyDeleted {study} => json!({"study":study.str_path()}),
+		};
+		json!({"event":event.name(),"timestamp":chrono::Utc::now().to_rfc3339(),"ids":ids})
+	}
+}
+
+static CHANNEL:LazyLock<broadcast::Sender<Event>> = LazyLock::new(||broadcast::channel(1024).0);
+pub(crate) static CLIENT:LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
+
+/// Receives all events emitted from now on.
+pub fn subscribe() -> broadcast::Receiver<Event>
+{
+	CHANNEL.subscribe()
+}
+
+/// Sends the event to the subscribers and the configured webhooks.
+pub fn emit(event:Event)
+{
+	debug!("emitting {event:?}");
+	routing::route(&event);
+	hooks::run(&event);
+	let body = serde_json::Value::from(&event).to_string();
+	for hook in config::get().events.webhooks.iter()
+		.filter(|h|h.events.as_ref().is_none_or(|e|e.iter().any(|e|e == event.name())))
+	{
+		tokio::spawn(deliver(hook, event.name(), body.clone()));
+	}
+	// no subscribers is fine
+	let _ = CHANNEL.send(event);
+}
+
+/// the value of the `X-Rudicom-Signature` header, the HMAC-SHA256 of the body with the secret
+pub(crate) fn signature(secret:&str, body:&str) -> String
+{
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take keys of any size");
+	mac.update(body.as_bytes());
+	format!("sha256={}",hex::encode(mac.finalize().into_bytes()))
+}
+
+/// posts the body to the webhook, retrying with exponential backoff
+async fn deliver(hook:&'static WebhookCfg, event:&'static str, body:String)
+{
+	let mut delay = Duration::from_secs(1);
+	for attempt in 0..=hook.retries {
+		let mut request = CLIENT.post(&hook.url)
+			.header(reqwest::header::CONTENT_TYPE, "application/json")
+			.header("X-Rudicom-Event", event)
+			.body(body.clone());
+		if let Some(secret) = &hook.secret {
+			request = request.header("X-Rudicom-Signature", signature(secret, &body));
+		}
+		match request.send().await.and_then(|r|r.error_for_status()) {
+			Ok(_) => return,
+			Err(e) => warn!("delivering {event} to {} failed (attempt {}): {e}", hook.url, attempt + 1),
+		}
+		if attempt < hook.retries {
+			tokio::time::sleep(delay).await;
+			delay *= 2;
+		}
+	}
+	warn!("giving up delivering {event} to {}", hook.url);
+}
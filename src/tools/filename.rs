@@ -0,0 +1,304 @@
+use crate::config::{self, Sanitize};
+use crate::dcm::AttributeSelector;
+use dicom::core::chrono::format::{Item, StrftimeItems};
+use dicom::core::value::PreciseDateTime;
+use dicom::core::PrimitiveValue;
+use dicom::dictionary_std::tags;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+/// names Windows doesn't allow as filenames (with any extension)
+const RESERVED:&[&str] = &[
+	"CON","PRN","AUX","NUL",
+	"COM1","COM2","COM3","COM4","COM5","COM6","COM7","COM8","COM9",
+	"LPT1","LPT2","LPT3","LPT4","LPT5","LPT6","LPT7","LPT8","LPT9",
+];
+
+/// where the value of a field comes from
+#[derive(Debug,Clone,PartialEq)]
+pub enum Source{
+	Selector(AttributeSelector),
+	/// a configured (e.g. computed) column
+	Column(String),
+	/// `'text'`
+	Literal(String),
+}
+
+/// applied to the value of a field in the given order
+#[derive(Debug,Clone,PartialEq)]
+pub enum Modifier{
+	/// `<n` or `n` keeps the first n characters
+	Left(usize),
+	/// `>n` keeps the last n characters
+	Right(usize),
+	/// `0n` pads integers with zeros to n digits
+	ZeroPad(usize),
+	/// `date=<strftime format>`
+	Date(String),
+	/// `time=<strftime format>`
+	Time(String),
+	/// `datetime=<strftime format>`
+	DateTime(String),
+	/// hex md5 hash
+	Md5,
+	/// hex sha256 hash
+	Sha256,
+}
+
+/// `{source|fallback|...:modifier:...}`
+#[derive(Debug,Clone,PartialEq)]
+pub struct Field{
+	pub sources:Vec<Source>,
+	pub modifiers:Vec<Modifier>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Part{
+	Text(String),
+	Field(Field),
+}
+
+/// a parsed `filename_pattern`
+#[derive(Debug,Clone,PartialEq)]
+pub struct Pattern{
+	pub parts:Vec<Part>,
+}
+
+/// positions of `sep` in `s` that are not inside quotes or brackets
+fn top_level(s:&str, sep:char) -> impl Iterator<Item=usize> + '_
+{
+	let (mut depth, mut quote) = (0, None);
+	s.char_indices().filter(move |(_,c)|{
+		match (quote, *c) {
+			(Some(q), c) => {if c == q {quote = None}}
+			(None, '"' | '\'') => quote = Some(*c),
+			(None, '(' | '[') => depth += 1,
+			(None, ')' | ']') if depth > 0 => depth -= 1,
+			(None, c) if c == sep && depth == 0 => return true,
+			_ => {}
+		}
+		false
+	}).map(|(i,_)|i)
+}
+
+fn split_top(s:&str, sep:char) -> Vec<&str>
+{
+	let mut start = 0;
+	let mut parts = vec![];
+	for i in top_level(s, sep) {
+		parts.push(&s[start..i]);
+		start = i + sep.len_utf8();
+	}
+	parts.push(&s[start..]);
+	parts
+}
+
+fn unquote(s:&str) -> &str
+{
+	let s = s.trim();
+	s.strip_prefix('\'').and_then(|s|s.strip_suffix('\'')).unwrap_or(s)
+}
+
+impl Source {
+	fn parse(s:&str, column:&dyn Fn(&str) -> bool) -> Result<Source,String>
+	{
+		let s = s.trim();
+		if s.len() > 1 && s.starts_with('\'') && s.ends_with('\'') {
+			return Ok(Source::Literal(unquote(s).to_string()))
+		}
+		match AttributeSelector::from_str(s) {
+			Ok(AttributeSelector::Derived(_)) => Err(format!("{s} can't be used in filenames")),
+			Ok(selector) => Ok(Source::Selector(selector)),
+			Err(_) if column(s) => Ok(Source::Column(s.to_string())),
+			Err(e) => Err(format!("{s} is neither a tag, a selector nor a column ({e})")),
+		}
+	}
+}
+
+impl Modifier {
+	fn parse(s:&str) -> Result<Modifier,String>
+	{
+		let s = s.trim();
+		let number = |n:&str| n.parse::<usize>().map_err(|_|format!("{n} is not a valid width"));
+		let format = |f:&str| {
+			let f = unquote(f);
+			if StrftimeItems::new(f).any(|i|i == Item::Error) {
+				Err(format!("{f} is not a valid date/time format"))
+			} else {
+				Ok(f.to_string())
+			}
+		};
+		if s == "md5" {
+			Ok(Modifier::Md5)
+		} else if s == "sha256" {
+			Ok(Modifier::Sha256)
+		} else if let Some(n) = s.strip_prefix('<') {
+			number(n).map(Modifier::Left)
+		} else if let Some(n) = s.strip_prefix('>') {
+			number(n).map(Modifier::Right)
+		} else if let Some(n) = s.strip_prefix('0') && !n.is_empty() {
+			number(n).map(Modifier::ZeroPad)
+		} else if let Some(f) = s.strip_prefix("datetime=") {
+			format(f).map(Modifier::DateTime)
+		} else if let Some(f) = s.strip_prefix("date=") {
+			format(f).map(Modifier::Date)
+		} else if let Some(f) = s.strip_prefix("time=") {
+			format(f).map(Modifier::Time)
+		} else {
+			number(s).map(Modifier::Left).map_err(|_|format!("{s} is not a known modifier"))
+		}
+	}
+
+	/// None if the value doesn't fit (e.g. is no date)
+	fn apply(&self, value:String) -> Option<String>
+	{
+		let value = match self {
+			Modifier::Left(n) => value.chars().take(*n).collect(),
+			Modifier::Right(n) => value.chars().skip(value.chars().count().saturating_sub(*n)).collect(),
+			Modifier::ZeroPad(n) => match value.parse::<i64>() {
+				Ok(number) => format!("{number:0width$}", width = *n),
+				Err(_) => value
+			},
+			Modifier::Date(f) => PrimitiveValue::from(value).to_date().ok()?.to_naive_date().ok()?.format(f).to_string(),
+			Modifier::Time(f) => PrimitiveValue::from(value).to_time().ok()?.to_naive_time().ok()?.format(f).to_string(),
+			Modifier::DateTime(f) => match PrimitiveValue::from(value).to_datetime().ok()?.to_precise_datetime().ok()? {
+				PreciseDateTime::Naive(dt) => dt.format(f).to_string(),
+				PreciseDateTime::TimeZone(dt) => dt.format(f).to_string(),
+			},
+			Modifier::Md5 => format!("{:x}", md5::compute(value)),
+			Modifier::Sha256 => hex::encode(Sha256::digest(value)),
+		};
+		Some(value)
+	}
+}
+
+/// makes the value usable as (part of) a filename according to the policy
+pub fn sanitize(value:&str, policy:Sanitize) -> String
+{
+	let keep = |c:char| match policy {
+		Sanitize::Posix => !matches!(c, '/' | ' ' | '\0'),
+		Sanitize::Windows => !c.is_control() && !r#"<>:"/\|?* "#.contains(c),
+		Sanitize::Portable => c.is_ascii_alphanumeric() || "._-".contains(c),
+	};
+	let mut value:String = value.chars().map(|c|if keep(c) {c} else {'_'}).collect();
+	// "." and ".." would move around in the directory tree
+	if value.chars().all(|c|c == '.') {
+		value = value.replace('.', "_");
+	}
+	if policy == Sanitize::Windows {
+		if value.ends_with('.') {
+			value.pop();
+			value.push('_');
+		}
+		let stem = value.split('.').next().unwrap_or_default();
+		if RESERVED.iter().any(|r|r.eq_ignore_ascii_case(stem)) {
+			value.insert(0, '_');
+		}
+	}
+	value
+}
+
+impl Field {
+	fn parse(s:&str, column:&dyn Fn(&str) -> bool) -> Result<Field,String>
+	{
+		let mut parts = split_top(s, ':').into_iter();
+		let sources = split_top(parts.next().unwrap_or_default(), '|').into_iter()
+			.map(|s|Source::parse(s, column))
+			.collect::<Result<_,_>>()?;
+		let modifiers = parts.map(Modifier::parse).collect::<Result<_,_>>()?;
+		Ok(Field{sources,modifiers})
+	}
+
+	/// the first source with a (non-empty) value that fits all modifiers
+	fn render(&self, lookup:&mut dyn FnMut(&Source) -> Option<PrimitiveValue>) -> String
+	{
+		let paths = &config::get().paths;
+		let mut empty = false;
+		for source in &self.sources {
+			let Some(value) = lookup(source) else {continue};
+			let value = value.to_str().trim_matches([' ','\0']).to_string();
+			if value.is_empty() {
+				empty = true;
+				continue
+			}
+			if let Some(value) = self.modifiers.iter().try_fold(value, |v,m|m.apply(v)) {
+				return sanitize(&value, paths.sanitize)
+			}
+		}
+		if empty {paths.empty_placeholder.clone()} else {paths.none_placeholder.clone()}
+	}
+
+	/// the value is (most likely) unique for every instance
+	fn is_unique(&self) -> bool
+	{
+		if self.sources.first() != Some(&Source::Selector(tags::SOP_INSTANCE_UID.into())) {
+			return false
+		}
+		let mut hashed = false;
+		for m in &self.modifiers {
+			match m {
+				Modifier::Md5 | Modifier::Sha256 => hashed = true,
+				Modifier::Left(n) | Modifier::Right(n) => if !hashed || *n < 16 {return false},
+				Modifier::ZeroPad(_) => {}
+				Modifier::Date(_) | Modifier::Time(_) | Modifier::DateTime(_) => return false,
+			}
+		}
+		true
+	}
+}
+
+impl Pattern {
+	/// `column` tells if a name is a configured column
+	pub fn parse(pattern:&str, column:&dyn Fn(&str) -> bool) -> Result<Pattern,String>
+	{
+		let mut parts = vec![];
+		let mut text = String::new();
+		let mut rest = pattern;
+		while let Some(c) = rest.chars().next() {
+			if let Some(r) = rest.strip_prefix("{{").or(rest.strip_prefix("}}")) {
+				text.push(c);
+				rest = r;
+			} else if c == '{' {
+				let end = top_level(&rest[1..], '}').next().ok_or(format!("unterminated field in {pattern}"))? + 1;
+				if !text.is_empty() {
+					parts.push(Part::Text(std::mem::take(&mut text)));
+				}
+				parts.push(Part::Field(Field::parse(&rest[1..end], column)?));
+				rest = &rest[end+1..];
+			} else if c == '}' {
+				return Err(format!(r#"unmatched "}}" in {pattern}"#))
+			} else {
+				text.push(c);
+				rest = &rest[c.len_utf8()..];
+			}
+		}
+		if !text.is_empty() {
+			parts.push(Part::Text(text));
+		}
+		Ok(Pattern{parts})
+	}
+
+	/// `lookup` provides the values of the sources
+	pub fn render(&self, mut lookup:impl FnMut(&Source) -> Option<PrimitiveValue>) -> String
+	{
+		self.parts.iter().map(|part|match part {
+			Part::Text(text) => text.clone(),
+			Part::Field(field) => field.render(&mut lookup),
+		}).collect()
+	}
+
+	/// reasons why different instances may get the same filename
+	pub fn uniqueness_warnings(&self) -> Vec<String>
+	{
+		let fields:Vec<_> = self.parts.iter()
+			.filter_map(|p|if let Part::Field(f) = p {Some(f)} else {None})
+			.collect();
+		if fields.iter().any(|f|f.is_unique()) {
+			vec![]
+		} else if fields.iter().any(|f|f.sources.iter().any(|s|*s == Source::Selector(tags::SOP_INSTANCE_UID.into()))) {
+			vec!["SOPInstanceUID is only used cropped, as fallback or with a hash shorter than 16 characters, different instances may get the same filename".into()]
+		} else {
+			vec!["SOPInstanceUID is not used, different instances may get the same filename".into()]
+		}
+	}
+}
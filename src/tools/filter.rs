@@ -1,17 +1,18 @@
 use std::collections::HashMap;
+use std::str::FromStr;
 use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use dicom::core::header::Header;
 use dicom::core::{DataDictionary, PrimitiveValue, Tag, VR};
 use dicom::core::dictionary::VirtualVr;
 use dicom::core::smallvec::SmallVec;
-use dicom::core::value::{DicomDate, DicomDateTime, DicomTime, Value, C};
+use dicom::core::value::{DataSetSequence, DicomDate, DicomDateTime, DicomTime, Value, C};
 use dicom::dictionary_std::StandardDataDictionary;
 use dicom::object::InMemDicomObject;
 use dicom::object::mem::InMemElement;
 use pyo3::{Bound, IntoPyObject, PyAny, PyErr, Python};
 use pyo3::conversion::FromPyObjectOwned;
 use pyo3::exceptions::PyValueError;
-use pyo3::types::{PyAnyMethods, PyDict, PyModule};
+use pyo3::types::{PyAnyMethods, PyBytes, PyDict, PyDictMethods, PyModule};
 use tracing::{debug, error};
 use crate::tools;
 
@@ -64,17 +65,33 @@ fn replace_element(val: Bound<PyAny>, e:InMemElement) -> Result<InMemElement, Py
 {
 	let tag = e.tag();
 	let vr = e.vr();
-	let err = format!("{} is not a primitive value", e.to_str().unwrap());
-	if let Some(mut e_val) = e.into_value().into_primitive(){
-		edit_value(val,&mut e_val)?;
-		Ok(InMemElement::new(tag,vr,e_val))
-	} else { Err(PyValueError::new_err(err)) }
+	match vr {
+		// sequences and binary data are always fully replaced
+		VR::SQ => {
+			let existing:Vec<_> = e.items().into_iter().flatten().collect();
+			make_sequence(val, &existing).map(|s|InMemElement::new(tag,vr,s))
+		}
+		VR::OB | VR::OW | VR::UN => make_value(val,vr).map(|v|InMemElement::new(tag,vr,v)),
+		_ => {
+			let err = format!("{} is not a primitive value", tag);
+			if let Some(mut e_val) = e.into_value().into_primitive(){
+				edit_value(val,&mut e_val)?;
+				Ok(InMemElement::new(tag,vr,e_val))
+			} else { Err(PyValueError::new_err(err)) }
+		}
+	}
+}
+
+fn make_bytes(val: Bound<PyAny>) -> Result<Vec<u8>, PyErr>
+{
+	val.extract::<Vec<u8>>()
+		.map_err(|_|PyValueError::new_err(format!("cannot extract {val} as bytes")))
 }
 
 fn make_value(val: Bound<PyAny>, vr:VR) -> Result<PrimitiveValue, PyErr> {
 	match vr {
-		VR::AE | VR::AS | VR::CS | VR::DA | VR::DS | VR::DT | VR::IS | VR::LO | VR::LT | VR::OD |
-		VR::OF | VR::OW | VR::PN | VR::SH | VR::ST | VR::TM | VR::UC | VR::UI | VR::UR | VR::UT =>
+		VR::AE | VR::AS | VR::CS | VR::DA | VR::DS | VR::DT | VR::IS | VR::LO | VR::LT |
+		VR::PN | VR::SH | VR::ST | VR::TM | VR::UC | VR::UI | VR::UR | VR::UT =>
 			{ // String
 				if let Some(val) = val.extract::<Vec<String>>().ok(){
 					Ok(PrimitiveValue::Strs(val.into()))
@@ -82,8 +99,16 @@ fn make_value(val: Bound<PyAny>, vr:VR) -> Result<PrimitiveValue, PyErr> {
 					Ok(PrimitiveValue::Str(val))
 				} else { Err(PyValueError::new_err(format!("cannot extract {val}"))) }
 			}
-		VR::FL => Ok(PrimitiveValue::F32(auto_array(val)?)),
-		VR::FD => Ok(PrimitiveValue::F64(auto_array(val)?)),
+		VR::OB | VR::UN => Ok(PrimitiveValue::U8(make_bytes(val)?.into())),
+		VR::OW => {
+			let bytes = make_bytes(val)?;
+			if bytes.len() % 2 != 0 {
+				return Err(PyValueError::new_err(format!("OW needs an even number of bytes, got {}", bytes.len())))
+			}
+			Ok(PrimitiveValue::U16(bytes.chunks_exact(2).map(|w|u16::from_le_bytes([w[0],w[1]])).collect()))
+		}
+		VR::FL | VR::OF => Ok(PrimitiveValue::F32(auto_array(val)?)),
+		VR::FD | VR::OD => Ok(PrimitiveValue::F64(auto_array(val)?)),
 		VR::SS => Ok(PrimitiveValue::I16(auto_array(val)?)),
 		VR::SL => Ok(PrimitiveValue::I32(auto_array(val)?)),
 		VR::SV => Ok(PrimitiveValue::I64(auto_array(val)?)),
@@ -135,10 +160,102 @@ fn from_primitive(v:PrimitiveValue, py:Python) -> Result<Bound<PyAny>, PyErr> {
 		_ => Err(PyValueError::new_err(format!("cannot make a python value from {v}")))
 	}
 }
-fn make_element(val: Bound<PyAny>, tag: Tag) -> Result<InMemElement, PyErr> {
-	if let VirtualVr::Exact(vr) = StandardDataDictionary::default().by_tag(tag).unwrap().vr{
+/// python representation of binary values (OB/OW/UN) as bytes
+fn from_binary(v:&PrimitiveValue, py:Python) -> Result<Bound<PyAny>, PyErr> {
+	let bytes:Vec<u8> = match v {
+		PrimitiveValue::U16(words) => words.iter().flat_map(|w|w.to_le_bytes()).collect(),
+		_ => v.to_bytes().into_owned()
+	};
+	Ok(PyBytes::new(py, bytes.as_slice()).into_any())
+}
+
+fn from_element<'py>(e:&InMemElement, py:Python<'py>) -> Result<Option<Bound<'py, PyAny>>, PyErr> {
+	match e.value() {
+		Value::Primitive(val) => match e.vr() {
+			VR::OB | VR::OW | VR::UN => from_binary(val,py).map(Some),
+			_ => from_primitive(val.clone(),py).map(Some)
+		},
+		Value::Sequence(seq) => {
+			let items = seq.items().iter()
+				.map(|item|from_object(item,py))
+				.collect::<Result<Vec<_>,_>>()?;
+			items.into_pyobject(py).map(|l|Some(l.into_any()))
+		},
+		_ => {
+			error!("Ignoring {} as it is neither a primitive value nor a sequence", e.tag());
+			Ok(None)
+		}
+	}
+}
+
+/// dictionary of all elements in an object (used for sequence items)
+fn from_object<'py>(obj:&InMemDicomObject, py:Python<'py>) -> Result<Bound<'py, PyDict>, PyErr> {
+	let ret = PyDict::new(py);
+	for e in obj {
+		if let Some(val) = from_element(e,py)? {
+			ret.set_item((e.tag().0,e.tag().1), val)?;
+		}
+	}
+	Ok(ret)
+}
+
+/// parse a key of a returned dictionary
+/// 
+/// Keys are either `(group,element)` or `(group,element,"VR")`.
+/// The latter is needed for creating tags not known to the standard dictionary (e.g. private tags).
+fn parse_key(key:&Bound<PyAny>) -> Result<(Tag,Option<VR>), PyErr> {
+	if let Ok((group,element,vr)) = key.extract::<(u16,u16,String)>() {
+		let vr = VR::from_str(vr.as_str())
+			.map_err(|_|PyValueError::new_err(format!("Invalid VR {vr} for tag {}", Tag(group,element))))?;
+		Ok((Tag(group,element),Some(vr)))
+	} else {
+		key.extract::<(u16,u16)>()
+			.map(|(group,element)|(Tag(group,element),None))
+			.map_err(|_|PyValueError::new_err(format!("{key} is not a valid tag")))
+	}
+}
+
+/// `existing` are the items of the sequence that is replaced (if any)
+fn make_sequence(val: Bound<PyAny>, existing:&[&InMemDicomObject]) -> Result<DataSetSequence<InMemDicomObject>, PyErr> {
+	let items = val.extract::<Vec<Bound<PyDict>>>()
+		.map_err(|_|PyValueError::new_err(format!("cannot extract {val} as a list of sequence items")))?
+		.into_iter().map(|item|make_object(item, existing))
+		.collect::<Result<Vec<_>,_>>()?;
+	Ok(items.into())
+}
+
+fn make_object(dict: Bound<PyDict>, existing:&[&InMemDicomObject]) -> Result<InMemDicomObject, PyErr> {
+	let mut obj = InMemDicomObject::new_empty();
+	for (key,val) in dict.iter() {
+		let (tag,vr) = parse_key(&key)?;
+		if !val.is_none() {
+			obj.put_element(make_element(val, tag, vr, existing)?);
+		}
+	}
+	Ok(obj)
+}
+
+/// creates the element from its python value
+///
+/// If no VR is given and the dictionary doesn't know it exactly (e.g. private tags), the VR of the element in the `existing` items is used.
+/// So items returned as they were received (or edited) keep their elements.
+fn make_element(val: Bound<PyAny>, tag: Tag, vr:Option<VR>, existing:&[&InMemDicomObject]) -> Result<InMemElement, PyErr> {
+	let known:Vec<_> = existing.iter().filter_map(|item|item.element(tag).ok()).collect();
+	let vr = match vr {
+		Some(vr) => vr,
+		None => match StandardDataDictionary::default().by_tag(tag).map(|e|e.vr) {
+			Some(VirtualVr::Exact(vr)) => vr,
+			_ if !known.is_empty() => known[0].vr(),
+			Some(_) => return Err(PyValueError::new_err(format!("VR of {tag} is ambiguous, please provide it explicitly"))),
+			None => return Err(PyValueError::new_err(format!("{tag} is unknown, please provide its VR explicitly")))
+		}
+	};
+	if vr == VR::SQ {
+		let existing:Vec<_> = known.iter().filter_map(|e|e.items()).flatten().collect();
+		make_sequence(val, &existing).map(|s|InMemElement::new(tag,vr,s))
+	} else {
 		make_value(val,vr).map(|v|InMemElement::new(tag,vr,v))
-	} else { Err(PyValueError::new_err(format!("Invalid VR for tag {}", tag.to_string()))) }
+	}
 }
 
 pub fn filter(code:Bound<PyModule>, obj:&mut InMemDicomObject) -> tools::Result<()>
@@ -153,21 +270,21 @@ pub fn filter(code:Bound<PyModule>, obj:&mut InMemDicomObject) -> tools::Result<
 	let param = PyDict::new(py);
 	for (t,e) in input
 	{
-		if let Value::Primitive(val) = e.value() {
-			param.set_item((t.0,t.1), from_primitive(val.clone(),py)?)?;
-		} else {
-			error!("Ignoring {} as it is not a primitive value", e.to_str().unwrap());
+		if let Some(val) = from_element(e,py)? {
+			param.set_item((t.0,t.1), val)?;
 		}
 	}
 	debug!("Running filter {code}");
-	let res:HashMap<(u16,u16),Option<Bound<PyAny>>> = code.call_method1("filter", (param,))?.extract()?;
-	for (tag,val) in res {
-		let tag = Tag::from(tag);
-		if let Some(val) = val {
-			let new_e = if let Some(e) = obj.take_element(tag).ok() {
-				replace_element(val, e)?
-			} else { make_element(val, tag)?};
-			debug!("Replacing {} with {}", tag.to_string(), new_e.to_str().unwrap());
+	let res:Bound<PyDict> = code.call_method1("filter", (param,))?.extract()?;
+	for (key,val) in res.iter() {
+		let (tag,vr) = parse_key(&key)?;
+		if !val.is_none() {
+			let new_e = match obj.take_element(tag).ok() {
+				// an explicitly given VR that differs from the existing one means re-creation
+				Some(e) if vr.is_none_or(|vr|vr == e.vr()) => replace_element(val, e)?,
+				_ => make_element(val, tag, vr, &[])?
+			};
+			debug!("Replacing {} with {}", tag.to_string(), new_e.to_str().unwrap_or_else(|_|new_e.vr().to_string().into()));
 			obj.put_element(new_e);
 		} else {
 			debug!("Removing {}", tag.to_string());
@@ -0,0 +1,208 @@
+use crate::config::{self, AePermissions, DimseCfg};
+use crate::db::dimse_access::Accessor;
+use crate::tools::{tls, Result};
+use dicom::ul::FullAeAddr;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, warn};
+
+/// time a new connection has to send its association request
+const REQUEST_TIMEOUT:Duration = Duration::from_secs(30);
+/// association requests bigger than this are not accepted
+const MAX_REQUEST:usize = 0x10000;
+/// A-ASSOCIATE-RJ "rejected-transient", "service-provider (presentation)", "local-limit-exceeded"
+const LIMIT_EXCEEDED:[u8;10] = [0x03, 0, 0, 0, 0, 4, 0, 0x02, 0x03, 0x02];
+
+/// what all associations share
+#[derive(Clone)]
+struct Shared{
+	dimse:&'static DimseCfg,
+	/// for the connections to the peers if `[dimse.tls]` is configured
+	connector:Option<TlsConnector>,
+	active:Active,
+}
+
+/// number of open associations per calling AE title
+#[derive(Clone,Default)]
+struct Active(Arc<Mutex<HashMap<String,usize>>>);
+
+/// an open association, counted in [Active] until it is dropped
+struct Slot{active:Active, calling_ae:String}
+
+impl Active
+{
+	fn claim(&self, calling_ae:&str, max:Option<usize>) -> Option<Slot>
+	{
+		let mut active = self.0.lock().unwrap();
+		let count = active.entry(calling_ae.to_string()).or_default();
+		if max.is_some_and(|max|*count >= max) {
+			return None
+		}
+		*count += 1;
+		Some(Slot{active:self.clone(), calling_ae:calling_ae.to_string()})
+	}
+}
+
+impl Drop for Slot
+{
+	fn drop(&mut self) {
+		let mut active = self.active.0.lock().unwrap();
+		if let Some(count) = active.get_mut(&self.calling_ae) {
+			*count -= 1;
+			if *count == 0 {
+				active.remove(&self.calling_ae);
+			}
+		}
+	}
+}
+
+/// the peers the calling AE may use as C-MOVE destination
+pub fn move_destinations(dimse:&DimseCfg, calling_ae:&str) -> Vec<String>
+{
+	let permissions:&AePermissions = dimse.permissions(Some(calling_ae));
+	let mut ret:Vec<_> = dimse.peers.keys()
+		.filter(|ae|permissions.may_move_to(ae))
+		.cloned()
+		.collect();
+	ret.sort();
+	ret
+}
+
+/// A listener on a local port that queues only one connection at a time (others are refused until it is accepted).
+///
+/// It is only meant for a connection made right away by ourselves, and should be dropped as soon as that was accepted.
+pub(crate) fn local_listener() -> std::io::Result<TcpListener>
+{
+	let socket = TcpSocket::new_v4()?;
+	socket.bind(SocketAddr::from(([127,0,0,1],0)))?;
+	socket.listen(0)
+}
+
+/// reads the A-ASSOCIATE-RQ starting a connection, returns it (to be passed on) and the calling AE title in it
+async fn read_request<S>(stream:&mut S) -> Result<(Vec<u8>,String)> where S:AsyncRead + Unpin
+{
+	let invalid = || std::io::Error::new(ErrorKind::InvalidData, "expected an association request");
+	let mut pdu = vec![0;6];
+	stream.read_exact(&mut pdu).await?;
+	let len = u32::from_be_bytes([pdu[2], pdu[3], pdu[4], pdu[5]]) as usize;
+	// protocol version, called and calling AE title and reserved bytes come before the variable items
+	if pdu[0] != 0x01 || !(68..=MAX_REQUEST).contains(&len) {
+		return Err(invalid().into())
+	}
+	pdu.resize(6 + len, 0);
+	stream.read_exact(&mut pdu[6..]).await?;
+	let calling_ae = String::from_utf8_lossy(&pdu[26..42]).trim().to_string();
+	Ok((pdu, calling_ae))
+}
+
+/// TLS proxies to the peers an association may move to, they run as long as they are kept
+struct Proxies(Vec<JoinHandle<Result<()>>>);
+
+impl Drop for Proxies
+{
+	fn drop(&mut self) {self.0.iter().for_each(JoinHandle::abort)}
+}
+
+/// the addresses of the allowed destinations, with `[dimse.tls]` these are local proxies to them
+///
+/// each proxy forwards only one connection, so an association can only move once to each destination via TLS
+async fn destinations(shared:&Shared, calling_ae:&str) -> Result<(Vec<(String,SocketAddr)>,Proxies)>
+{
+	let mut ret = vec![];
+	let mut proxies = Proxies(vec![]);
+	for ae in move_destinations(shared.dimse, calling_ae) {
+		let addr = shared.dimse.peers[&ae];
+		match &shared.connector {
+			Some(connector) => {
+				let proxy = local_listener()?;
+				ret.push((ae, proxy.local_addr()?));
+				proxies.0.push(tokio::spawn(tls::originate(proxy, connector.clone(), addr)));
+			}
+			None => ret.push((ae, addr))
+		}
+	}
+	Ok((ret, proxies))
+}
+
+/// Serves one association by its own SCP instance which knows the calling AE and the peer.
+///
+/// The instance accepts only the connection relaying the association on a local port, it is told to stop accepting
+/// (closing the port) as soon as it answered the association request. So no other local process can act as the calling AE.
+/// Associations of calling AEs that already have `max_associations` open are rejected.
+async fn handle<S>(mut stream:S, peer:SocketAddr, shared:Shared) -> Result<()> where S:AsyncRead + AsyncWrite + Unpin
+{
+	let dimse = shared.dimse;
+	let (request, calling_ae) = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await
+		.map_err(|_|std::io::Error::from(ErrorKind::TimedOut))??;
+	let permissions = dimse.permissions(Some(&calling_ae));
+	let Some(_slot) = shared.active.claim(&calling_ae, permissions.max_associations) else {
+		warn!("refused association from {calling_ae} at {peer}, it already has {} open", permissions.max_associations.unwrap_or_default());
+		stream.write_all(&LIMIT_EXCEEDED).await?;
+		return Ok(())
+	};
+
+	let listener = local_listener()?;
+	// connected before the SCP instance runs, so it's the first (and only queued) connection the instance accepts
+	let mut local = TcpStream::connect(listener.local_addr()?).await?;
+	// the SCP instance only knows the allowed destinations, others are answered as unknown destination
+	let (peers, _proxies) = destinations(&shared, &calling_ae).await?;
+	let peers = peers.into_iter().map(|(ae,addr)|FullAeAddr::new(ae, addr));
+	let accessor = Accessor{calling_ae:Some(calling_ae.clone()), peer:Some(peer)};
+	let (signal_tx, signal_rx) = watch::channel(());
+	let scp = tokio::spawn(dimse::serve(FullAeAddr::new(&dimse.aet, listener), peers, signal_tx, accessor));
+
+	debug!("association from {calling_ae} at {peer}");
+	local.write_all(&request).await?;
+	// once there is an answer the instance has accepted the connection, it stops accepting others but serves the association
+	local.peek(&mut [0;1]).await?;
+	drop(signal_rx);
+	let relayed = tokio::io::copy_bidirectional(&mut stream, &mut local).await;
+	scp.await??;
+	relayed.map(|_|()).map_err(|e|e.into())
+}
+
+async fn accept(listener:TcpListener, dimse:&'static DimseCfg) -> Result<()>
+{
+	let acceptor = dimse.tls.as_ref().map(tls::acceptor).transpose()?;
+	let shared = Shared{
+		dimse,
+		connector:dimse.tls.as_ref().map(tls::connector).transpose()?,
+		active:Active::default()
+	};
+	loop {
+		let (stream, peer) = listener.accept().await?;
+		let (acceptor, shared) = (acceptor.clone(), shared.clone());
+		tokio::spawn(async move {
+			let handled = match acceptor {
+				Some(acceptor) => match tls::handshake(&acceptor, stream).await {
+					Ok(stream) => handle(stream, peer, shared).await,
+					Err(e) => Err(e.into())
+				},
+				None => handle(stream, peer, shared).await
+			};
+			if let Err(e) = handled {
+				warn!("association from {peer} failed: {e}");
+			}
+		});
+	}
+}
+
+/// Accepts DIMSE connections on `listener` until shutdown and serves each association by its own SCP instance.
+///
+/// With `[dimse.tls]` the TLS handshake is done here, so the SCP instances see the address of the actual peer.
+pub async fn serve(listener:TcpListener) -> Result<()>
+{
+	let dimse = config::get().dimse.as_ref().expect("the SCP needs the [dimse] config");
+	tokio::select! {
+		result = accept(listener, dimse) => result,
+		_ = crate::tools::shutdown_signal() => Ok(()),
+	}
+}
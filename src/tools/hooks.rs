@@ -0,0 +1,161 @@
+use crate::config::{self, HookCfg};
+use crate::db::{self, RecordId, DB};
+use crate::tools::events::Event;
+use crate::tools::Error::{HookFailed, NotFound};
+use crate::tools::{Context, Result};
+use pyo3::sync::PyOnceLock;
+use pyo3::types::{PyAnyMethods, PyModule};
+use pyo3::{Py, PyAny, Python};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::LazyLock;
+use std::time::Duration;
+use surrealdb::types as db_types;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+/// the `status` of hooks that were started
+pub const RUNNING:&str = "running";
+pub const SUCCESS:&str = "success";
+pub const FAILED:&str = "failed";
+pub const TIMEOUT:&str = "timeout";
+
+/// the output stored is cut to its end
+const MAX_OUTPUT:usize = 4096;
+
+/// limits the concurrent runs of each hook
+static LIMITS:LazyLock<HashMap<&'static str,Semaphore>> = LazyLock::new(||
+	config::get().hooks.iter().map(|h|(h.name.as_str(),Semaphore::new(h.concurrency.max(1)))).collect()
+);
+
+/// the `hook` functions of the python hooks, compiled when they are first run
+static PYTHON_HOOKS:LazyLock<HashMap<&'static str,PyOnceLock<Py<PyAny>>>> = LazyLock::new(||
+	config::get().hooks.iter().map(|h|(h.name.as_str(),PyOnceLock::new())).collect()
+);
+
+/// Starts all hooks triggered by the event (called by [crate::tools::events::emit]).
+pub(crate) fn run(event:&Event)
+{
+	let target = match event {
+		Event::InstanceStored {instance,..} => instance.clone(),
+		Event::SeriesComplete {series,..} => series.clone(),
+		_ => return
+	};
+	for hook in config::get().hooks.iter().filter(|h|h.on == event.name()) {
+		tokio::spawn(execute(hook, target.clone()));
+	}
+}
+
+/// stores the status of the hook as `hooks.<name>` of the entry
+async fn set_status(target:&RecordId, hook:&str, status:&str, output:Option<String>)
+{
+	let mut result = db_types::Object::default();
+	result.insert("status", status.to_string());
+	result.insert("timestamp", chrono::Utc::now().to_rfc3339());
+	if let Some(output) = output {
+		result.insert("output", output);
+	}
+	let mut hooks = db_types::Object::default();
+	hooks.insert(hook, db_types::Value::Object(result));
+	let mut content = db_types::Object::default();
+	content.insert("hooks", db_types::Value::Object(hooks));
+	let updated = DB.query("UPDATE $rec MERGE $content")
+		.bind(("rec",target.0.clone()))
+		.bind(("content",content)).await
+		.and_then(|r|r.check());
+	if let Err(e) = updated {
+		warn!("Failed to store status of hook {hook} on {target}: {e}");
+	}
+}
+
+async fn execute(hook:&'static HookCfg, target:RecordId)
+{
+	let _permit = LIMITS[hook.name.as_str()].acquire().await;
+	set_status(&target, &hook.name, RUNNING, None).await;
+	let (status, output) = match tokio::time::timeout(Duration::from_secs(hook.timeout), invoke(hook, &target)).await {
+		Ok(Ok(output)) => (SUCCESS, output),
+		Ok(Err(e)) => {
+			warn!("hook {} on {target} failed: {e}",hook.name);
+			(FAILED, e.to_string())
+		},
+		Err(_) => {
+			warn!("hook {} on {target} timed out",hook.name);
+			(TIMEOUT, format!("didn't finish within {} seconds",hook.timeout))
+		}
+	};
+	debug!("hook {} on {target}: {status}",hook.name);
+	set_status(&target, &hook.name, status, Some(output)).await;
+}
+
+async fn invoke(hook:&'static HookCfg, target:&RecordId) -> Result<String>
+{
+	let entry = db::lookup(target).await?.ok_or(NotFound).context(format!("looking up {target} for hook {}",hook.name))?;
+	let files:Vec<_> = entry.get_files().await?.iter()
+		.map(|f|f.get_path().to_string_lossy().into_owned())
+		.collect();
+	if let Some(command) = &hook.command {
+		run_command(&hook.name, command, files, target).await
+	} else if let Some(code) = &hook.python {
+		let name = hook.name.as_str();
+		tokio::task::spawn_blocking(move ||run_python(name, code, files)).await?
+	} else {
+		Ok(String::new())
+	}
+}
+
+/// the end of the output
+fn tail(output:&[u8]) -> String
+{
+	let output = String::from_utf8_lossy(output);
+	let output = output.trim();
+	let start = output.len().saturating_sub(MAX_OUTPUT);
+	let start = (start..output.len()).find(|i|output.is_char_boundary(*i)).unwrap_or(output.len());
+	output[start..].to_string()
+}
+
+async fn run_command(name:&str, command:&[String], files:Vec<String>, target:&RecordId) -> Result<String>
+{
+	let Some((program, args)) = command.split_first() else {return Ok(String::new())};
+	let mut cmd = tokio::process::Command::new(program);
+	for arg in args {
+		if arg == "{files}" {
+			cmd.args(&files);
+		} else {
+			cmd.arg(arg.replace("{id}", target.str_key().as_str()));
+		}
+	}
+	if !args.iter().any(|a|a == "{files}") {
+		cmd.args(&files);
+	}
+	// aborted runs (timeout) must not keep running
+	cmd.kill_on_drop(true);
+	let output = cmd.output().await.context(format!("running {program} for hook {name}"))?;
+	let text = tail(&[output.stdout, output.stderr].concat());
+	if output.status.success() {
+		Ok(text)
+	} else {
+		Err(HookFailed {hook:name.to_string(), reason:format!("{}: {text}",output.status)})
+	}
+}
+
+/// compiles the python code of the hook as module named like the hook and returns its `hook` function
+fn compile(py:Python, name:&str, code:&str) -> Result<Py<PyAny>>
+{
+	let nul = |_| HookFailed {hook:name.to_string(), reason:"the name or the python code contains a NUL byte".into()};
+	let code = CString::new(code).map_err(nul)?;
+	let module_name = CString::new(name).map_err(nul)?;
+	let module = PyModule::from_code(py, code.as_ref(), c"", module_name.as_ref())?;
+	Ok(module.getattr("hook")?.unbind())
+}
+
+/// Calls `hook(paths)` of the python code (timeouts can't abort it, it just isn't waited for anymore).
+///
+/// The code is compiled on the first run of the hook, failed compilations are retried the next time.
+fn run_python(name:&str, code:&str, files:Vec<String>) -> Result<String>
+{
+	Python::attach(|py| {
+		let function = PYTHON_HOOKS[name].get_or_try_init(py, ||compile(py, name, code))?;
+		let result = function.bind(py).call1((files,))?;
+		Ok(if result.is_none() {String::new()} else {result.str()?.to_string()})
+	})
+}
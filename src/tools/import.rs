@@ -1,11 +1,15 @@
-use crate::db::{Entry, RecordId, RegisterResult, DB, Session, SharedSession, File};
-use crate::tools::Error;
+use crate::db::audit::{self, Actor};
+use crate::db::{Entry, Provenance, RecordId, RegisterResult, DB, Session, SharedSession, File};
+use crate::tools::Error::PathNotAllowed;
+use crate::tools::{Context, Error};
 use futures::{stream, Stream, TryStreamExt};
 use glob::glob;
 use itertools::Itertools;
 use serde::ser::SerializeStruct;
 use serde::{Deserialize, Serialize, Serializer};
+use serde_json::json;
 use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
 use dicom::object::DefaultDicomObject;
 use surrealdb::engine::any::Any;
 use crate::tools::store::is_storage;
@@ -15,6 +19,7 @@ pub enum ImportResult {
 	Existed{filename:String,existing_id:RecordId},
 	DataConflict {filename:String,existed:Entry},
 	Md5Conflict {filename:String,existing_md5:String,my_md5:String, existing_id:RecordId},
+	Quarantined {filename:String,id:RecordId,reason:Error},
 	Err{filename:String,error:Error},
 	GlobError(glob::GlobError)
 }
@@ -79,6 +84,13 @@ impl Serialize for ImportResult
 				s.serialize_field("existing md5", existing_md5)?;
 				s.end()
 			}
+			ImportResult::Quarantined {filename, id, reason} => {
+				let mut s=s.serialize_struct("quarantined",3)?;
+				s.serialize_field("filename",filename)?;
+				s.serialize_field("quarantine_path",id.str_path().as_str())?;
+				s.serialize_field("reason", reason.to_string().as_str())?;
+				s.end()
+			}
 			ImportResult::Err { filename,error} => {
 				let mut s=s.serialize_struct("failed",2)?;
 				s.serialize_field("filename", filename)?;
@@ -99,36 +111,83 @@ impl Serialize for ImportResult
 	}
 }
 
-async fn import_file_ob<S>(info: File, obj: DefaultDicomObject, mode: ImportMode, session: &mut S) -> ImportResult where S:Session<Any>
+async fn import_file_ob<S>(info: File, obj: DefaultDicomObject, mode: ImportMode, actor: &Actor, pattern:&str, session: &mut S) -> ImportResult where S:Session<Any>
 {
+	let provenance = Provenance::import(actor, mode.to_string().as_str(), pattern);
 	let filename = info.get_path().to_string_lossy().to_string();
 	let import =
 		match mode {
-			ImportMode::Import => {crate::tools::store::import_file_ob(info,obj, session).await}
-			ImportMode::Store => {crate::tools::store::store_ob(obj, session).await}
-			ImportMode::Move => {crate::tools::store::move_file_ob(info, obj, session).await}
+			ImportMode::Import => {crate::tools::store::import_file_ob(info,obj, &provenance, session).await}
+			ImportMode::Store => {crate::tools::store::store_ob(obj, &provenance, session).await}
+			ImportMode::Move => {crate::tools::store::move_file_ob(info, obj, &provenance, session).await}
 		};
 	match import
 	{
-		Ok(RegisterResult::Stored(_)) => ImportResult::Registered{ filename },
+		Ok(RegisterResult::Stored(id)) => {
+			audit::log(actor, mode.to_string().as_str(), &id, json!({"filename":filename})).await;
+			ImportResult::Registered{ filename }
+		},
 		Ok(RegisterResult::AlreadyStored(existed)) =>
 			ImportResult::Existed {filename,existing_id:existed},
 		Err(Error::Md5Conflict {existing_md5,my_md5, existing_id}) =>  
 			ImportResult::Md5Conflict {filename,existing_md5,my_md5,existing_id},
 		Err(Error::DataConflict(existed)) => 
 			ImportResult::DataConflict { filename, existed },
+		Err(Error::Quarantined {id, reason}) =>
+			ImportResult::Quarantined { filename, id, reason:*reason },
 		Err(e) => ImportResult::Err{error:e,filename},
 	}
 }
 
 
-pub fn import_glob<T>(pattern:T, config:ImportConfig, mode: ImportMode) -> crate::tools::Result<impl Stream<Item=crate::tools::Result<ImportResult>>> where T:AsRef<str>
+/// Makes sure the pattern can only match files inside one of the given (canonical) roots.
+///
+/// The pattern must be absolute, must not contain ".." and its part before the first wildcard must (after canonicalization) be inside a root.
+pub fn check_pattern(pattern:&str, roots:&[PathBuf]) -> crate::tools::Result<()>
+{
+	let path = Path::new(pattern);
+	if !path.is_absolute() {
+		return Err(PathNotAllowed {path:path.to_path_buf(),reason:"it is not absolute".into()});
+	}
+	if path.components().any(|c|c == Component::ParentDir) {
+		return Err(PathNotAllowed {path:path.to_path_buf(),reason:r#"".." is not allowed"#.into()});
+	}
+	let base:PathBuf = path.components()
+		.take_while(|c|!c.as_os_str().to_string_lossy().contains(['*','?','[']))
+		.collect();
+	check_path(&canonical(&base)?, roots)
+}
+
+/// canonicalizes the path, paths that cannot be resolved are not allowed
+fn canonical(path:&Path) -> crate::tools::Result<PathBuf>
+{
+	path.canonicalize().map_err(|e|PathNotAllowed {path:path.to_path_buf(),reason:format!("it cannot be resolved ({e})")})
+}
+
+/// Makes sure the (canonical) path is inside one of the given (canonical) roots.
+fn check_path(path:&Path, roots:&[PathBuf]) -> crate::tools::Result<()>
+{
+	if roots.iter().any(|r|path.starts_with(r)) {
+		Ok(())
+	} else {
+		Err(PathNotAllowed {path:path.to_path_buf(),reason:"it is outside of the import roots".into()})
+	}
+}
+
+/// Imports all files matching the pattern.
+///
+/// If `roots` is given, the pattern and every file it matches (after resolving symlinks) must be inside one of them.
+pub fn import_glob<T>(pattern:T, config:ImportConfig, mode: ImportMode, actor: Actor, roots:Option<&'static [PathBuf]>) -> crate::tools::Result<impl Stream<Item=crate::tools::Result<ImportResult>>> where T:AsRef<str>
 {
+	if let Some(roots) = roots {
+		check_pattern(pattern.as_ref(), roots)?;
+	}
 	let max_files = crate::config::get().limits.max_files;
 	let mut files= glob(pattern.as_ref())?.filter_map_ok(|p|
 		if p.is_file() {Some(p)} else {None}
 	);
 	let session_pool = SharedSession::<Any>::create(&DB, 1);
+	let glob_pattern = pattern.as_ref().to_string();
 
 	// if there is not at least one file, it's probably a good idea to return an error
 	if let Some(file)=files.next().transpose()?{
@@ -141,13 +200,19 @@ pub fn import_glob<T>(pattern:T, config:ImportConfig, mode: ImportMode) -> crate
 					ImportMode::Store => true,
 					ImportMode::Move => is_storage(&p)
 				};
-				File::new_from_existing(p, owned)
+				async move {
+					if let Some(roots) = roots {
+						check_path(&canonical(&p)?, roots)?;
+					}
+					File::new_from_existing(p, owned).await
+				}
 			})
 			.try_buffer_unordered(max_files as usize)
 			.and_then(move|(info,obj)|{
 				let mut session =session_pool.clone();
+				let (actor, pattern) = (actor.clone(), glob_pattern.clone());
 				async move {
-					Ok(import_file_ob(info,obj,mode,&mut session).await)
+					Ok(import_file_ob(info,obj,mode,&actor,&pattern,&mut session).await)
 				}})
 			.try_filter(move |item|{
 				let ret =	match item.to_owned() {
@@ -163,9 +228,9 @@ pub fn import_glob<T>(pattern:T, config:ImportConfig, mode: ImportMode) -> crate
 	}
 }
 
-pub fn import_glob_as_text<T>(pattern:T, config:ImportConfig, mode: ImportMode) -> crate::tools::Result<impl Stream<Item=crate::tools::Result<String>>> where T:AsRef<str>
+pub fn import_glob_as_text<T>(pattern:T, config:ImportConfig, mode: ImportMode, actor: Actor, roots:Option<&'static [PathBuf]>) -> crate::tools::Result<impl Stream<Item=crate::tools::Result<String>>> where T:AsRef<str>
 {
-	Ok(import_glob(pattern, config, mode)?
+	Ok(import_glob(pattern, config, mode, actor, roots)?
 		.map_ok(|item| {
 			let register_msg = match item {
 				ImportResult::Registered { filename } => Ok(filename),
@@ -184,6 +249,8 @@ pub fn import_glob_as_text<T>(pattern:T, config:ImportConfig, mode: ImportMode)
 				},
 				ImportResult::Md5Conflict { filename, existing_id,.. } => 
 					Ok(format!("{filename} was rejected as {} already exists but its checksum differs", existing_id.str_path())),
+				ImportResult::Quarantined { filename, id, reason } =>
+					Ok(format!("{filename} was quarantined as {} ({reason})", id.str_path())),
 				ImportResult::Err { filename, error } => {
 					Err(error.context(format!("importing {filename}")))
 				}
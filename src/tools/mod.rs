@@ -7,6 +7,20 @@ pub mod conv;
 pub mod tar;
 pub mod csa;
 pub mod filter;
+pub mod quarantine;
+pub mod correct;
+pub mod reorganize;
+pub mod tls;
+pub mod gateway;
+pub mod events;
+pub mod completeness;
+pub mod routing;
+pub mod scu;
+pub mod hooks;
+pub mod mosaic;
+pub mod decoders;
+pub mod computed;
+pub mod filename;
 
 use crate::db;
 use crate::db::{lookup_uid, Pickable, RecordId, DB};
@@ -0,0 +1,23 @@
+use crate::tools::csa::{CsaHeader, IMAGE_SELECTOR};
+use dicom::object::InMemDicomObject;
+use dicom::pixeldata::image::DynamicImage;
+
+/// the number of slices of a Siemens mosaic (None if the object is no mosaic)
+pub fn number_of_slices(obj:&InMemDicomObject) -> Option<u32>
+{
+	CsaHeader::from_element(IMAGE_SELECTOR.element(obj)?).ok()?
+		.value("NumberOfImagesInMosaic").ok().flatten()?
+		.to_int::<u32>().ok()
+		.filter(|n|*n > 1)
+}
+
+/// Cuts `slice` out of the image of a mosaic with `slices` slices (None if there is no such slice).
+///
+/// The slices are tiled row by row on a square grid just big enough for all of them.
+pub fn slice(image:&DynamicImage, slices:u32, slice:u32) -> Option<DynamicImage>
+{
+	if slice >= slices {return None}
+	let tiles = slices.isqrt() + u32::from(slices.isqrt().pow(2) < slices);
+	let (width, height) = (image.width() / tiles, image.height() / tiles);
+	Some(image.crop_imm((slice % tiles) * width, (slice / tiles) * height, width, height))
+}
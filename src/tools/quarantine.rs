@@ -0,0 +1,253 @@
+use crate::db::{register_instance_replacement, register_instance_version, File, FileInfo, LocalSession, Pickable, Provenance, RecordId, RegisterResult, Session, DB};
+use crate::tools::Error::{IdNotFound, UnexpectedResult};
+use crate::tools::{complete_filepath, extract_from_dicom, Context, Error, Result};
+use crate::{config, db};
+use dicom::dictionary_std::tags;
+use dicom::object::DefaultDicomObject;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use surrealdb::types as db_types;
+use surrealdb::types::SurrealValue;
+use tracing::{error, info, warn};
+
+/// An object that was kept in the quarantine instead of being registered
+pub struct Quarantined
+{
+	pub id:RecordId,
+	pub uid:String,
+	pub reason:String,
+	pub message:String,
+	pub existing:Option<RecordId>,
+	pub file:File,
+	pub timestamp:db_types::Value,
+	/// where the object came from, used when it is registered later
+	pub provenance:Provenance,
+}
+
+impl Quarantined
+{
+	/// removes the quarantined object from the database and deletes its file
+	pub async fn discard(self) -> Result<()>
+	{
+		DB.delete::<Option<db_types::Value>>(self.id.0.clone()).await
+			.context(format!("deleting {}",self.id))?;
+		self.file.remove().await
+	}
+}
+
+/// Moves objects into the quarantine if `err` is a conflict with existing data and the quarantine is enabled.
+///
+/// Returns `Error::Quarantined` if the object was kept, or `err` itself otherwise.
+pub async fn on_conflict(obj:Arc<DefaultDicomObject>, err:Error, provenance:&Provenance) -> Error
+{
+	let Some(path) = &config::get().paths.quarantine_path else {return err};
+	let (reason, existing) = match &err {
+		Error::DataConflict(existing) => ("ConflictingMetadata", existing.id().clone()),
+		Error::FieldConflict {id,..} => ("ConflictingFields", id.clone()),
+		Error::Md5Conflict {existing_id,..} => ("ConflictingMd5", existing_id.clone()),
+		_ => return err
+	};
+	match put(obj, path, reason, existing, &err, provenance).await {
+		Ok(id) => {
+			info!("{err}, moved into quarantine as {id}");
+			Error::Quarantined {id,reason:Box::new(err)}
+		}
+		Err(e) => {
+			error!("Failed to quarantine object ({e}), it will be rejected");
+			err
+		}
+	}
+}
+
+async fn put(obj:Arc<DefaultDicomObject>, path:&Path, reason:&str, existing:RecordId, err:&Error, provenance:&Provenance) -> Result<RecordId>
+{
+	let uid = extract_from_dicom(&obj, tags::SOP_INSTANCE_UID)?.to_string();
+	let filename = complete_filepath(&path).join(format!("{uid}.{:016x}.dcm",rand::random::<u64>()));
+	let file = File::new_from_obj_at(obj, filename).await?;
+
+	let mut content = db_types::Object::default();
+	content.insert("uid", uid);
+	content.insert("reason", reason.to_string());
+	content.insert("message", err.to_string());
+	content.insert("existing", existing.0.into_value());
+	content.insert("file", db_types::Value::try_from(file.clone())?);
+	content.insert("provenance", db_types::Value::from(provenance.clone()));
+	let id = DB.query("CREATE ONLY quarantine CONTENT $content RETURN VALUE id")
+		.bind(("content",content)).await
+		.and_then(|mut r|r.take::<Option<db_types::RecordId>>(0));
+	match id {
+		Ok(Some(id)) => Ok(RecordId(id)),
+		Ok(None) => {
+			file.remove().await?;
+			Err(Error::NotFound.context("creating quarantine entry"))
+		}
+		Err(e) => {
+			file.remove().await?;
+			Err(Error::context_from(e,"creating quarantine entry"))
+		}
+	}
+}
+
+pub async fn list() -> Result<Vec<Quarantined>>
+{
+	DB.query("SELECT * FROM quarantine ORDER BY timestamp").await?
+		.take::<Vec<db_types::Value>>(0)?
+		.into_iter().map(Quarantined::try_from)
+		.collect()
+}
+
+pub async fn lookup(id:&RecordId) -> Result<Option<Quarantined>>
+{
+	let ctx = format!("looking up {id}");
+	DB.select::<Option<db_types::Value>>(id.0.clone()).await.context(ctx.clone())?
+		.map(Quarantined::try_from).transpose().context(ctx)
+}
+
+async fn get(id:&RecordId) -> Result<Quarantined>
+{
+	lookup(id).await?.ok_or(IdNotFound {id:id.str_key()})
+}
+
+pub async fn discard(id:&RecordId) -> Result<()>
+{
+	get(id).await?.discard().await
+}
+
+/// moves an owned file out of the way and returns where it was and where it is now
+async fn set_aside(file:&File) -> Result<Option<(PathBuf,PathBuf)>>
+{
+	if !file.owned {return Ok(None)}
+	let path = file.get_path();
+	let mut aside = path.clone().into_os_string();
+	aside.push(".replaced");
+	let aside = PathBuf::from(aside);
+	tokio::fs::rename(&path, &aside).await.context(format!("moving {} aside", path.display()))?;
+	Ok(Some((path,aside)))
+}
+
+/// the series and study of the existing instance
+async fn parents(existing:Option<&db::Entry>) -> Result<Vec<RecordId>>
+{
+	Ok(match existing {
+		Some(existing) => db::find_down_tree(existing.id()).await?.split_off(1),
+		None => vec![]
+	})
+}
+
+/// recalculates the aggregates of the former series and study (the new object may belong to others)
+async fn refresh(parents:&[RecordId]) -> Result<()>
+{
+	// series before study
+	for parent in parents {
+		db::aggregates::refresh(parent).await?;
+	}
+	Ok(())
+}
+
+/// Replaces the existing instance by the quarantined object.
+///
+/// The existing entry is replaced in the same transaction the quarantined object is registered in, its file is
+/// only deleted afterward. So if the registration fails again the existing instance and the quarantine entry stay.
+/// The aggregates of the series and study the existing instance belonged to are recalculated afterward.
+pub async fn overwrite(id:&RecordId) -> Result<RegisterResult>
+{
+	let quarantined = get(id).await?;
+	let obj = quarantined.file.read().await?;
+	let uid = extract_from_dicom(&obj, tags::SOP_INSTANCE_UID)?.to_string();
+	let existing = db::lookup_uid("instances", uid).await?;
+	let former_parents = parents(existing.as_ref()).await?;
+	// the new file may get the same name
+	let aside = match &existing {
+		Some(existing) => set_aside(&existing.get_file()?).await?,
+		None => None
+	};
+	let registered = register_instance_replacement(obj, &mut FileInfo::Store, &quarantined.provenance, &mut LocalSession::create(&DB,1)).await
+		.context(format!("registering {id}"));
+	match (registered, aside) {
+		(Err(e), Some((path,aside))) => {
+			tokio::fs::rename(&aside, &path).await.context(format!("restoring {}", path.display()))?;
+			Err(e)
+		}
+		(Err(e), None) => Err(e),
+		(Ok(registered), aside) => {
+			if let Some((_,aside)) = aside && let Err(e) = tokio::fs::remove_file(&aside).await {
+				warn!("failed to delete replaced file {} ({e})", aside.display());
+			}
+			if let Some(existing) = existing {
+				db::version::remove_versions(existing.id()).await?;
+			}
+			refresh(&former_parents).await?;
+			quarantined.discard().await?;
+			Ok(registered)
+		}
+	}
+}
+
+/// Registers the quarantined object as a new version of the existing instance.
+///
+/// The quarantine entry is removed if the registration succeeds. If it fails again it stays.
+/// The aggregates of the series and study the existing instance belonged to are recalculated afterward.
+pub async fn accept(id:&RecordId) -> Result<RegisterResult>
+{
+	let quarantined = get(id).await?;
+	let obj = quarantined.file.read().await?;
+	let uid = extract_from_dicom(&obj, tags::SOP_INSTANCE_UID)?.to_string();
+	let former_parents = parents(db::lookup_uid("instances", uid).await?.as_ref()).await?;
+	let registered = register_instance_version(obj, &mut FileInfo::Store, &quarantined.provenance, &mut LocalSession::create(&DB,1)).await
+		.context(format!("registering {id}"))?;
+	refresh(&former_parents).await?;
+	quarantined.discard().await?;
+	Ok(registered)
+}
+
+impl TryFrom<db_types::Value> for Quarantined
+{
+	type Error = Error;
+
+	fn try_from(value: db_types::Value) -> std::result::Result<Self, Self::Error> {
+		let kind = value.kind().to_string();
+		match value {
+			db_types::Value::Object(obj) => obj.try_into(),
+			_ => Err(UnexpectedResult {expected:"object".into(),found:kind})
+		}.context("parsing database object as quarantine entry")
+	}
+}
+
+impl TryFrom<db_types::Object> for Quarantined
+{
+	type Error = Error;
+
+	fn try_from(mut obj: db_types::Object) -> std::result::Result<Self, Self::Error> {
+		Ok(Quarantined{
+			id: RecordId(obj.pick_remove("id")?.into_record()?),
+			uid: obj.pick_remove("uid")?.into_string()?,
+			reason: obj.pick_remove("reason")?.into_string()?,
+			message: obj.pick_remove("message")?.into_string()?,
+			existing: obj.remove("existing")
+				.filter(|v|!v.is_nullish())
+				.map(|v|v.into_record().map(RecordId)).transpose()?,
+			file: obj.pick_remove("file")?.try_into()?,
+			timestamp: obj.remove("timestamp").unwrap_or_default(),
+			provenance: obj.remove("provenance")
+				.filter(|v|!v.is_nullish())
+				.map(Provenance::try_from).transpose()?
+				.unwrap_or_else(Provenance::local),
+		})
+	}
+}
+
+impl From<&Quarantined> for serde_json::Value
+{
+	fn from(q: &Quarantined) -> Self {
+		json!({
+			"id":q.id.str_key(),
+			"uid":q.uid,
+			"reason":q.reason,
+			"message":q.message,
+			"existing":q.existing.as_ref().map(RecordId::str_path),
+			"file":q.file.get_path(),
+			"timestamp":crate::tools::conv::value_to_json(q.timestamp.clone()),
+		})
+	}
+}
@@ -1,21 +1,41 @@
 use crate::db;
 use crate::tools::entries_for_record;
+use crate::tools::events::{self, Event};
 use crate::tools::Result;
 use std::path::{Path, PathBuf};
 use crate::db::{if_retry, DB};
 use surrealdb::types as db_types;
 use tokio::fs::remove_dir;
 
+/// Removes the entry with all its instances (and their files).
+///
+/// Emits [Event::StudyDeleted] if no instances of the study are left afterward.
 pub async fn remove(id:&db::RecordId) -> Result<()>
 {
+	let instances = entries_for_record(id,"instances").await?;
+	let parents = db::find_down_tree(id).await?;
+	let study = parents.last().cloned();
 	let mut jobs=tokio::task::JoinSet::new();
-	for job in entries_for_record(id,"instances").await?
-		.into_iter().map(|e|remove_instance(e.id().clone()))
+	for job in instances.into_iter().map(|e|remove_instance(e.id().clone()))
 	{
 		jobs.spawn(job);
 	}
 	let res:Result<Vec<_>> = jobs.join_all().await.into_iter().collect();
-	res.map(|_|())
+	res?;
+	// aggregates of what is left, series first
+	for parent in parents.iter().filter(|p|*p != id) {
+		db::aggregates::refresh(parent).await?;
+	}
+	if let Some(study) = study.as_ref().filter(|s|*s != id) {
+		db::search::refresh(study).await?;
+	}
+	if let Some(study) = study {
+		let empty = study == *id || entries_for_record(&study,"instances").await.map_or(true,|i|i.is_empty());
+		if empty {
+			events::emit(Event::StudyDeleted {study});
+		}
+	}
+	Ok(())
 }
 
 async fn remove_instance(id:db::RecordId) -> Result<Option<db::Entry>>
@@ -35,6 +55,7 @@ async fn remove_instance(id:db::RecordId) -> Result<Option<db::Entry>>
 	}
 	let removed= db::Entry::try_from(res)?;
 	removed.get_file()?.remove().await?;
+	db::version::remove_versions(removed.id()).await?;
 	Ok(Some(removed))
 }
 
@@ -0,0 +1,127 @@
+use crate::db::audit::Actor;
+use crate::db::{lookup, Entry, RecordId};
+use crate::tools::correct::{apply, rewrite_all};
+use crate::tools::Error::{IdNotFound, InvalidChange};
+use crate::tools::{entries_for_record, Context, Result};
+use dicom::core::Tag;
+use dicom::dictionary_std::tags;
+use dicom::object::mem::InMemElement;
+use serde_json::json;
+use tracing::info;
+
+/// attributes that are copied from the target study when series are moved
+const STUDY_ATTRIBUTES:[Tag;12] = [
+	tags::STUDY_INSTANCE_UID, tags::STUDY_ID, tags::STUDY_DATE, tags::STUDY_TIME, tags::STUDY_DESCRIPTION,
+	tags::ACCESSION_NUMBER, tags::REFERRING_PHYSICIAN_NAME,
+	tags::PATIENT_NAME, tags::PATIENT_ID, tags::ISSUER_OF_PATIENT_ID, tags::PATIENT_BIRTH_DATE, tags::PATIENT_SEX
+];
+
+async fn get(id:&RecordId, table:&str) -> Result<Entry>
+{
+	let entry = lookup(id).await?.ok_or(IdNotFound {id:id.str_key()})?;
+	if id.table.as_str() != table {
+		return Err(InvalidChange {tag:id.to_string(),reason:format!("it is not in {table}")});
+	}
+	Ok(entry)
+}
+
+/// the study a series belongs to
+fn study_of(series:&Entry) -> Option<RecordId>
+{
+	series.get("study").and_then(|v|v.clone().into_record().ok()).map(RecordId)
+}
+
+/// study and patient attributes of the given study (taken from its first instance)
+async fn study_attributes(study:&RecordId) -> Result<Vec<(Tag,Option<InMemElement>)>>
+{
+	let instance = entries_for_record(study, "instances").await?.into_iter().next()
+		.ok_or(IdNotFound {id:study.str_key()}).context(format!("looking for an instance of {study}"))?;
+	let obj = instance.get_file()?.read().await?;
+	Ok(STUDY_ATTRIBUTES.iter().map(|tag|(*tag,obj.element(*tag).ok().cloned())).collect())
+}
+
+/// rewrites all instances of the given series to belong to the study described by `attributes`
+async fn move_all(series:&[Entry], attributes:Vec<(Tag,Option<InMemElement>)>, actor:&Actor, action:&str, target:&RecordId, diff:serde_json::Value) -> Result<Vec<RecordId>>
+{
+	let mut instances = Vec::new();
+	for s in series {
+		instances.extend(entries_for_record(s.id(), "instances").await?);
+	}
+	let rewritten = rewrite_all(&instances, |obj| {
+		for (tag,element) in &attributes {
+			match element {
+				Some(element) => {obj.put(element.clone());},
+				None => {obj.remove_element(*tag);}
+			}
+		}
+		Ok(())
+	}).await?;
+	apply(rewritten, actor, action, target, crate::tools::conv::json_to_value(diff)).await
+}
+
+/// Moves a series into another (existing) study.
+///
+/// StudyInstanceUID, the other study attributes and the patient attributes of all instances are replaced by those of the target study.
+/// The files are rewritten (owned copies are created for files that are not owned).
+/// The former study is removed if it is empty afterward.
+///
+/// returns: the ids of all moved instances
+pub async fn move_series(series:&RecordId, study:&RecordId, actor:&Actor) -> Result<Vec<RecordId>>
+{
+	let ctx = format!("moving {series} into {study}");
+	let entry = get(series, "series").await.context(ctx.clone())?;
+	get(study, "studies").await.context(ctx.clone())?;
+	let attributes = study_attributes(study).await.context(ctx.clone())?;
+	let diff = json!({"from":study_of(&entry).map(|s|s.to_string()),"to":study.to_string()});
+	let moved = move_all(&[entry], attributes, actor, "move_series", series, diff).await.context(ctx)?;
+	info!("moved {series} ({} instances) into {study}", moved.len());
+	Ok(moved)
+}
+
+/// Moves all series of `source` into the study `target`.
+///
+/// See [move_series]. The source study is removed afterward.
+pub async fn merge_studies(target:&RecordId, source:&RecordId, actor:&Actor) -> Result<Vec<RecordId>>
+{
+	let ctx = format!("merging {source} into {target}");
+	if target == source {
+		return Err(InvalidChange {tag:source.to_string(),reason:"a study cannot be merged into itself".into()}.context(ctx));
+	}
+	get(target, "studies").await.context(ctx.clone())?;
+	get(source, "studies").await.context(ctx.clone())?;
+	let attributes = study_attributes(target).await.context(ctx.clone())?;
+	let series = entries_for_record(source, "series").await.context(ctx.clone())?;
+	let diff = json!({"merged":source.to_string(),"series":series.iter().map(|s|s.id().to_string()).collect::<Vec<_>>()});
+	let moved = move_all(&series, attributes, actor, "merge_studies", target, diff).await.context(ctx)?;
+	info!("merged {source} ({} instances) into {target}", moved.len());
+	Ok(moved)
+}
+
+/// Moves the given series of a study into a new study.
+///
+/// The new study gets a newly generated StudyInstanceUID, all other attributes are kept.
+///
+/// returns: the id of the new study
+pub async fn split_study(study:&RecordId, series:&[RecordId], actor:&Actor) -> Result<RecordId>
+{
+	let ctx = format!("splitting {study}");
+	get(study, "studies").await.context(ctx.clone())?;
+	let mut entries = Vec::with_capacity(series.len());
+	for s in series {
+		let entry = get(s, "series").await.context(ctx.clone())?;
+		if study_of(&entry).as_ref() != Some(study) {
+			return Err(InvalidChange {tag:s.to_string(),reason:format!("it is not part of {study}")}.context(ctx));
+		}
+		entries.push(entry);
+	}
+	// UUID derived UID as in PS3.5 B.2
+	let uid = format!("2.25.{}",rand::random::<u128>());
+	let new_study = RecordId::from_study(uid.as_str());
+	let mut attributes = study_attributes(study).await.context(ctx.clone())?;
+	attributes[0].1 = Some(InMemElement::new(tags::STUDY_INSTANCE_UID, dicom::core::VR::UI, uid.as_str()));
+
+	let diff = json!({"new_study":new_study.to_string(),"series":series.iter().map(RecordId::to_string).collect::<Vec<_>>()});
+	let moved = move_all(&entries, attributes, actor, "split_study", study, diff).await.context(ctx)?;
+	info!("moved {} series ({} instances) of {study} into {new_study}", series.len(), moved.len());
+	Ok(new_study)
+}
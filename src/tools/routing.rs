@@ -0,0 +1,220 @@
+use crate::config::{self, RoutingRule};
+use crate::db::{self, RecordId, DB};
+use crate::dcm::find_tag;
+use crate::tools::events::{signature, Event, CLIENT};
+use crate::tools::Error::{ForwardFailed, NotFound};
+use crate::tools::{entries_for_record, scu, Context, Result};
+use dicom::object::DefaultDicomObject;
+use serde_json::json;
+use std::sync::{LazyLock, OnceLock};
+use std::time::Duration;
+use surrealdb::method::Transaction;
+use surrealdb::types as db_types;
+use surrealdb::types::SurrealValue;
+use surrealdb::Connection;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+/// a forward waiting in the `routing_queue` table
+#[derive(Debug,Clone,SurrealValue)]
+struct Job
+{
+	id:db_types::RecordId,
+	rule:String,
+	target:db_types::RecordId,
+	peer:Option<String>,
+	webhook:Option<String>,
+	attempts:i64,
+}
+
+static NOTIFY:LazyLock<Notify> = LazyLock::new(Notify::new);
+static WORKER:OnceLock<()> = OnceLock::new();
+
+/// Queues forwards for all rules triggered by the event (called by [crate::tools::events::emit]).
+///
+/// Forwards of stored instances were already queued during their registration (see [enqueue]), they only have to be worked on.
+pub(crate) fn route(event:&Event)
+{
+	let study = match event {
+		Event::InstanceStored {..} => {
+			resume();
+			NOTIFY.notify_one();
+			return
+		}
+		Event::StudyComplete {study} => study.clone(),
+		_ => return
+	};
+	if !config::get().routing.rules.iter().any(|r|r.on == event.name()) {return}
+	tokio::spawn(async move {
+		if let Err(e) = enqueue_study(&study).await {
+			warn!("Failed to queue routing of {study}: {e}");
+		}
+	});
+}
+
+/// Starts working on the queue, including forwards left over from before a restart.
+pub fn resume()
+{
+	WORKER.get_or_init(||{tokio::spawn(work());});
+}
+
+/// the rule matches if the values of all tags match their glob patterns
+fn matches(rule:&RoutingRule, obj:&DefaultDicomObject) -> bool
+{
+	rule.conditions.iter().all(|(name,pattern)|{
+		let (Some(tag), Ok(pattern)) = (find_tag(name), glob::Pattern::new(pattern)) else {
+			warn!("Invalid condition {name} = \"{pattern}\" in routing rule {}",rule.name);
+			return false
+		};
+		let value = obj.element(tag).ok().and_then(|e|e.to_str().ok()).unwrap_or_default();
+		pattern.matches(value.trim_end_matches(['\0',' ']))
+	})
+}
+
+/// Queues forwards for the rules triggered by the event whose conditions match `obj` within the transaction.
+///
+/// The registration calls this with [Event::InstanceStored], so the forwards are queued if and only if the instance is.
+pub(crate) async fn enqueue<C>(event:&Event, obj:&DefaultDicomObject, transaction:&Transaction<C>) -> Result<()> where C:Connection
+{
+	let target = match event {
+		Event::InstanceStored {instance,..} => instance,
+		Event::StudyComplete {study} => study,
+		_ => return Ok(())
+	};
+	for rule in config::get().routing.rules.iter().filter(|r|r.on == event.name() && matches(r, obj)) {
+		// one job per destination, so retries don't repeat successful ones
+		let destinations = [(rule.peer.clone(),None), (None,rule.webhook.clone())];
+		for (peer, webhook) in destinations.into_iter().filter(|(p,w)|p.is_some() || w.is_some()) {
+			transaction.query("CREATE routing_queue SET rule = $rule, target = $target, peer = $peer, webhook = $webhook, attempts = 0, next_try = $now, failed = false")
+				.bind(("rule",rule.name.clone()))
+				.bind(("target",target.0.clone()))
+				.bind(("peer",peer))
+				.bind(("webhook",webhook))
+				.bind(("now",chrono::Utc::now().timestamp())).await?
+				.check().context(format!("queueing {target} for routing rule {}",rule.name))?;
+		}
+		debug!("{target} matches routing rule {}",rule.name);
+	}
+	Ok(())
+}
+
+/// queues the forwards of a completed study, its rules are checked against its first instance
+async fn enqueue_study(study:&RecordId) -> Result<()>
+{
+	let Some(instance) = entries_for_record(study, "instances").await?.into_iter().next() else {return Ok(())};
+	let obj = instance.get_file()?.read().await?;
+	let transaction = DB.clone().begin().await?;
+	if let Err(e) = enqueue(&Event::StudyComplete {study:study.clone()}, &obj, &transaction).await {
+		transaction.cancel().await?;
+		return Err(e)
+	}
+	transaction.commit().await?;
+	resume();
+	NOTIFY.notify_one();
+	Ok(())
+}
+
+/// the jobs due now (failed ones are kept for inspection but not retried)
+async fn due() -> Result<Vec<Job>>
+{
+	DB.query("SELECT * FROM routing_queue WHERE failed = false AND next_try <= $now ORDER BY next_try LIMIT 10")
+		.bind(("now",chrono::Utc::now().timestamp())).await?
+		.take::<Vec<Job>>(0)
+		.context("looking for due forwards")
+}
+
+async fn work()
+{
+	loop {
+		match due().await {
+			Ok(jobs) if !jobs.is_empty() => {
+				for job in jobs {
+					if let Err(e) = process(job).await {
+						warn!("Failed to update the routing queue: {e}");
+					}
+				}
+				continue
+			}
+			Ok(_) => {},
+			Err(e) => warn!("{e}"),
+		}
+		// wait for new jobs or retries becoming due
+		let _ = tokio::time::timeout(Duration::from_secs(1), NOTIFY.notified()).await;
+	}
+}
+
+async fn process(job:Job) -> Result<()>
+{
+	let cfg = &config::get().routing;
+	let target = RecordId(job.target.clone());
+	let forwarded = match cfg.rules.iter().find(|r|r.name == job.rule) {
+		Some(rule) => forward(rule, &job, &target).await,
+		None => Err(ForwardFailed {destination:job.rule.clone(), reason:"the routing rule doesn't exist anymore".into()})
+	};
+	match forwarded {
+		Ok(()) => {
+			info!("forwarded {target} according to {}",job.rule);
+			DB.query("DELETE $job").bind(("job",job.id)).await?.check()?;
+		}
+		Err(e) => {
+			let attempts = job.attempts + 1;
+			let failed = attempts >= cfg.max_attempts as i64;
+			let delay = cfg.retry_delay.saturating_mul(1 << (attempts - 1).min(16)) as i64;
+			if failed {
+				warn!("giving up forwarding {target} according to {}: {e}",job.rule);
+			} else {
+				warn!("forwarding {target} according to {} failed (attempt {attempts}): {e}",job.rule);
+			}
+			DB.query("UPDATE $job SET attempts = $attempts, failed = $failed, error = $error, next_try = $next")
+				.bind(("job",job.id))
+				.bind(("attempts",attempts))
+				.bind(("failed",failed))
+				.bind(("error",e.to_string()))
+				.bind(("next",chrono::Utc::now().timestamp() + delay)).await?
+				.check()?;
+		}
+	}
+	Ok(())
+}
+
+async fn forward(rule:&RoutingRule, job:&Job, target:&RecordId) -> Result<()>
+{
+	if let Some(peer) = &job.peer {
+		let addr = config::get().dimse.as_ref().and_then(|d|d.peers.get(peer))
+			.ok_or(ForwardFailed {destination:peer.clone(), reason:"it is not in [dimse.peers]".into()})?;
+		let entry = db::lookup(target).await?.ok_or(NotFound).context(format!("looking up {target} for forwarding"))?;
+		let files = entry.get_files().await?.iter().map(|f|f.get_path()).collect();
+		scu::store(peer, *addr, files).await?;
+	}
+	if let Some(url) = &job.webhook {
+		let body = json!({"rule":rule.name,"event":rule.on,"timestamp":chrono::Utc::now().to_rfc3339(),"target":target.str_path()}).to_string();
+		let mut request = CLIENT.post(url)
+			.header(reqwest::header::CONTENT_TYPE, "application/json")
+			.header("X-Rudicom-Event", rule.on.as_str());
+		if let Some(secret) = &rule.secret {
+			request = request.header("X-Rudicom-Signature", signature(secret, &body));
+		}
+		request.body(body).send().await
+			.and_then(|r|r.error_for_status())
+			.map_err(|e|ForwardFailed {destination:url.clone(), reason:e.to_string()})?;
+	}
+	Ok(())
+}
+
+/// Lists the queued forwards (including failed ones).
+pub async fn queue() -> Result<Vec<db_types::Value>>
+{
+	DB.query("SELECT * FROM routing_queue ORDER BY next_try")
+		.await?.take::<Vec<db_types::Value>>(0)
+		.context("listing the routing queue")
+}
+
+/// Makes failed forwards due again.
+pub async fn retry_failed() -> Result<()>
+{
+	DB.query("UPDATE routing_queue SET failed = false, attempts = 0, next_try = $now WHERE failed = true")
+		.bind(("now",chrono::Utc::now().timestamp())).await?
+		.check()?;
+	NOTIFY.notify_one();
+	Ok(())
+}
@@ -0,0 +1,118 @@
+use crate::config;
+use crate::tools::{gateway, tls};
+use crate::tools::Error::{DicomError, ForwardFailed};
+use crate::tools::Result;
+use dicom::core::{dicom_value, DataElement, PrimitiveValue, VR};
+use dicom::dictionary_std::tags;
+use dicom::object::InMemDicomObject;
+use dicom::transfer_syntax::entries::IMPLICIT_VR_LITTLE_ENDIAN;
+use dicom::ul::pdu::{PDataValue, PDataValueType, PresentationContextResultReason};
+use dicom::ul::{ClientAssociationOptions, Pdu};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tracing::debug;
+
+/// calling AE title if there is no `[dimse]` config
+const DEFAULT_AET:&str = "RUDICOM";
+
+fn uid(uid:&str) -> String {uid.trim_end_matches(['\0',' ']).to_string()}
+
+/// Sends the files to the peer `called` at `addr` via C-STORE within one association.
+///
+/// The data is sent in the transfer syntax it is stored in, so the peer has to accept that.
+/// If `[dimse.tls]` is configured the connection goes through a local TLS proxy (like the SCP).
+pub async fn store(called:&str, addr:SocketAddr, files:Vec<PathBuf>) -> Result<()>
+{
+	let dimse = config::get().dimse.as_ref();
+	let calling = dimse.map_or(DEFAULT_AET, |d|d.aet.as_str()).to_string();
+	let proxy = match dimse.and_then(|d|d.tls.as_ref()) {
+		Some(cfg) => {
+			let listener = gateway::local_listener()?;
+			let local = listener.local_addr()?;
+			Some((local, tokio::spawn(tls::originate(listener, tls::connector(cfg)?, addr))))
+		}
+		None => None
+	};
+	let target = proxy.as_ref().map_or(addr, |(local,_)|*local);
+	let called = called.to_string();
+	let sent = tokio::task::spawn_blocking(move ||send(&calling, &called, target, &files)).await;
+	if let Some((_,task)) = proxy {
+		task.abort();
+	}
+	sent?
+}
+
+fn send(calling:&str, called:&str, addr:SocketAddr, files:&[PathBuf]) -> Result<()>
+{
+	let failed = |reason:String| ForwardFailed {destination:called.to_string(), reason};
+	let objs = files.iter()
+		.map(|f|dicom::object::open_file(f).map_err(|e|DicomError(e.into())))
+		.collect::<Result<Vec<_>>>()?;
+
+	let mut options = ClientAssociationOptions::new()
+		.calling_ae_title(calling.to_string())
+		.called_ae_title(called.to_string());
+	let mut contexts = vec![];
+	for obj in &objs {
+		let context = (uid(obj.meta().media_storage_sop_class_uid()), uid(obj.meta().transfer_syntax()));
+		if !contexts.contains(&context) {
+			options = options.with_presentation_context(context.0.clone(), vec![context.1.clone()]);
+			contexts.push(context);
+		}
+	}
+	let mut association = options.establish(addr).map_err(|e|failed(e.to_string()))?;
+
+	for (message_id, obj) in objs.iter().enumerate() {
+		let (sop_class, transfer_syntax) = (uid(obj.meta().media_storage_sop_class_uid()), uid(obj.meta().transfer_syntax()));
+		let sop_instance = uid(obj.meta().media_storage_sop_instance_uid());
+		let pc = association.presentation_contexts().iter()
+			.find(|pc|pc.reason == PresentationContextResultReason::Acceptance
+				&& uid(&pc.abstract_syntax) == sop_class && uid(&pc.transfer_syntax) == transfer_syntax)
+			.map(|pc|pc.id)
+			.ok_or_else(||failed(format!("{sop_class} in {transfer_syntax} was not accepted")))?;
+
+		let command = InMemDicomObject::command_from_element_iter([
+			DataElement::new(tags::AFFECTED_SOP_CLASS_UID, VR::UI, PrimitiveValue::from(sop_class.as_str())),
+			DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0001])), // C-STORE-RQ
+			DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [(message_id % 0xFFFF) as u16 + 1])),
+			DataElement::new(tags::PRIORITY, VR::US, dicom_value!(U16, [0x0000])),
+			DataElement::new(tags::COMMAND_DATA_SET_TYPE, VR::US, dicom_value!(U16, [0x0000])),
+			DataElement::new(tags::AFFECTED_SOP_INSTANCE_UID, VR::UI, PrimitiveValue::from(sop_instance.as_str())),
+		]);
+		let mut data = vec![];
+		command.write_dataset_with_ts(&mut data, &IMPLICIT_VR_LITTLE_ENDIAN.erased())
+			.map_err(|e|DicomError(e.into()))?;
+		association.send(&Pdu::PData {data:vec![PDataValue{
+			presentation_context_id:pc,
+			value_type:PDataValueType::Command,
+			is_last:true,
+			data
+		}]}).map_err(|e|failed(e.to_string()))?;
+
+		let mut data = vec![];
+		obj.write_dataset(&mut data).map_err(|e|DicomError(e.into()))?;
+		{
+			let mut writer = association.send_pdata(pc);
+			writer.write_all(&data).map_err(|e|failed(e.to_string()))?;
+		} // the last fragment is sent when the writer is dropped
+
+		match association.receive().map_err(|e|failed(e.to_string()))? {
+			Pdu::PData {data} if data.first().is_some_and(|v|v.value_type == PDataValueType::Command) => {
+				let response = InMemDicomObject::read_dataset_with_ts(&data[0].data[..], &IMPLICIT_VR_LITTLE_ENDIAN.erased())
+					.map_err(|e|DicomError(e.into()))?;
+				let status = response.element(tags::STATUS).ok()
+					.and_then(|e|e.to_int::<u16>().ok())
+					.ok_or_else(||failed("C-STORE response without status".into()))?;
+				// 0x0000 is success, 0xBxxx are warnings
+				if status != 0 && status & 0xF000 != 0xB000 {
+					return Err(failed(format!("{sop_instance} was rejected with status {status:#06x}")));
+				}
+				debug!("sent {sop_instance} to {called}");
+			}
+			_ => return Err(failed("unexpected answer to C-STORE".into()))
+		}
+	}
+	association.release().map_err(|e|failed(e.to_string()))?;
+	Ok(())
+}
@@ -1,8 +1,8 @@
 use std::ffi::CString;
 use crate::db::RegisterResult::AlreadyStored;
-use crate::db::{lookup, File, FileInfo, RegisterResult, Session};
+use crate::db::{lookup, File, FileInfo, Provenance, RegisterResult, Session};
 use crate::storage::async_store;
-use crate::tools::{Context, Error};
+use crate::tools::{quarantine, Context, Error};
 use crate::{db, tools, config};
 use dicom::object::DefaultDicomObject;
 use std::path::{Path, PathBuf};
@@ -20,7 +20,7 @@ pub fn is_storage<T:AsRef<Path>>(path:T) -> bool
 /// Stores a dicom object as a file and registers it as owned (might change data).
 /// 
 /// If the object already exists, the store is aborted but considered successful if existing data are equal.
-pub async fn store_ob<S>(mut obj:DefaultDicomObject, session: &mut S) -> tools::Result<RegisterResult> where S:Session<Any>
+pub async fn store_ob<S>(mut obj:DefaultDicomObject, provenance:&Provenance, session: &mut S) -> tools::Result<RegisterResult> where S:Session<Any>
 {
 	if !config::get().filters.is_empty(){
 		Python::attach::<_,tools::Result<()>>(|py| {
@@ -35,15 +35,19 @@ pub async fn store_ob<S>(mut obj:DefaultDicomObject, session: &mut S) -> tools::
 			Ok(())
 		})?;
 	}
-	db::register_instance(Arc::new(obj), &mut FileInfo::Store, session).await
+	let obj = Arc::new(obj);
+	match db::register_instance(obj.clone(), &mut FileInfo::Store, provenance, session).await {
+		Err(e) => Err(quarantine::on_conflict(obj, e, provenance).await),
+		registered => registered
+	}
 }
 
 /// Stores given dicom file as file (makes a copy) and registers it as owned (might change data).
 /// 
 /// If the object already exists, the store is aborted but considered successful if existing data are equal.
-pub async fn store_file<S>(filename:PathBuf, session: &mut S) -> tools::Result<RegisterResult> where S:Session<Any>
+pub async fn store_file<S>(filename:PathBuf, provenance:&Provenance, session: &mut S) -> tools::Result<RegisterResult> where S:Session<Any>
 {
-	store_ob(async_store::read(&filename).await?, session).await
+	store_ob(async_store::read(&filename).await?, provenance, session).await
 }
 
 /// Registers an existing file without storing (data won't be changed).
@@ -51,28 +55,33 @@ pub async fn store_file<S>(filename:PathBuf, session: &mut S) -> tools::Result<R
 /// If the data already exists, the store is aborted but considered successful if existing data are equal.
 /// 
 /// If the existing data has a different checksum, an error is returned
-pub async fn import_file<S>(path:&Path, session: &mut S) -> tools::Result<RegisterResult> where S: Session<Any>
+pub async fn import_file<S>(path:&Path, provenance:&Provenance, session: &mut S) -> tools::Result<RegisterResult> where S: Session<Any>
 {
 	let (info,obj) = File::new_from_existing(path,is_storage(path)).await?;
-	import_file_ob(info, obj, session).await
+	import_file_ob(info, obj, provenance, session).await
 }
 
-pub async fn import_file_ob<S>(info:File,obj:DefaultDicomObject, session: &mut S) -> tools::Result<RegisterResult> where S:Session<Any>
+pub async fn import_file_ob<S>(info:File,obj:DefaultDicomObject, provenance:&Provenance, session: &mut S) -> tools::Result<RegisterResult> where S:Session<Any>
 {
 	let my_md5= info.get_md5().to_string();
-	let registered=db::register_instance(Arc::new(obj),&mut FileInfo::Exists(info), session).await;
-	let registered = registered?;
+	let obj = Arc::new(obj);
+	let registered=db::register_instance(obj.clone(),&mut FileInfo::Exists(info), provenance, session).await;
+	let registered = match registered {
+		Err(e) => return Err(quarantine::on_conflict(obj, e, provenance).await),
+		Ok(registered) => registered
+	};
 	if let AlreadyStored(existing) = &registered //if register says equal data exist, we check md5sum
 	{
 		let existing_file = lookup(existing).await?
 			.expect("existing entry should exist").get_file()?;
 		let existing_md5 = existing_file.get_md5();
 		if existing_md5 != my_md5 {
-			return Err(Error::Md5Conflict {
+			let conflict = Error::Md5Conflict {
 				existing_md5:existing_md5.to_string(),
 				existing_id:existing.clone(),
 				my_md5:my_md5.to_string(),
-			})
+			};
+			return Err(quarantine::on_conflict(obj, conflict, provenance).await)
 		}
 	}
 	Ok(registered)
@@ -84,12 +93,16 @@ pub async fn import_file_ob<S>(info:File,obj:DefaultDicomObject, session: &mut S
 /// If the data already exists, the store is aborted but considered successful if existing data are equal.
 ///
 /// If the existing data has a different checksum, an error is returned
-pub async fn move_file_ob<S>(info: File, obj: DefaultDicomObject, session: &mut S) -> tools::Result<RegisterResult> where S:Session<Any>
+pub async fn move_file_ob<S>(info: File, obj: DefaultDicomObject, provenance:&Provenance, session: &mut S) -> tools::Result<RegisterResult> where S:Session<Any>
 {
 	if info.owned { // if the file is already owned just import it
-		import_file_ob(info,obj, session).await
+		import_file_ob(info,obj, provenance, session).await
 	} else { // if not, store (aka copy) file and delete the source once we're done
-		let stored = db::register_instance(obj, &mut FileInfo::Store, session).await?;
+		let obj = Arc::new(obj);
+		let stored = match db::register_instance(obj.clone(), &mut FileInfo::Store, provenance, session).await {
+			Err(e) => return Err(quarantine::on_conflict(obj, e, provenance).await),
+			Ok(stored) => stored
+		};
 		if let RegisterResult::Stored(_) = stored { //no error and no previously existing file, we can delete the source
 			tokio::fs::remove_file(info.get_path()).await.context(format!("moving file {}", info.get_path().display()))?;
 		}
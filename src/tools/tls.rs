@@ -0,0 +1,162 @@
+use crate::config::TlsCfg;
+use crate::tools::Error::ParseError;
+use crate::tools::{Context, Result};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, warn};
+
+const HANDSHAKE_TIMEOUT:Duration = Duration::from_secs(10);
+
+fn parse_error<E>(path:&Path, error:E) -> crate::tools::Error where E:std::error::Error + Send + Sync + 'static
+{
+	ParseError {to_parse:path.display().to_string(),source:Box::new(error)}
+}
+
+fn certs(path:&Path) -> Result<Vec<CertificateDer<'static>>>
+{
+	CertificateDer::pem_file_iter(path)
+		.and_then(|certs|certs.collect::<std::result::Result<Vec<_>,_>>())
+		.map_err(|e|parse_error(path, e))
+}
+
+fn key(path:&Path) -> Result<PrivateKeyDer<'static>>
+{
+	PrivateKeyDer::from_pem_file(path).map_err(|e|parse_error(path, e))
+}
+
+fn roots(cfg:&TlsCfg) -> Result<Arc<RootCertStore>>
+{
+	let path = cfg.ca.as_ref()
+		.ok_or(ParseError {to_parse:"tls config".into(),source:"ca is needed to verify peers".into()})?;
+	let mut store = RootCertStore::empty();
+	for cert in certs(path)? {
+		store.add(cert).context(format!("adding {} to the trusted certificates",path.display()))?;
+	}
+	Ok(Arc::new(store))
+}
+
+/// Server side TLS with the configured certificate, requiring client certificates if `verify_clients` is set.
+pub fn acceptor(cfg:&TlsCfg) -> Result<TlsAcceptor>
+{
+	let builder = ServerConfig::builder();
+	let builder = if cfg.verify_clients {
+		let verifier = WebPkiClientVerifier::builder(roots(cfg)?).build()
+			.map_err(|e|ParseError {to_parse:"client certificate verifier".into(),source:Box::new(e)})?;
+		builder.with_client_cert_verifier(verifier)
+	} else {
+		builder.with_no_client_auth()
+	};
+	let config = builder.with_single_cert(certs(&cfg.cert)?, key(&cfg.key)?)
+		.context(format!("setting up TLS with {}",cfg.cert.display()))?;
+	Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Client side TLS verifying servers against `ca` and authenticating with the configured certificate.
+pub fn connector(cfg:&TlsCfg) -> Result<TlsConnector>
+{
+	let config = ClientConfig::builder()
+		.with_root_certificates(roots(cfg)?)
+		.with_client_auth_cert(certs(&cfg.cert)?, key(&cfg.key)?)
+		.context(format!("setting up TLS with {}",cfg.cert.display()))?;
+	Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// [axum::serve::Listener] doing the TLS handshake for accepted connections
+///
+/// Each handshake runs in its own task, connections that fail it are dropped.
+pub struct TlsListener
+{
+	local_addr:SocketAddr,
+	handshaken:mpsc::Receiver<(TlsStream<TcpStream>,SocketAddr)>,
+	task:JoinHandle<()>,
+}
+
+impl TlsListener
+{
+	pub fn new(listener:TcpListener, acceptor:TlsAcceptor) -> std::io::Result<TlsListener>
+	{
+		let local_addr = listener.local_addr()?;
+		let (sender, handshaken) = mpsc::channel(64);
+		let task = tokio::spawn(async move {
+			loop {
+				let (stream, addr) = match listener.accept().await {
+					Ok(accepted) => accepted,
+					Err(e) => {
+						// like axum does for plain listeners (e.g. too many open files)
+						warn!("accepting connection failed: {e}");
+						tokio::time::sleep(Duration::from_secs(1)).await;
+						continue
+					}
+				};
+				let (acceptor, sender) = (acceptor.clone(), sender.clone());
+				tokio::spawn(async move {
+					match handshake(&acceptor, stream).await {
+						Ok(stream) => {let _ = sender.send((stream, addr)).await;},
+						Err(e) => debug!("TLS handshake with {addr} failed: {e}"),
+					}
+				});
+			}
+		});
+		Ok(TlsListener{local_addr,handshaken,task})
+	}
+}
+
+impl Drop for TlsListener
+{
+	fn drop(&mut self) {self.task.abort()}
+}
+
+impl axum::serve::Listener for TlsListener
+{
+	type Io = TlsStream<TcpStream>;
+	type Addr = SocketAddr;
+
+	async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+		match self.handshaken.recv().await {
+			Some(accepted) => accepted,
+			None => std::future::pending().await, // the accepting task never stops on its own
+		}
+	}
+
+	fn local_addr(&self) -> std::io::Result<Self::Addr> {
+		Ok(self.local_addr)
+	}
+}
+
+/// server side TLS handshake on an accepted connection
+pub async fn handshake(acceptor:&TlsAcceptor, stream:TcpStream) -> std::io::Result<TlsStream<TcpStream>>
+{
+	tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+		.map_err(|_|std::io::Error::from(std::io::ErrorKind::TimedOut))?
+}
+
+/// Accepts one plain connection on `listener` and forwards it via TLS to `peer`.
+///
+/// Used for connections of the SCU to its peers, the certificate of the peer must be valid for its IP address.
+/// The listener is closed as soon as the connection is accepted, so it can't be used by other local processes afterward.
+pub async fn originate(listener:TcpListener, connector:TlsConnector, peer:SocketAddr) -> Result<()>
+{
+	let name = ServerName::IpAddress(peer.ip().into());
+	let (mut plain, _) = listener.accept().await?;
+	drop(listener);
+	let forwarded = async {
+		let mut tls = connector.connect(name, TcpStream::connect(peer).await?).await?;
+		tokio::io::copy_bidirectional(&mut plain, &mut tls).await
+	};
+	if let Err(e) = forwarded.await {
+		warn!("TLS connection to {peer} failed: {e}");
+		return Err(e.into())
+	}
+	Ok(())
+}
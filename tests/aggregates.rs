@@ -0,0 +1,82 @@
+use crate::common::dcm::{bulk_insert, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db_with;
+use dicom::core::{DataElement, PrimitiveValue, VR};
+use dicom::dictionary_std::tags;
+use rudicom::db::audit::Actor;
+use rudicom::db::{lookup, lookup_uid, RecordId, RegisterResult};
+use rudicom::tools::correct::correct;
+use rudicom::tools::conv::value_to_json;
+use rudicom::tools::remove::remove;
+use rudicom::tools::reorganize::split_study;
+use serde_json::json;
+use surrealdb::types::ToSql;
+
+mod common;
+
+#[tokio::test]
+async fn aggregates() -> Result<(), Box<dyn std::error::Error>>
+{
+	let config = std::env::temp_dir().join("rudicom_aggregates.toml");
+	std::fs::write(&config, r#"
+		[instance_tags]
+		EchoTime = ["EchoTime"]
+		AcquisitionTime = ["AcquisitionTime"]
+		[series_tags]
+		Modality = ["Modality"]
+		[series_aggregates]
+		EchoTimes = {of = "EchoTime", op = "count_distinct"}
+		FirstAcquisition = {of = "AcquisitionTime", op = "min"}
+		Duration = {of = "AcquisitionTime", op = "duration"}
+		[study_aggregates]
+		ModalitiesInStudy = {of = "Modality", op = "distinct"}
+		Echoes = {of = "EchoTime", op = "count"}
+	"#)?;
+	init_db_with(Some(config)).await?.health().await?;
+	let uid_gen = UidSynthesizer::default();
+
+	let mut instances = vec![];
+	for (number, echo, time) in [(1,"10","120000"),(2,"20","120010"),(3,"20","120020")] {
+		let mut obj = synthesize_dicom_obj(&uid_gen, 1, 1, number);
+		obj.put(DataElement::new(tags::ECHO_TIME, VR::DS, PrimitiveValue::from(echo)));
+		obj.put(DataElement::new(tags::ACQUISITION_TIME, VR::TM, PrimitiveValue::from(time)));
+		instances.push(obj);
+	}
+	let mut ct = synthesize_dicom_obj(&uid_gen, 1, 2, 1);
+	ct.put(DataElement::new(tags::MODALITY, VR::CS, PrimitiveValue::from("CT")));
+	instances.push(ct);
+	for stored in bulk_insert(instances.iter()).await? {
+		assert!(matches!(stored, RegisterResult::Stored(_)), "unexpected initial store: {stored:?}");
+	}
+
+	let series = lookup_uid("series", uid_gen.series(1, 1)).await?.expect("series should exist");
+	assert_eq!(series.get("EchoTimes").cloned().map(value_to_json), Some(json!(2)));
+	let first = lookup_uid("instances", uid_gen.instance(1, 1, 1)).await?.expect("instance should exist");
+	assert_eq!(series.get("FirstAcquisition"), first.get("AcquisitionTime"));
+	assert_eq!(series.get("Duration").map(|d|d.to_sql()), Some("20s".to_string()));
+
+	let study = lookup_uid("studies", uid_gen.study(1)).await?.expect("study should exist");
+	assert_eq!(study.get("ModalitiesInStudy").cloned().map(value_to_json), Some(json!(["CT","MR"])));
+	assert_eq!(study.get("Echoes").cloned().map(value_to_json), Some(json!(3)));
+
+	// removing data updates the aggregates of what's left
+	remove(&RecordId::from_series(uid_gen.series(1, 2).as_str())).await?;
+	let study = lookup_uid("studies", uid_gen.study(1)).await?.expect("study should still exist");
+	assert_eq!(study.get("ModalitiesInStudy").cloned().map(value_to_json), Some(json!(["MR"])));
+
+	// so do corrections
+	let first = RecordId::from_instance(uid_gen.instance(1, 1, 1).as_str());
+	correct(&first, vec![(tags::ECHO_TIME, Some("20".into()))], &Actor::local()).await?;
+	let series = lookup_uid("series", uid_gen.series(1, 1)).await?.expect("series should exist");
+	assert_eq!(series.get("EchoTimes").cloned().map(value_to_json), Some(json!(1)));
+
+	// and moving series into another study
+	bulk_insert(instances[3..].iter()).await?;
+	let study_id = RecordId::from_study(uid_gen.study(1).as_str());
+	let new_study = split_study(&study_id, &[RecordId::from_series(uid_gen.series(1, 2).as_str())], &Actor::local()).await?;
+	let study = lookup_uid("studies", uid_gen.study(1)).await?.expect("study should still exist");
+	assert_eq!(study.get("ModalitiesInStudy").cloned().map(value_to_json), Some(json!(["MR"])));
+	assert_eq!(study.get("Echoes").cloned().map(value_to_json), Some(json!(3)));
+	let new_study = lookup(&new_study).await?.expect("the new study should exist");
+	assert_eq!(new_study.get("ModalitiesInStudy").cloned().map(value_to_json), Some(json!(["CT"])));
+	Ok(())
+}
@@ -0,0 +1,41 @@
+use crate::common::init_db_with;
+use rudicom::db::audit::{self, Actor, AuditFilter};
+use rudicom::db::RecordId;
+use rudicom::tools::Error;
+use tokio::net::UdpSocket;
+
+mod common;
+
+#[tokio::test]
+async fn audit_log() -> Result<(), Box<dyn std::error::Error>>
+{
+	let syslog = UdpSocket::bind("127.0.0.1:0").await?;
+	let config = std::env::temp_dir().join("rudicom_audit.toml");
+	std::fs::write(&config, format!("[audit]\nsyslog = \"{}\"\n", syslog.local_addr()?))?;
+	init_db_with(Some(config)).await?.health().await?;
+
+	let study = RecordId::from_study("1.2.3");
+	let actor = Actor::dimse(Some("MODALITY "), Some("10.0.0.1".parse()?));
+	audit::log(&actor, "delete", &study, serde_json::json!({})).await;
+
+	// the DIMSE peer is recorded with its AE title and address
+	let entries = audit::query(Some(&study), &AuditFilter::default()).await?;
+	assert_eq!(entries.len(), 1);
+	assert_eq!(entries[0].actor.user.as_deref(), Some("MODALITY"));
+	assert_eq!(entries[0].actor.source.as_deref(), Some("10.0.0.1"));
+
+	// and exported as "DICOM Study Deleted"
+	let mut buf = vec![0; 4096];
+	let len = tokio::time::timeout(std::time::Duration::from_secs(5), syslog.recv(&mut buf)).await??;
+	let message = String::from_utf8_lossy(&buf[..len]);
+	assert!(message.contains(r#"csd-code="110105""#), "unexpected syslog message {message}");
+	assert!(message.contains(r#"UserID="MODALITY" NetworkAccessPointID="10.0.0.1""#), "unexpected syslog message {message}");
+
+	// timestamps that cannot be parsed are rejected
+	let filter = AuditFilter{since:Some("yesterday".into()),..Default::default()};
+	match audit::query(None, &filter).await {
+		Err(Error::InvalidFilter {name,..}) => assert_eq!(name, "since"),
+		r => panic!("An invalid timestamp should fail with InvalidFilter, got {:?}", r.map(|e|e.len()))
+	}
+	Ok(())
+}
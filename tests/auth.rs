@@ -0,0 +1,80 @@
+use crate::common::init_db_with;
+use base64::Engine;
+use jsonwebtoken::{EncodingKey, Header};
+use rudicom::config::Role;
+use rudicom::db::users;
+use rudicom::server;
+use serde_json::json;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+mod common;
+
+const SECRET:&str = "not so secret";
+
+/// sends a GET request and returns the status code of the response
+async fn get(addr:SocketAddr, path:&str, authorization:Option<String>) -> Result<u16, Box<dyn std::error::Error>>
+{
+	let mut stream = TcpStream::connect(addr).await?;
+	let authorization = authorization.map(|a|format!("Authorization: {a}\r\n")).unwrap_or_default();
+	stream.write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n{authorization}Connection: close\r\n\r\n").as_bytes()).await?;
+	let mut response = String::new();
+	stream.read_to_string(&mut response).await?;
+	let status = response.split(' ').nth(1).ok_or("empty response")?;
+	Ok(status.parse()?)
+}
+
+fn basic(user:&str, password:&str) -> Option<String>
+{
+	Some(format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"))))
+}
+
+fn jwt(role:&str, secret:&str) -> Result<Option<String>, Box<dyn std::error::Error>>
+{
+	let exp = chrono::Utc::now().timestamp() + 600;
+	let claims = json!({"sub":"someone","role":role,"exp":exp});
+	let token = jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()))?;
+	Ok(Some(format!("Bearer {token}")))
+}
+
+#[tokio::test]
+async fn authentication() -> Result<(), Box<dyn std::error::Error>>
+{
+	let key = std::env::temp_dir().join("rudicom_auth.key");
+	std::fs::write(&key, SECRET)?;
+	let config = std::env::temp_dir().join("rudicom_auth.toml");
+	std::fs::write(&config, format!(r#"
+		[auth.tokens]
+		"reader-token" = {{user = "reader", role = "read-only"}}
+		[auth.jwt]
+		key = "{}"
+		algorithm = "HS256"
+	"#, key.display()))?;
+	init_db_with(Some(config)).await?.health().await?;
+	// like the set-user command does for the first admin
+	users::set_user("admin", "correct horse", Role::Admin).await?;
+
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let addr = listener.local_addr()?;
+	tokio::spawn(server::serve(listener));
+
+	// login
+	assert_eq!(get(addr, "/api/info", None).await?, 401, "Requests without credentials should be rejected.");
+	assert_eq!(get(addr, "/api/users", basic("admin", "correct horse")).await?, 200);
+	assert_eq!(get(addr, "/api/users", basic("admin", "wrong")).await?, 401);
+	assert_eq!(get(addr, "/api/users", basic("nobody", "correct horse")).await?, 401);
+
+	// role enforcement
+	let reader = Some("Bearer reader-token".to_string());
+	assert_eq!(get(addr, "/api/info", reader.clone()).await?, 200);
+	assert_eq!(get(addr, "/api/users", reader).await?, 403, "Reading users should need the admin role.");
+	assert_eq!(get(addr, "/api/audit", jwt("uploader", SECRET)?).await?, 403);
+	assert_eq!(get(addr, "/api/audit", jwt("admin", SECRET)?).await?, 200);
+
+	// invalid tokens
+	assert_eq!(get(addr, "/api/info", Some("Bearer unknown-token".into())).await?, 401);
+	assert_eq!(get(addr, "/api/info", jwt("admin", "some other secret")?).await?, 401, "JWTs with a wrong signature should be rejected.");
+	assert_eq!(get(addr, "/api/info", jwt("superuser", SECRET)?).await?, 401, "JWTs without a valid role should be rejected.");
+	Ok(())
+}
@@ -0,0 +1,72 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db_with;
+use dicom::dictionary_std::tags;
+use rudicom::config;
+use rudicom::db::audit::Actor;
+use rudicom::db::{list_collisions, lookup, LocalSession, Provenance, RegisterResult, DB};
+use rudicom::tools::conv::value_to_json;
+use rudicom::tools::correct::correct;
+use rudicom::tools::store::store_ob;
+
+mod common;
+
+#[tokio::test]
+async fn collisions() -> Result<(), Box<dyn std::error::Error>>
+{
+	let config = std::env::temp_dir().join("rudicom_collisions.toml");
+	std::fs::write(&config, r#"
+		[paths]
+		filename_pattern = "collisions/S{SeriesNumber}.dcm"
+		on_collision = "uid_hash"
+	"#)?;
+	init_db_with(Some(config)).await?.health().await?;
+	let directory = config::get().paths.storage_path.join("collisions");
+	if directory.exists() {
+		std::fs::remove_dir_all(&directory)?;
+	}
+	let mut session = LocalSession::create(&DB, 1);
+	let uid_gen = UidSynthesizer::default();
+
+	// all instances of the series get the same filename
+	let mut ids = vec![];
+	let mut paths = vec![];
+	for number in 1..=2 {
+		let obj = synthesize_dicom_obj(&uid_gen, 1, 1, number);
+		let RegisterResult::Stored(id) = store_ob(obj, &Provenance::local(), &mut session).await?
+		else { panic!("Colliding instances should still be stored."); };
+		let file = lookup(&id).await?.expect("stored instance should be found").get_file()?;
+		assert!(file.read().await.is_ok(), "The stored file should be readable.");
+		paths.push(file.get_path());
+		ids.push(id);
+	}
+	let hash = format!("{:x}", md5::compute(uid_gen.instance(1, 1, 2)));
+	assert_eq!(paths[0], directory.join("S1.dcm"));
+	assert_eq!(paths[1], directory.join(format!("S1_{}.dcm", &hash[..8])));
+
+	let report:Vec<_> = list_collisions().await?.into_iter().map(value_to_json).collect();
+	assert_eq!(report.len(), 1, "Only the second instance should be reported.");
+	assert_eq!(report[0]["intended"], directory.join("S1.dcm").to_string_lossy().as_ref());
+	assert_eq!(report[0]["path"], paths[1].to_string_lossy().as_ref());
+
+	// a correction keeps the suffixed filename
+	correct(&ids[1], vec![(tags::IMAGE_COMMENTS, Some("checked".into()))], &Actor::local()).await?;
+	let file = lookup(&ids[1]).await?.expect("corrected instance should be found").get_file()?;
+	assert_eq!(file.get_path(), paths[1]);
+	assert_eq!(file.read().await?.element(tags::IMAGE_COMMENTS)?.to_str()?, "checked");
+
+	// corrections changing the filename avoid collisions like new instances
+	correct(&ids[0], vec![(tags::SERIES_NUMBER, Some("2".into()))], &Actor::local()).await?;
+	let mut renamed = vec![];
+	for (id, number) in ids.iter().zip(1..=2) {
+		let file = lookup(id).await?.expect("corrected instance should be found").get_file()?;
+		assert!(file.read().await.is_ok(), "The corrected file should be readable.");
+		let hash = format!("{:x}", md5::compute(uid_gen.instance(1, 1, number)));
+		let path = file.get_path();
+		assert!(path == directory.join("S2.dcm") || path == directory.join(format!("S2_{}.dcm", &hash[..8])), "{} is not what the pattern gives", path.display());
+		renamed.push(path);
+	}
+	assert_ne!(renamed[0], renamed[1]);
+	assert!(!paths.iter().any(|p|p.exists()), "The files with the old names should be gone.");
+
+	cleanup().await.map_err(|e| e.into())
+}
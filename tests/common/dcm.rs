@@ -5,7 +5,7 @@ use dicom::object::{FileDicomObject, FileMetaTableBuilder, InMemDicomObject};
 use rudicom::{db, tools};
 use rudicom::tools::remove::remove;
 use rudicom::tools::store::store_ob;
-use rudicom::db::{RegisterResult, Session, SharedSession, DB};
+use rudicom::db::{Provenance, RegisterResult, Session, SharedSession, DB};
 use std::time::SystemTime;
 use surrealdb::engine::any::Any;
 use tokio::task::JoinSet;
@@ -106,7 +106,7 @@ pub async fn bulk_insert(instances:impl Iterator<Item=&FileDicomObject<InMemDico
 	{
 		if let Some(obj) = instances.next() {
 			let mut session = session.clone();
-			tasks.spawn(async move { store_ob(obj,&mut session).await });
+			tasks.spawn(async move { store_ob(obj,&Provenance::local(),&mut session).await });
 		} else {break} //abort if we already run out of instances
 	}
 	// take out the next finished import and thus drain the task list
@@ -115,7 +115,7 @@ pub async fn bulk_insert(instances:impl Iterator<Item=&FileDicomObject<InMemDico
 		// we finished one store task, add another one as long as we have them
 		if let Some(obj) = instances.next() {
 			let mut session = session.clone();
-			tasks.spawn(async move{ store_ob(obj,&mut session).await });
+			tasks.spawn(async move{ store_ob(obj,&Provenance::local(),&mut session).await });
 		}
 	}
 	Ok(ret)
@@ -130,3 +130,36 @@ pub async fn cleanup() -> rudicom::tools::Result<()>
 	}
 	Ok(())
 }
+
+/// builds a CSA header with the given elements (name, vr, vm, items)
+pub fn synthesize_csa(version:u8, elements:&[(&str,&str,i32,&[&str])]) -> Vec<u8>
+{
+	let mut data = vec![];
+	if version == 2 {
+		data.extend_from_slice(b"SV10\x04\x03\x02\x01");
+	}
+	data.extend_from_slice(&(elements.len() as u32).to_le_bytes());
+	data.extend_from_slice(&77u32.to_le_bytes());
+	for (name,vr,vm,items) in elements {
+		let mut field = [0u8;64];
+		field[..name.len()].copy_from_slice(name.as_bytes());
+		data.extend_from_slice(&field);
+		data.extend_from_slice(&vm.to_le_bytes());
+		let mut field = [0u8;4];
+		field[..vr.len()].copy_from_slice(vr.as_bytes());
+		data.extend_from_slice(&field);
+		data.extend_from_slice(&0i32.to_le_bytes()); // SyngoDT
+		data.extend_from_slice(&(items.len() as i32).to_le_bytes());
+		data.extend_from_slice(&77i32.to_le_bytes());
+		for item in items.iter() {
+			let len = item.len() as i32 + 1;
+			let first = if version == 1 {len + elements.len() as i32} else {len};
+			for l in [first, len, 77, len] {
+				data.extend_from_slice(&l.to_le_bytes());
+			}
+			data.extend_from_slice(item.as_bytes());
+			data.resize(data.len() + (len as usize).next_multiple_of(4) - item.len(), 0);
+		}
+	}
+	data
+}
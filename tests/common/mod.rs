@@ -4,12 +4,19 @@ pub mod dcm;
 use rudicom::config;
 use rudicom::db;
 use std::ops::Deref;
+use std::path::PathBuf;
 use surrealdb::engine::any::Any;
 use surrealdb::Surreal;
 
 pub async fn init_db() -> Result<&'static Surreal<Any>, Box<dyn std::error::Error>>
 {
-	init_config()?;
+	init_db_with(None).await
+}
+
+/// like [init_db] but with the given config file (on top of the defaults)
+pub async fn init_db_with(config_file:Option<PathBuf>) -> Result<&'static Surreal<Any>, Box<dyn std::error::Error>>
+{
+	init_config_with(config_file)?;
 	db::init_local("memory").await?;
 	db::DB.use_ns("namespace").use_db("database").await
 		.map_err(|e|format!("Selecting database and namespace failed: {e}"))?;
@@ -19,6 +26,10 @@ pub async fn init_db() -> Result<&'static Surreal<Any>, Box<dyn std::error::Erro
 }
 
 pub fn init_config() -> Result<(), Box<dyn std::error::Error>> {
+	init_config_with(None)
+}
+
+pub fn init_config_with(config_file:Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
 	// create a storage path where the default config would expect it
 	let storage_path = std::env::temp_dir().join("db_store");
 	println!("Using {}",storage_path.display());
@@ -26,7 +37,7 @@ pub fn init_config() -> Result<(), Box<dyn std::error::Error>> {
 		std::fs::create_dir(&storage_path)?;
 	};
 
-	config::init(None)?;
+	config::init(config_file)?;
 
 	let storage_path = &config::get().paths.storage_path;
 	if !storage_path.is_absolute(){
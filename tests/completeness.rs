@@ -0,0 +1,49 @@
+use crate::common::dcm::{cleanup, synthesize_series, UidSynthesizer};
+use crate::common::init_db_with;
+use rudicom::db::{lookup, LocalSession, Provenance, RecordId, RegisterResult, DB};
+use rudicom::tools::completeness::{COMPLETE, RECEIVING};
+use rudicom::tools::events::{subscribe, Event};
+use rudicom::tools::store::store_ob;
+use std::time::Duration;
+
+mod common;
+
+async fn state(id:&RecordId) -> Result<Option<String>, Box<dyn std::error::Error>>
+{
+	let entry = lookup(id).await?.expect("entry should exist");
+	Ok(entry.get_string("state").map(str::to_string))
+}
+
+#[tokio::test]
+async fn completeness() -> Result<(), Box<dyn std::error::Error>>
+{
+	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+	let config = std::env::temp_dir().join("rudicom_completeness.toml");
+	std::fs::write(&config, "[completeness]\nseries_timeout = 1\nstudy_timeout = 2\n")?;
+	init_db_with(Some(config)).await?.health().await?;
+	let mut receiver = subscribe();
+	let uid_gen = UidSynthesizer::default();
+	let mut session = LocalSession::create(&DB, 1);
+	for obj in synthesize_series(&uid_gen, 1, 1, 3) {
+		let RegisterResult::Stored(_) = store_ob(obj, &Provenance::local(), &mut session).await?
+		else { panic!("Initial store should return stored."); };
+	}
+	let series = RecordId::from_series(uid_gen.series(1, 1));
+	let study = RecordId::from_study(uid_gen.study(1));
+	tokio::time::sleep(Duration::from_millis(200)).await;
+	assert_eq!(state(&series).await?.as_deref(), Some(RECEIVING));
+
+	let mut completed = vec![];
+	while completed.len() < 2 {
+		match tokio::time::timeout(Duration::from_secs(10), receiver.recv()).await?? {
+			Event::SeriesComplete {series,..} => completed.push(series),
+			Event::StudyComplete {study} => completed.push(study),
+			_ => {}
+		}
+	}
+	assert_eq!(completed, [series.clone(), study.clone()], "The series should be completed before the study.");
+	assert_eq!(state(&series).await?.as_deref(), Some(COMPLETE));
+	assert_eq!(state(&study).await?.as_deref(), Some(COMPLETE));
+
+	cleanup().await.map_err(|e| e.into())
+}
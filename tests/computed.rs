@@ -0,0 +1,66 @@
+use crate::common::dcm::{synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_config_with;
+use dicom::core::{DataElement, PrimitiveValue, VR};
+use dicom::dictionary_std::tags;
+use rudicom::dcm::{extract, gen_filepath, AttributeSelector, INSTANCE_TAGS};
+use rudicom::tools::conv::value_to_json;
+use serde_json::json;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+mod common;
+
+#[test]
+fn computed() -> Result<(), Box<dyn std::error::Error>>
+{
+	let config = std::env::temp_dir().join("rudicom_computed.toml");
+	std::fs::write(&config, r#"
+		[paths]
+		filename_pattern = "{Run}/{SOPInstanceUID}.ima"
+		[instance_tags]
+		Age = ["=age(PatientBirthDate, StudyDate)"]
+		Run = ["=capture(SeriesDescription, 'run-(\\d+)')", "=concat('run', SeriesNumber)"]
+	"#)?;
+	init_config_with(Some(config))?;
+
+	let mut obj = synthesize_dicom_obj(&UidSynthesizer::default(), 1, 1, 1);
+	obj.put(DataElement::new(tags::PATIENT_BIRTH_DATE, VR::DA, PrimitiveValue::from("19800102")));
+	obj.put(DataElement::new(tags::SERIES_DESCRIPTION, VR::LO, PrimitiveValue::from("bold run-02")));
+	obj.put(DataElement::new(tags::REPETITION_TIME, VR::DS, PrimitiveValue::from("2500")));
+
+	let selectors = [
+		("age", "=age(PatientBirthDate, StudyDate)"),
+		("label", "=concat(PatientID, '-', SeriesNumber)"),
+		("run", r"=capture(SeriesDescription, 'run-(\d+)')"),
+		("tr", "=convert(RepetitionTime, 'ms', 's')"),
+		("nested", "=concat('TR ', convert(RepetitionTime, 'ms', 's'), 's')"),
+		("missing", "=age(PatientBirthDate, AcquisitionDate)"),
+	];
+	let requested:HashMap<_,_> = selectors.iter()
+		.map(|(name,s)|Ok((name.to_string(), vec![AttributeSelector::from_str(s)?])))
+		.collect::<Result<_,String>>()?;
+	for (name,s) in selectors {
+		assert_eq!(requested[name][0].to_string(), s);
+	}
+	for broken in ["=convert(RepetitionTime, 'ms', 'mm')", "=capture(SeriesDescription, '(')", "=age(PatientBirthDate)", "=concat(#BValue)"] {
+		assert!(AttributeSelector::from_str(broken).is_err(), "{broken} should be rejected");
+	}
+
+	let extracted:HashMap<_,_> = extract(&obj, &requested).into_iter()
+		.map(|(k,v)|(k.to_string(), value_to_json(v)))
+		.collect();
+	assert_eq!(extracted["age"], json!(44)); // 2025-01-01 is just before the 45th birthday
+	assert_eq!(extracted["label"], json!("John_Doe-1"));
+	assert_eq!(extracted["run"], json!("02"));
+	assert_eq!(extracted["tr"], json!(2.5));
+	assert_eq!(extracted["nested"], json!("TR 2.5s"));
+	assert!(!extracted.contains_key("missing"), "Values of missing elements should not be computed.");
+
+	// computed columns are usable in the filename pattern and fall back like any other
+	assert!(INSTANCE_TAGS.contains_key("Age"));
+	let uid = obj.element(tags::SOP_INSTANCE_UID)?.to_str()?.to_string();
+	assert_eq!(gen_filepath(&obj)?, format!("02/{uid}.ima"));
+	obj.put(DataElement::new(tags::SERIES_DESCRIPTION, VR::LO, PrimitiveValue::from("localizer")));
+	assert_eq!(gen_filepath(&obj)?, format!("run1/{uid}.ima"));
+	Ok(())
+}
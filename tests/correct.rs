@@ -0,0 +1,63 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db;
+use dicom::dictionary_std::tags;
+use rudicom::db::audit::{self, Actor, AuditFilter};
+use rudicom::db::{lookup, LocalSession, Provenance, RecordId, RegisterResult, DB};
+use rudicom::tools::correct::correct;
+use rudicom::tools::store::store_ob;
+use rudicom::tools::Error;
+
+mod common;
+
+#[tokio::test]
+async fn correction() -> Result<(), Box<dyn std::error::Error>>
+{
+	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+	init_db().await?.health().await?;
+	let mut session = LocalSession::create(&DB, 1);
+	let uid_gen = UidSynthesizer::default();
+	let obj = synthesize_dicom_obj(&uid_gen, 1, 1, 1);
+
+	let RegisterResult::Stored(id) = store_ob(obj, &Provenance::local(), &mut session).await?
+	else { panic!("First store should return stored."); };
+	let old_file = lookup(&id).await?.expect("stored instance should be found").get_file()?;
+	let RegisterResult::Stored(other) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 2, 1), &Provenance::local(), &mut session).await?
+	else { panic!("Store of the second series should return stored."); };
+	let series = RecordId::from_series(uid_gen.series(1, 1));
+
+	// UIDs cannot be corrected
+	match correct(&id, vec![(tags::SOP_INSTANCE_UID, Some("1.2.3".into()))], &Actor::local()).await {
+		Err(Error::InvalidChange {..}) => {},
+		r => panic!("Correcting a UID should fail with InvalidChange, got {r:?}")
+	}
+
+	let corrected = correct(&series, vec![
+		(tags::PATIENT_NAME, Some("Doe^Jane".into())),
+		(tags::STUDY_ID, None),
+	], &Actor::local()).await?;
+	// patient and study tags are corrected in all series of the study
+	assert_eq!(corrected.len(), 2);
+	assert!(corrected.contains(&id) && corrected.contains(&other));
+	let other_obj = lookup(&other).await?.expect("other instance should be found").get_file()?.read().await?;
+	assert_eq!(other_obj.element(tags::PATIENT_NAME)?.to_str()?, "Doe^Jane");
+
+	let new_file = lookup(&id).await?.expect("corrected instance should be found").get_file()?;
+	new_file.verify().await?;
+	let new_obj = new_file.read().await?;
+	assert_eq!(new_obj.element(tags::PATIENT_NAME)?.to_str()?, "Doe^Jane");
+	assert!(new_obj.element(tags::STUDY_ID).is_err(), "Study ID should have been removed");
+	if new_file.get_path() != old_file.get_path() {
+		assert!(!old_file.get_path().exists(), "The old file should be gone after the correction.");
+	}
+
+	// instance tags only in the given entry
+	let corrected = correct(&series, vec![(tags::IMAGE_COMMENTS, Some("checked".into()))], &Actor::local()).await?;
+	assert_eq!(corrected, vec![id.clone()]);
+
+	let audit = audit::query(Some(&series), &AuditFilter::default()).await?;
+	assert_eq!(audit.len(), 2, "The corrections should be recorded in the audit.");
+	assert_eq!(audit[0].action, "correct");
+	assert_eq!(audit[0].actor.source.as_deref(), Some("local"));
+
+	cleanup().await.map_err(|e| e.into())
+}
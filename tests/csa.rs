@@ -0,0 +1,44 @@
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::InMemDicomObject;
+use rudicom::tools::csa::{headers, CsaHeader, IMAGE_HEADER, CREATOR};
+use crate::common::dcm::synthesize_csa;
+
+mod common;
+
+const ELEMENTS:&[(&str,&str,i32,&[&str])] = &[
+	("EchoLinePosition","IS",1,&["64"]),
+	("NumberOfImagesInMosaic","US",1,&["36"]),
+	("SliceMeasurementDuration","DS",1,&["87500.00000000"]),
+	("MosaicRefAcqTimes","FD",3,&["0","410.5","820","","",""]),
+	("ImaAbsTablePosition","SL",3,&[]),
+	("SequenceMask","CS",1,&["SK"]),
+];
+
+#[test]
+fn csa() -> Result<(), Box<dyn std::error::Error>>
+{
+	for version in [1,2] {
+		let header = CsaHeader::parse(&synthesize_csa(version, ELEMENTS))?;
+		assert_eq!(header.version, version);
+		assert_eq!(header.elements.len(), ELEMENTS.len());
+		assert_eq!(header.value("NumberOfImagesInMosaic")?, Some(PrimitiveValue::from(36_u16)));
+		assert_eq!(header.value("EchoLinePosition")?, Some(PrimitiveValue::from(64_i32)));
+		assert_eq!(header.value("MosaicRefAcqTimes")?, Some(PrimitiveValue::F64(vec![0.0,410.5,820.0].into())));
+		assert_eq!(header.value("SequenceMask")?, Some(PrimitiveValue::Strs(vec!["SK".to_string()].into())));
+		assert_eq!(header.value("ImaAbsTablePosition")?, None);
+		assert_eq!(header.value("DoesNotExist")?, None);
+	}
+
+	// broken data must fail without panicking
+	let data = synthesize_csa(2, ELEMENTS);
+	assert!(CsaHeader::parse(&data[..data.len()/2]).is_err(), "Truncated headers should fail.");
+	assert!(CsaHeader::parse(b"SV10").is_err(), "Truncated headers should fail.");
+
+	let mut obj = InMemDicomObject::new_empty();
+	obj.put(DataElement::new(Tag(0x0029,0x0010), VR::LO, PrimitiveValue::from(CREATOR)));
+	obj.put(DataElement::new(IMAGE_HEADER, VR::OB, PrimitiveValue::from(synthesize_csa(2, ELEMENTS))));
+	let found = headers(&obj)?;
+	assert_eq!(found.len(), 1);
+	assert_eq!(found[0].0, "image");
+	Ok(())
+}
@@ -0,0 +1,119 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db_with;
+use dicom::core::{DataElement, PrimitiveValue, VR};
+use dicom::dictionary_std::tags;
+use dicom::object::InMemDicomObject;
+use dicom::ul::ClientAssociationOptions;
+use dimse::io::FileAccess;
+use futures::StreamExt;
+use rudicom::config;
+use rudicom::db::dimse_access::Accessor;
+use rudicom::tools::gateway;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+mod common;
+
+const CT_IMAGE_STORAGE:&str = "1.2.840.10008.5.1.4.1.1.2";
+const IMPLICIT_VR_LE:&str = "1.2.840.10008.1.2";
+
+fn study_identifier(uid:String) -> InMemDicomObject
+{
+	InMemDicomObject::from_element_iter([
+		DataElement::new(tags::QUERY_RETRIEVE_LEVEL, VR::CS, PrimitiveValue::from("STUDY")),
+		DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, PrimitiveValue::from(uid)),
+	])
+}
+
+fn accessor(calling_ae:&str) -> Accessor
+{
+	Accessor{calling_ae:Some(calling_ae.into()), peer:Some("10.0.0.1:104".parse().unwrap())}
+}
+
+/// opens an association as `calling_ae` and keeps it open until the returned sender is dropped
+async fn associate(calling_ae:&'static str, addr:SocketAddr) -> Result<std::sync::mpsc::Sender<()>, String>
+{
+	let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+	let (established_tx, established_rx) = tokio::sync::oneshot::channel();
+	tokio::task::spawn_blocking(move || {
+		let association = ClientAssociationOptions::new()
+			.calling_ae_title(calling_ae)
+			.called_ae_title("RUDICOM")
+			.with_presentation_context(CT_IMAGE_STORAGE, vec![IMPLICIT_VR_LE])
+			.establish(addr);
+		match association {
+			Ok(association) => {
+				let _ = established_tx.send(Ok(()));
+				let _ = release_rx.recv();
+				let _ = association.release();
+			}
+			Err(e) => {let _ = established_tx.send(Err(e.to_string()));}
+		}
+	});
+	established_rx.await.map_err(|e|e.to_string())?.map(|_|release_tx)
+}
+
+#[tokio::test]
+async fn dimse_access() -> Result<(), Box<dyn std::error::Error>>
+{
+	let config = std::env::temp_dir().join("rudicom_dimse_access.toml");
+	std::fs::write(&config, r#"
+		[dimse]
+		aet = "RUDICOM"
+		address = "127.0.0.1:0"
+		[dimse.peers]
+		ARCHIVE = "127.0.0.1:11112"
+		VIEWER = "127.0.0.1:11113"
+		[dimse.default]
+		store = false
+		query = false
+		move_destinations = []
+		[dimse.access.MODALITY]
+		query = false
+		move_destinations = []
+		max_associations = 1
+		[dimse.access.VIEWER]
+		store = false
+		move_destinations = ["VIEWER"]
+	"#)?;
+	init_db_with(Some(config)).await?.health().await?;
+	let uid_gen = UidSynthesizer::default();
+	let study = || study_identifier(uid_gen.study(1));
+
+	// store
+	assert!(accessor("MODALITY").store_file(synthesize_dicom_obj(&uid_gen, 1, 1, 1)).await.is_ok(), "MODALITY should be allowed to store.");
+	assert!(accessor("VIEWER").store_file(synthesize_dicom_obj(&uid_gen, 1, 1, 2)).await.is_err(), "VIEWER should not be allowed to store.");
+	assert!(accessor("UNKNOWN").store_file(synthesize_dicom_obj(&uid_gen, 1, 1, 3)).await.is_err(), "Unknown AEs should get the default permissions.");
+
+	// find
+	let Ok(found) = accessor("VIEWER").find(study()).await else {panic!("VIEWER should be allowed to query.")};
+	assert_eq!(found.count().await, 1);
+	assert!(accessor("MODALITY").find(study()).await.is_err(), "MODALITY should not be allowed to query.");
+
+	// move
+	let Ok(found) = accessor("VIEWER").lookup(study()).await else {panic!("VIEWER should be allowed to move.")};
+	assert_eq!(found.count().await, 1);
+	assert!(accessor("MODALITY").lookup(study()).await.is_err(), "MODALITY should not be allowed to move.");
+	let dimse = config::get().dimse.as_ref().unwrap();
+	assert_eq!(gateway::move_destinations(dimse, "VIEWER"), vec!["VIEWER"]);
+	assert!(gateway::move_destinations(dimse, "MODALITY").is_empty());
+
+	// associations beyond max_associations are rejected
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let addr = listener.local_addr()?;
+	tokio::spawn(gateway::serve(listener));
+	let first = associate("MODALITY", addr).await?;
+	assert!(associate("MODALITY", addr).await.is_err(), "A second association of MODALITY should be rejected.");
+	let other = associate("VIEWER", addr).await?;
+	drop(first);
+	let mut again = associate("MODALITY", addr).await;
+	for _ in 0..50 {
+		if again.is_ok() {break}
+		tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+		again = associate("MODALITY", addr).await;
+	}
+	assert!(again.is_ok(), "MODALITY should be able to associate again after its association was released.");
+	drop(other);
+
+	cleanup().await.map_err(|e| e.into())
+}
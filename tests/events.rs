@@ -0,0 +1,38 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db;
+use rudicom::db::{LocalSession, Provenance, RecordId, RegisterResult, DB};
+use rudicom::tools::events::{subscribe, Event};
+use rudicom::tools::remove::remove;
+use rudicom::tools::store::store_ob;
+use std::time::Duration;
+
+mod common;
+
+#[tokio::test]
+async fn events() -> Result<(), Box<dyn std::error::Error>>
+{
+	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+	init_db().await?.health().await?;
+	let mut receiver = subscribe();
+	let uid_gen = UidSynthesizer::default();
+
+	let RegisterResult::Stored(id) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 1, 1), &Provenance::local(), &mut LocalSession::create(&DB, 1)).await?
+	else { panic!("First store should return stored."); };
+	match tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await?? {
+		Event::InstanceStored {instance,series,study} => {
+			assert_eq!(instance, id);
+			assert_eq!(series, RecordId::from_series(uid_gen.series(1, 1)));
+			assert_eq!(study, RecordId::from_study(uid_gen.study(1)));
+		}
+		e => panic!("Expected instance_stored, got {e:?}")
+	}
+
+	// removing the only instance removes the study
+	remove(&id).await?;
+	match tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await?? {
+		Event::StudyDeleted {study} => assert_eq!(study, RecordId::from_study(uid_gen.study(1))),
+		e => panic!("Expected study_deleted, got {e:?}")
+	}
+
+	cleanup().await.map_err(|e| e.into())
+}
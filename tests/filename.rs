@@ -0,0 +1,60 @@
+use crate::common::dcm::{synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_config;
+use dicom::core::{DataElement, PrimitiveValue, VR};
+use dicom::dictionary_std::tags;
+use dicom::object::DefaultDicomObject;
+use rudicom::config::Sanitize;
+use rudicom::dcm::AttributeSelector;
+use rudicom::tools::filename::{sanitize, Pattern, Source};
+
+mod common;
+
+fn render(pattern:&str, obj:&DefaultDicomObject) -> Result<String, String>
+{
+	let pattern = Pattern::parse(pattern, &|name|name == "Run")?;
+	Ok(pattern.render(|source|match source {
+		Source::Selector(AttributeSelector::Core(s)) => obj.entry_at(s.clone()).ok()?.value().primitive().cloned(),
+		Source::Column(_) => Some(PrimitiveValue::from("run 1")),
+		Source::Literal(text) => Some(PrimitiveValue::from(text.as_str())),
+		_ => None
+	}))
+}
+
+#[test]
+fn filename() -> Result<(), Box<dyn std::error::Error>>
+{
+	init_config()?;
+	let mut obj = synthesize_dicom_obj(&UidSynthesizer::default(), 1, 1, 7);
+	obj.put(DataElement::new(tags::SERIES_DESCRIPTION, VR::LO, PrimitiveValue::from("")));
+	obj.put(DataElement::new(tags::PROTOCOL_NAME, VR::LO, PrimitiveValue::from("t1 mprage/sag")));
+	let uid = obj.element(tags::SOP_INSTANCE_UID)?.to_str()?.to_string();
+
+	// the old syntax still works
+	assert_eq!(render("{StudyDate:>6}_{PatientID:<4}", &obj)?, "250101_John");
+	assert_eq!(render("{SeriesDescription}/{ImageComments}", &obj)?, "__empty__/__none__");
+	// fallbacks skip empty and missing values
+	assert_eq!(render("{SeriesDescription|ProtocolName}", &obj)?, "t1_mprage_sag");
+	assert_eq!(render("{ImageComments|'unknown'}", &obj)?, "unknown");
+	assert_eq!(render("{InstanceNumber:05}", &obj)?, "00007");
+	assert_eq!(render("{StudyDate:date=%Y-%m}/{StudyTime:time='%H:%M'}", &obj)?, "2025-01/00:00");
+	assert_eq!(render("{SOPInstanceUID:md5:8}", &obj)?, &format!("{:x}", md5::compute(&uid))[..8]);
+	assert_eq!(render("{Run}", &obj)?, "run_1");
+	assert_eq!(render("{{{PatientID}}}", &obj)?, "{John_Doe}");
+
+	assert!(Pattern::parse("{DoesNotExist}", &|_|false).is_err(), "unknown names should be rejected");
+	assert!(Pattern::parse("{PatientID:foo}", &|_|false).is_err(), "unknown modifiers should be rejected");
+	assert!(Pattern::parse("{PatientID", &|_|false).is_err(), "unterminated fields should be rejected");
+
+	assert_eq!(sanitize("a b/c:d", Sanitize::Posix), "a_b_c:d");
+	assert_eq!(sanitize("a b/c:d", Sanitize::Windows), "a_b_c_d");
+	assert_eq!(sanitize("CON", Sanitize::Windows), "_CON");
+	assert_eq!(sanitize("Müller^Hans", Sanitize::Portable), "M_ller_Hans");
+	assert_eq!(sanitize("..", Sanitize::Posix), "__");
+
+	let warnings = |p:&str| Pattern::parse(p, &|_|false).map(|p|p.uniqueness_warnings().len());
+	assert_eq!(warnings("{PatientID}/{SOPInstanceUID}")?, 0);
+	assert_eq!(warnings("{SOPInstanceUID:sha256:32}")?, 0);
+	assert_eq!(warnings("{PatientID}/{InstanceNumber}")?, 1);
+	assert_eq!(warnings("{SOPInstanceUID:<8}")?, 1);
+	Ok(())
+}
@@ -2,13 +2,14 @@ mod common;
 
 use crate::common::dcm::cleanup;
 use crate::common::{dcm, init_db};
-use dicom::core::value::Value;
+use dicom::core::value::{DataSetSequence, Value};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
 use dicom::dictionary_std::tags;
-use dicom::object::AccessError;
+use dicom::object::{AccessError, InMemDicomObject};
 use dimse::Taker;
 use pyo3::types::PyModule;
 use pyo3::Python;
-use rudicom::db::{lookup, LocalSession, RegisterResult, Session, DB};
+use rudicom::db::{lookup, LocalSession, Provenance, RegisterResult, Session, DB};
 use rudicom::tools;
 use rudicom::tools::store::store_ob;
 use rudicom::tools::Error::PythonErr;
@@ -34,6 +35,34 @@ def filter(input:dict[tuple[int,int],Any]) -> dict[tuple[int,int],Optional[Any]]
 		raise KeyError("input is missing")
 "#;
 
+static NESTED:&str = r#"
+from typing import Any, Optional
+
+input_tags = [(0x0008,0x1140),(0x7fe0,0x0010)]
+
+def filter(input:dict[tuple[int,int],Any]) -> dict[tuple[int,int],Optional[Any]]:
+	items = input[(0x0008,0x1140)]
+	items.append({(0x0008,0x1150):"1.2.3", (0x0008,0x1155):"1.2.3.4"})
+	return {
+		(0x0008,0x1140):items,
+		(0x0019,0x0010,"LO"):"SIEMENS MR HEADER",
+		(0x0019,0x100c,"IS"):"1000",
+		(0x0029,0x1010,"OB"):input[(0x7fe0,0x0010)][::-1],
+	}
+"#;
+
+static UNCHANGED_ITEMS:&str = r#"
+input_tags = [(0x0008,0x1140)]
+
+def filter(input):
+	return {(0x0008,0x1140):input[(0x0008,0x1140)]}
+"#;
+
+static ODD_OW:&str = r#"
+def filter(input):
+	return {(0x0029,0x1010,"OW"):b"\x01\x02\x03"}
+"#;
+
 #[test]
 fn replace_filter()  -> Result<(), Box<dyn std::error::Error>>
 {
@@ -73,6 +102,77 @@ fn missing_input()  -> Result<(), Box<dyn std::error::Error>>
 	})
 }
 
+#[test]
+fn nested_filter()  -> Result<(), Box<dyn std::error::Error>>
+{
+	let code = CString::new(NESTED)?;
+	let mut obj = dcm::synthesize_dicom_obj(&dcm::UidSynthesizer::default(), 1, 1, 1).into_inner();
+	let item = InMemDicomObject::from_element_iter([
+		DataElement::new(tags::REFERENCED_SOP_CLASS_UID, VR::UI, "1.2"),
+		DataElement::new(tags::REFERENCED_SOP_INSTANCE_UID, VR::UI, "1.2.3"),
+	]);
+	obj.put(DataElement::new(tags::REFERENCED_IMAGE_SEQUENCE, VR::SQ, DataSetSequence::from(vec![item])));
+	obj.put(DataElement::new(tags::PIXEL_DATA, VR::OB, PrimitiveValue::from(vec![1u8,2,3,4])));
+
+	Python::attach(|py|{
+		let code = PyModule::from_code(py, code.as_ref(), c"test.py", c"test")?;
+		tools::filter::filter(code,&mut obj)
+	})?;
+
+	let items = obj.element(tags::REFERENCED_IMAGE_SEQUENCE)?.items().expect("sequence should still be there");
+	assert_eq!(items.len(), 2);
+	assert_eq!(items[0].element(tags::REFERENCED_SOP_INSTANCE_UID)?.to_str()?, "1.2.3");
+	assert_eq!(items[1].element(tags::REFERENCED_SOP_INSTANCE_UID)?.to_str()?, "1.2.3.4");
+	assert_eq!(obj.element(Tag(0x0019,0x100c))?.vr(), VR::IS);
+	assert_eq!(obj.element(Tag(0x0019,0x100c))?.to_int::<i32>()?, 1000);
+	assert_eq!(obj.element(Tag(0x0029,0x1010))?.to_bytes()?.as_ref(), &[4u8,3,2,1]);
+
+	Ok(())
+}
+
+#[test]
+fn unchanged_items_filter()  -> Result<(), Box<dyn std::error::Error>>
+{
+	let code = CString::new(UNCHANGED_ITEMS)?;
+	let mut obj = dcm::synthesize_dicom_obj(&dcm::UidSynthesizer::default(), 1, 1, 1).into_inner();
+	let item = InMemDicomObject::from_element_iter([
+		DataElement::new(tags::REFERENCED_SOP_INSTANCE_UID, VR::UI, PrimitiveValue::from("1.2.3")),
+		// private and ambiguous (US or SS) elements can only be rebuilt from the received item
+		DataElement::new(Tag(0x0019,0x1010), VR::LO, PrimitiveValue::from("private")),
+		DataElement::new(tags::SMALLEST_IMAGE_PIXEL_VALUE, VR::SS, PrimitiveValue::from(-5_i16)),
+	]);
+	obj.put(DataElement::new(tags::REFERENCED_IMAGE_SEQUENCE, VR::SQ, DataSetSequence::from(vec![item])));
+
+	Python::attach(|py|{
+		let code = PyModule::from_code(py, code.as_ref(), c"test.py", c"test")?;
+		tools::filter::filter(code,&mut obj)
+	})?;
+
+	let items = obj.element(tags::REFERENCED_IMAGE_SEQUENCE)?.items().expect("sequence should still be there");
+	assert_eq!(items.len(), 1);
+	let private = items[0].element(Tag(0x0019,0x1010))?;
+	assert_eq!((private.vr(), private.to_str()?.as_ref()), (VR::LO, "private"));
+	let smallest = items[0].element(tags::SMALLEST_IMAGE_PIXEL_VALUE)?;
+	assert_eq!((smallest.vr(), smallest.to_int::<i16>()?), (VR::SS, -5));
+	Ok(())
+}
+
+#[test]
+fn odd_ow_filter()  -> Result<(), Box<dyn std::error::Error>>
+{
+	let code = CString::new(ODD_OW)?;
+	let mut obj = dcm::synthesize_dicom_obj(&dcm::UidSynthesizer::default(), 1, 1, 1).into_inner();
+	Python::attach(|py|{
+		let code = PyModule::from_code(py, code.as_ref(), c"test.py", c"test")?;
+		if let Err(PythonErr(e)) = tools::filter::filter(code, &mut obj){
+			assert_eq!(e.to_string(), "ValueError: OW needs an even number of bytes, got 3");
+		} else { panic!("odd OW values should be rejected"); }
+		Ok::<_,Box<dyn std::error::Error>>(())
+	})?;
+	assert!(obj.element(Tag(0x0029,0x1010)).is_err(), "nothing should be put into the object");
+	Ok(())
+}
+
 #[tokio::test]
 async fn filtered_store()  -> Result<(), Box<dyn std::error::Error>>
 {
@@ -81,7 +181,7 @@ async fn filtered_store()  -> Result<(), Box<dyn std::error::Error>>
 	let mut sess = LocalSession::create(&DB, 1);
 	let mut obj = dcm::synthesize_series(&dcm::UidSynthesizer::default(), 1, 1, 2);
 
-	if let RegisterResult::Stored(stored) = store_ob(obj.remove(0), &mut sess).await? {
+	if let RegisterResult::Stored(stored) = store_ob(obj.remove(0), &Provenance::local(), &mut sess).await? {
 		let stored = lookup(&stored).await?.expect("existing object should be found");
 		let red = stored.get_file()?.read().await?;
 		assert_eq!(stored.id().str_key(), red.element(tags::SOP_INSTANCE_UID)?.string()?);
@@ -93,7 +193,7 @@ async fn filtered_store()  -> Result<(), Box<dyn std::error::Error>>
 	else { panic!("Store should return stored."); }
 
 	assert!(obj[0].update_value(tags::MODALITY,|v|*v = Value::from("SR")));
-	if let RegisterResult::Stored(stored) = store_ob(obj.remove(0), &mut sess).await? {
+	if let RegisterResult::Stored(stored) = store_ob(obj.remove(0), &Provenance::local(), &mut sess).await? {
 		let stored = lookup(&stored).await?.expect("existing object should be found");
 		let red = stored.get_file()?.read().await?;
 		assert_eq!(stored.id().str_key(), red.element(tags::SOP_INSTANCE_UID)?.string()?);
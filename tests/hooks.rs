@@ -0,0 +1,86 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db_with;
+use rudicom::db::{lookup, LocalSession, Provenance, RecordId, RegisterResult, DB};
+use rudicom::tools::conv::value_to_json;
+use rudicom::tools::hooks::{FAILED, RUNNING, SUCCESS, TIMEOUT};
+use rudicom::tools::store::store_ob;
+use std::time::Duration;
+
+mod common;
+
+async fn hooks(id:&RecordId) -> Result<serde_json::Value, Box<dyn std::error::Error>>
+{
+	let entry = lookup(id).await?.expect("entry should exist");
+	Ok(entry.get("hooks").cloned().map(value_to_json).unwrap_or_default())
+}
+
+/// the hooks of the entry once the given ones aren't running anymore (or after 10 seconds)
+async fn finished(id:&RecordId, names:&[&str]) -> Result<serde_json::Value, Box<dyn std::error::Error>>
+{
+	let mut result = hooks(id).await?;
+	for _ in 0..50 {
+		if names.iter().all(|h|result[h]["status"].as_str().is_some_and(|s|s != RUNNING)) {break}
+		tokio::time::sleep(Duration::from_millis(200)).await;
+		result = hooks(id).await?;
+	}
+	Ok(result)
+}
+
+#[tokio::test]
+async fn hooks_run() -> Result<(), Box<dyn std::error::Error>>
+{
+	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+	let config = std::env::temp_dir().join("rudicom_hooks.toml");
+	std::fs::write(&config, r#"
+		[[hooks]]
+		name = "exists"
+		command = ["sh", "-c", "test -f \"$0\" && echo found"]
+		[[hooks]]
+		name = "python"
+		python = """
+def hook(paths):
+	return str(len(paths))
+"""
+		[[hooks]]
+		name = "counter"
+		python = """
+runs = 0
+def hook(paths):
+	global runs
+	runs += 1
+	return str(runs)
+"""
+		[[hooks]]
+		name = "nul"
+		python = "def hook(paths):\u0000	return ''"
+		[[hooks]]
+		name = "fails"
+		command = ["false"]
+		[[hooks]]
+		name = "slow"
+		command = ["sleep", "10"]
+		timeout = 1
+	"#)?;
+	init_db_with(Some(config)).await?.health().await?;
+	let uid_gen = UidSynthesizer::default();
+	let RegisterResult::Stored(id) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 1, 1), &Provenance::local(), &mut LocalSession::create(&DB, 1)).await?
+	else { panic!("Storing should return stored."); };
+
+	let result = finished(&id, &["exists","python","counter","nul","fails","slow"]).await?;
+	assert_eq!(result["exists"]["status"], SUCCESS);
+	assert_eq!(result["exists"]["output"], "found");
+	assert_eq!(result["python"]["status"], SUCCESS);
+	assert_eq!(result["python"]["output"], "1");
+	assert_eq!(result["counter"]["output"], "1");
+	assert_eq!(result["nul"]["status"], FAILED, "Python code with a NUL byte should fail the hook.");
+	assert_eq!(result["fails"]["status"], FAILED);
+	assert_eq!(result["slow"]["status"], TIMEOUT);
+
+	// the module is compiled once, so its state is kept between runs
+	let RegisterResult::Stored(id) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 1, 2), &Provenance::local(), &mut LocalSession::create(&DB, 1)).await?
+	else { panic!("Storing should return stored."); };
+	let result = finished(&id, &["counter"]).await?;
+	assert_eq!(result["counter"]["output"], "2");
+
+	cleanup().await.map_err(|e| e.into())
+}
@@ -0,0 +1,26 @@
+use rudicom::tools::import::check_pattern;
+use rudicom::tools::Error;
+
+#[test]
+fn import_roots() -> Result<(), Box<dyn std::error::Error>>
+{
+	let root = std::env::temp_dir().join("import_root");
+	std::fs::create_dir_all(root.join("sub"))?;
+	// roots are canonicalized when the config is loaded
+	let root = root.canonicalize()?;
+	let roots = [root.clone()];
+
+	check_pattern(&format!("{}/**/*.dcm",root.display()), &roots)?;
+	check_pattern(&format!("{}/sub/*",root.display()), &roots)?;
+
+	// relative, wildcard-only or not existing patterns are rejected as well
+	for pattern in [
+		format!("{}/../*",root.display()), "/etc/*".to_string(), "/*/passwd".to_string(),
+		"*".to_string(), "sub/*".to_string(), format!("{}/missing/*",root.display())
+	] {
+		let Err(Error::PathNotAllowed {..}) = check_pattern(&pattern, &roots)
+		else {panic!("{pattern} should not be allowed")};
+	}
+	assert!(check_pattern("/etc/*", &[]).is_err(), "Without roots nothing should be allowed.");
+	Ok(())
+}
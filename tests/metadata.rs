@@ -0,0 +1,69 @@
+use crate::common::dcm::{bulk_insert, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db_with;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::tags;
+use rudicom::db::{lookup_uid, metadata, set_value, RegisterResult};
+use rudicom::tools::conv::value_to_json;
+use serde_json::json;
+use std::collections::BTreeMap;
+use surrealdb::types::SurrealValue;
+
+mod common;
+
+#[tokio::test]
+async fn metadata() -> Result<(), Box<dyn std::error::Error>>
+{
+	let config = std::env::temp_dir().join("rudicom_metadata.toml");
+	std::fs::write(&config, r#"
+		[instance_tags]
+		EchoTime = ["EchoTime"]
+		Age = ["=age(PatientBirthDate, StudyDate)"]
+		[metadata]
+		enabled = true
+		max_element_size = "80"
+		exclude = ["(0019,xxxx)"]
+	"#)?;
+	init_db_with(Some(config)).await?.health().await?;
+	let uid_gen = UidSynthesizer::default();
+
+	let mut instances = vec![];
+	for (series, options) in [(1, "FS\\PFP"), (2, "PFP")] {
+		let mut obj = synthesize_dicom_obj(&uid_gen, 1, series, 1);
+		let options = options.split('\\').map(str::to_string).collect::<Vec<_>>();
+		obj.put(DataElement::new(tags::SCAN_OPTIONS, VR::CS, PrimitiveValue::Strs(options.into())));
+		obj.put(DataElement::new(tags::ECHO_TIME, VR::DS, PrimitiveValue::from("2.5")));
+		obj.put(DataElement::new(tags::IMAGE_COMMENTS, VR::LT, PrimitiveValue::from("x".repeat(100))));
+		obj.put(DataElement::new(Tag(0x0019,0x1010), VR::LO, PrimitiveValue::from("private")));
+		instances.push(obj);
+	}
+	for stored in bulk_insert(instances.iter()).await? {
+		assert!(matches!(stored, RegisterResult::Stored(_)), "unexpected initial store: {stored:?}");
+	}
+
+	let instance = lookup_uid("instances", uid_gen.instance(1, 1, 1)).await?.expect("instance should exist");
+	assert!(instance.get("metadata").is_none(), "metadata should not be part of the instance record");
+	let stored = metadata::get(instance.id()).await?.expect("instance should have metadata");
+	let document = value_to_json(stored.get("metadata").cloned().expect("metadata should have been stored"));
+	assert_eq!(document["ScanOptions"], json!(["FS", "PFP"]));
+	assert_eq!(document["EchoTime"], json!(2.5));
+	assert_eq!(document["PatientName"], json!({"family": "Doe", "given": "John"}));
+	assert!(document.get("(0019,1010)").is_none(), "excluded elements should be left out");
+	assert!(document.get("ImageComments").is_none(), "big elements should be left out");
+	assert_eq!(stored.get("metadata_omitted").cloned().map(value_to_json), Some(json!(["ImageComments"])));
+
+	let filter = |pairs:&[(&str,&str)]| pairs.iter().map(|(k,v)|(k.to_string(), v.to_string())).collect::<BTreeMap<_,_>>();
+	let found = metadata::query("series", &filter(&[("ScanOptions","FS")])).await?;
+	assert_eq!(found.len(), 1);
+	assert_eq!(found[0].id().str_key(), uid_gen.series(1, 1));
+	assert_eq!(metadata::query("instances", &filter(&[("EchoTime","2.5")])).await?.len(), 2);
+	assert_eq!(metadata::query("studies", &filter(&[("ScanOptions","PFP"),("Modality","MR")])).await?.len(), 1);
+	assert!(metadata::query("instances", &filter(&[("ScanOptions","T2")])).await?.is_empty());
+
+	// columns come back from the metadata without reading the files
+	set_value(instance.id().0.clone(), "EchoTime".into(), "wrong".to_string().into_value()).await?;
+	let skipped = metadata::rederive().await?;
+	assert_eq!(skipped, vec!["instances.Age".to_string()]);
+	let instance = lookup_uid("instances", uid_gen.instance(1, 1, 1)).await?.expect("instance should exist");
+	assert_eq!(instance.get("EchoTime").cloned().map(value_to_json), Some(json!(2.5)));
+	Ok(())
+}
@@ -0,0 +1,35 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db;
+use rudicom::db::migrate::migrate;
+use rudicom::db::{lookup_uid, LocalSession, Provenance, RegisterResult, DB};
+use rudicom::tools::store::store_ob;
+use rudicom::tools::Error;
+use surrealdb::types as db_types;
+
+mod common;
+
+#[tokio::test]
+async fn migrate_values() -> Result<(), Box<dyn std::error::Error>>
+{
+	init_db().await?.health().await?;
+	let uid_gen = UidSynthesizer::default();
+	let mut session = LocalSession::create(&DB, 1);
+	migrate().await?;
+	let RegisterResult::Stored(_) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 1, 1), &Provenance::local(), &mut session).await?
+	else { panic!("Storing should return stored."); };
+
+	// columns as older versions stored them
+	DB.query("UPDATE series, studies SET Date = '20250101', Time = '000000.000000'; DELETE meta:values;").await?.check()?;
+	match store_ob(synthesize_dicom_obj(&uid_gen, 1, 1, 2), &Provenance::local(), &mut session).await {
+		Err(Error::FieldConflict {..}) => {},
+		r => panic!("Old values should conflict with new instances, got {r:?}")
+	}
+
+	migrate().await?;
+	let study = lookup_uid("studies", uid_gen.study(1)).await?.expect("study should exist");
+	assert!(!matches!(study.get("Date"), Some(db_types::Value::String(_))), "The date of the study should have been converted.");
+	let RegisterResult::Stored(_) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 1, 2), &Provenance::local(), &mut session).await?
+	else { panic!("Storing into a converted study should return stored."); };
+
+	cleanup().await.map_err(|e| e.into())
+}
@@ -0,0 +1,48 @@
+use crate::common::dcm::{synthesize_csa, synthesize_dicom_obj, UidSynthesizer};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::tags;
+use dicom::pixeldata::image::DynamicImage;
+use rudicom::dcm::{extract, AttributeSelector, Derived};
+use rudicom::tools::conv::value_to_json;
+use rudicom::tools::csa::{CREATOR, IMAGE_HEADER};
+use rudicom::tools::mosaic;
+use serde_json::json;
+use std::collections::HashMap;
+
+mod common;
+
+#[test]
+fn mosaic() -> Result<(), Box<dyn std::error::Error>>
+{
+	let mut obj = synthesize_dicom_obj(&UidSynthesizer::default(), 1, 1, 1);
+	assert_eq!(mosaic::number_of_slices(&obj), None);
+
+	obj.put(DataElement::new(Tag(0x0029,0x0010), VR::LO, PrimitiveValue::from(CREATOR)));
+	obj.put(DataElement::new(IMAGE_HEADER, VR::OB, PrimitiveValue::from(synthesize_csa(2, &[
+		("NumberOfImagesInMosaic","US",1,&["4"]),
+		("MosaicRefAcqTimes","FD",4,&["0","500","250","750"]),
+		("B_value","IS",1,&["1000"]),
+		("DiffusionGradientDirection","FD",3,&["0","0.5","-0.5"]),
+		("PhaseEncodingDirectionPositive","IS",1,&["0"]),
+	]))));
+	obj.put(DataElement::new(tags::IN_PLANE_PHASE_ENCODING_DIRECTION, VR::CS, PrimitiveValue::from("COL")));
+	assert_eq!(mosaic::number_of_slices(&obj), Some(4));
+
+	// 4 slices are tiled 2x2
+	let image = DynamicImage::new_luma8(128, 96);
+	let slice = mosaic::slice(&image, 4, 3).expect("slice 3 should exist");
+	assert_eq!((slice.width(), slice.height()), (64, 48));
+	assert!(mosaic::slice(&image, 4, 4).is_none(), "There should be no slice 4.");
+
+	let requested:HashMap<_,_> = [Derived::SliceTiming, Derived::BValue, Derived::DiffusionGradientDirection, Derived::PhaseEncodingDirection]
+		.into_iter().map(|d|(d.name().to_string(), vec![AttributeSelector::Derived(d)]))
+		.collect();
+	let extracted:HashMap<_,_> = extract(&obj, &requested).into_iter()
+		.map(|(k,v)|(k.to_string(), value_to_json(v)))
+		.collect();
+	assert_eq!(extracted["SliceTiming"], json!([0.0,500.0,250.0,750.0]));
+	assert_eq!(extracted["BValue"], json!(1000));
+	assert_eq!(extracted["DiffusionGradientDirection"], json!([0.0,0.5,-0.5]));
+	assert_eq!(extracted["PhaseEncodingDirection"], json!("j-"));
+	Ok(())
+}
@@ -0,0 +1,81 @@
+use crate::common::dcm::{synthesize_dicom_obj, UidSynthesizer};
+use dicom::core::value::DataSetSequence;
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::object::mem::InMemElement;
+use dicom::object::InMemDicomObject;
+use rudicom::dcm::{extract, AttributeSelector};
+use rudicom::tools::conv::value_to_json;
+use rudicom::tools::decoders::{self, DecodedHeader, HeaderDecoder};
+use serde_json::json;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+mod common;
+
+const PHILIPS:&str = "Philips MR Imaging DD 001";
+
+/// "decodes" a header by counting its bytes
+struct Length(usize);
+impl DecodedHeader for Length
+{
+	fn value(&self, element:&str) -> rudicom::tools::Result<Option<PrimitiveValue>> {
+		Ok((element == "Length").then(||PrimitiveValue::from(self.0 as u32)))
+	}
+}
+struct LengthDecoder;
+impl HeaderDecoder for LengthDecoder
+{
+	fn decode(&self, el:&InMemElement) -> rudicom::tools::Result<Box<dyn DecodedHeader>> {
+		Ok(Box::new(Length(el.to_bytes().map(|b|b.len()).unwrap_or(0))))
+	}
+}
+
+#[test]
+fn private_tags() -> Result<(), Box<dyn std::error::Error>>
+{
+	decoders::register("LENGTH", Arc::new(LengthDecoder));
+
+	let mut obj = synthesize_dicom_obj(&UidSynthesizer::default(), 1, 1, 1);
+	// the creator doesn't use the first block, so its elements are in (0019,11xx)
+	obj.put(DataElement::new(Tag(0x0019,0x0010), VR::LO, PrimitiveValue::from("SOMEONE ELSE")));
+	obj.put(DataElement::new(Tag(0x0019,0x0011), VR::LO, PrimitiveValue::from("GEMS_ACQU_01 ")));
+	obj.put(DataElement::new(Tag(0x0019,0x100C), VR::SS, PrimitiveValue::from(1_i16)));
+	obj.put(DataElement::new(Tag(0x0019,0x110C), VR::SS, PrimitiveValue::from(42_i16)));
+	obj.put(DataElement::new(Tag(0x0019,0x1110), VR::OB, PrimitiveValue::from(vec![0_u8;8])));
+
+	let items = [1_i32,2].map(|n|InMemDicomObject::from_element_iter([
+		DataElement::new(Tag(0x2005,0x0010), VR::LO, PrimitiveValue::from(PHILIPS)),
+		DataElement::new(Tag(0x2005,0x1013), VR::SL, PrimitiveValue::from(n)),
+	]));
+	obj.put(DataElement::new(Tag(0x2005,0x0010), VR::LO, PrimitiveValue::from(PHILIPS)));
+	obj.put(DataElement::new(Tag(0x2005,0x100E), VR::SQ, DataSetSequence::from(items.to_vec())));
+
+	let selectors = [
+		("ge", r#"(0019,"GEMS_ACQU_01",0C)"#),
+		("philips_first", r#"(2005,"Philips MR Imaging DD 001",0E).(2005,"Philips MR Imaging DD 001",13)"#),
+		("philips_second", r#"(2005,"Philips MR Imaging DD 001",0E)[1].(2005,"Philips MR Imaging DD 001",13)"#),
+		("length", r#"(0019,"GEMS_ACQU_01",10)#LENGTH.Length"#),
+		("missing", r#"(0019,"NOBODY",0C)"#),
+	];
+	let requested:HashMap<_,_> = selectors.iter()
+		.map(|(name,s)|Ok((name.to_string(), vec![AttributeSelector::from_str(s)?])))
+		.collect::<Result<_,String>>()?;
+
+	// parsing and printing round trips
+	for (name,s) in selectors {
+		assert_eq!(requested[name][0].to_string(), s);
+	}
+	assert!(AttributeSelector::from_str("(0019,1110)#UNKNOWN.Length").is_err(), "Unknown decoders should be rejected.");
+	assert!(AttributeSelector::from_str(r#"(0019,"GEMS_ACQU_01")"#).is_err(), "Missing offsets should be rejected.");
+
+	let extracted:HashMap<_,_> = extract(&obj, &requested).into_iter()
+		.map(|(k,v)|(k.to_string(), value_to_json(v)))
+		.collect();
+	assert_eq!(extracted["ge"], json!(42));
+	assert_eq!(extracted["philips_first"], json!(1));
+	assert_eq!(extracted["philips_second"], json!(2));
+	assert_eq!(extracted["length"], json!(8));
+	assert!(!extracted.contains_key("missing"), "Elements of absent creators should not be found.");
+	Ok(())
+}
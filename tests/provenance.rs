@@ -0,0 +1,37 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db;
+use dimse::io::FileAccess;
+use rudicom::db::dimse_access::Accessor;
+use rudicom::db::provenance::{query, ProvenanceFilter};
+use rudicom::db::{lookup, LocalSession, Provenance, RecordId, RegisterResult, DB};
+use rudicom::tools::store::store_ob;
+
+mod common;
+
+#[tokio::test]
+async fn provenance() -> Result<(), Box<dyn std::error::Error>>
+{
+	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+	init_db().await?.health().await?;
+	let mut session = LocalSession::create(&DB, 1);
+	let uid_gen = UidSynthesizer::default();
+	// stored by the SCP of an association from MODALITY at 10.0.0.1
+	let mut accessor = Accessor{calling_ae:Some("MODALITY".into()), peer:Some("10.0.0.1:104".parse()?)};
+	assert!(accessor.store_file(synthesize_dicom_obj(&uid_gen, 1, 1, 1)).await.is_ok(), "Storing via the SCP should succeed.");
+	let id = RecordId::from_instance(uid_gen.instance(1, 1, 1));
+	let RegisterResult::Stored(_) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 1, 2), &Provenance::local(), &mut session).await?
+	else { panic!("Second store should return stored."); };
+
+	let stored = lookup(&id).await?.expect("stored instance should be found");
+	let stored = Provenance::try_from(stored.get("provenance").expect("instance should have a provenance").clone())?;
+	assert_eq!(stored, Provenance::dimse(Some("MODALITY".into()), Some("10.0.0.1".into())));
+
+	let by_ae = query(&ProvenanceFilter{calling_ae:Some("MODALITY".into()),..Default::default()}).await?;
+	assert_eq!(by_ae.len(), 1);
+	assert_eq!(by_ae[0].id(), &id);
+	let local = query(&ProvenanceFilter{channel:Some("local".into()),..Default::default()}).await?;
+	assert_eq!(local.len(), 1);
+	assert!(query(&ProvenanceFilter{channel:Some("http".into()),..Default::default()}).await?.is_empty());
+
+	cleanup().await.map_err(|e| e.into())
+}
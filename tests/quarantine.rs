@@ -0,0 +1,66 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db_with;
+use dicom::core::{DataElement, VR};
+use dicom::dictionary_std::tags;
+use rudicom::db::{list_versions, lookup_uid, Entry, LocalSession, Provenance, RegisterResult, Session, DB};
+use rudicom::tools::quarantine;
+use rudicom::tools::store::store_ob;
+use rudicom::tools::Error::Quarantined;
+
+mod common;
+
+#[tokio::test]
+async fn quarantine_actions() -> Result<(), Box<dyn std::error::Error>>
+{
+	let config = std::env::temp_dir().join("rudicom_quarantine.toml");
+	std::fs::write(&config, r#"
+		[paths]
+		quarantine_path = "quarantine"
+	"#)?;
+	init_db_with(Some(config)).await?.health().await?;
+	let mut session = LocalSession::create(&DB, 1);
+	let uid_gen = UidSynthesizer::default();
+
+	let mut quarantined = vec![];
+	for number in 1..=3 {
+		let obj = synthesize_dicom_obj(&uid_gen, 1, 1, number);
+		let RegisterResult::Stored(_) = store_ob(obj.clone(), &Provenance::local(), &mut session).await?
+		else { panic!("First store should return stored."); };
+		// same instance with a different number
+		let mut conflicting = obj;
+		conflicting.put(DataElement::new(tags::INSTANCE_NUMBER, VR::IS, (number + 10).to_string()));
+		match store_ob(conflicting, &Provenance::local(), &mut session).await {
+			Err(Quarantined {id, ..}) => quarantined.push(id),
+			other => panic!("Conflicting instance should be quarantined, got {other:?}")
+		}
+	}
+	assert_eq!(quarantine::list().await?.len(), 3);
+	let instance = async |number| -> Result<Entry, Box<dyn std::error::Error>> {
+		Ok(lookup_uid("instances", uid_gen.instance(1, 1, number)).await?.expect("instance should still exist"))
+	};
+
+	// overwrite replaces the instance and its file without keeping a version
+	let old_path = instance(1).await?.get_file()?.get_path();
+	quarantine::overwrite(&quarantined[0]).await?;
+	let overwritten = instance(1).await?;
+	assert_eq!(overwritten.get_text("Number", VR::IS).as_deref(), Some("11"));
+	let file = overwritten.get_file()?;
+	assert_eq!(file.read().await?.element(tags::INSTANCE_NUMBER)?.to_int::<u16>()?, 11);
+	assert!(list_versions(overwritten.id()).await?.is_empty(), "Overwriting should not keep a version.");
+	assert!(file.get_path() == old_path || !old_path.exists(), "The replaced file should be gone.");
+
+	// accept keeps both as versions
+	quarantine::accept(&quarantined[1]).await?;
+	let accepted = instance(2).await?;
+	assert_eq!(accepted.get_text("Number", VR::IS).as_deref(), Some("12"));
+	assert_eq!(list_versions(accepted.id()).await?.len(), 1);
+
+	// discard rejects the quarantined object and keeps the existing instance
+	let discarded = quarantine::lookup(&quarantined[2]).await?.expect("quarantine entry should exist").file.get_path();
+	quarantine::discard(&quarantined[2]).await?;
+	assert_eq!(instance(3).await?.get_text("Number", VR::IS).as_deref(), Some("3"));
+	assert!(!discarded.exists(), "The discarded file should be deleted.");
+
+	assert!(quarantine::list().await?.is_empty(), "All quarantine entries should be resolved.");
+	cleanup().await.map_err(|e| e.into())
+}
@@ -0,0 +1,68 @@
+use crate::common::dcm::{cleanup, synthesize_series, UidSynthesizer};
+use crate::common::init_db_with;
+use dicom::core::{DataElement, VR};
+use dicom::dictionary_std::tags;
+use rudicom::db::audit::Actor;
+use rudicom::db::{lookup, register_instance_version, FileInfo, LocalSession, Provenance, RecordId, RegisterResult, DB};
+use rudicom::tools::reorganize::{merge_studies, move_series, split_study};
+use rudicom::tools::store::store_ob;
+use rudicom::tools::{entries_for_record, extract_from_dicom};
+
+mod common;
+
+async fn study_uid_of(instance:&RecordId) -> Result<String, Box<dyn std::error::Error>>
+{
+	let obj = lookup(instance).await?.expect("instance should be found").get_file()?.read().await?;
+	Ok(extract_from_dicom(&obj, tags::STUDY_INSTANCE_UID)?.to_string())
+}
+
+#[tokio::test]
+async fn reorganize() -> Result<(), Box<dyn std::error::Error>>
+{
+	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+	// all instances of a series get the same filename, so they are stored with suffixes
+	let config = std::env::temp_dir().join("rudicom_reorganize.toml");
+	std::fs::write(&config, r#"
+		[paths]
+		filename_pattern = "reorganize/{StudyInstanceUID}/S{SeriesNumber}.dcm"
+		on_collision = "suffix"
+	"#)?;
+	init_db_with(Some(config)).await?.health().await?;
+	let mut session = LocalSession::create(&DB, 1);
+	let uid_gen = UidSynthesizer::default();
+	for obj in synthesize_series(&uid_gen, 1, 1, 3).into_iter().chain(synthesize_series(&uid_gen, 2, 1, 3)) {
+		let RegisterResult::Stored(_) = store_ob(obj, &Provenance::local(), &mut session).await?
+		else { panic!("Initial store should return stored."); };
+	}
+	// and one of the moved instances has a second version
+	let mut obj = synthesize_series(&uid_gen, 2, 1, 3).remove(0);
+	obj.put(DataElement::new(tags::IMAGE_COMMENTS, VR::LT, "second version"));
+	let RegisterResult::Stored(_) = register_instance_version(obj, &mut FileInfo::Store, &Provenance::local(), &mut session).await?
+	else { panic!("Storing a new version should return stored."); };
+	let actor = Actor::local();
+	let study1 = RecordId::from_study(uid_gen.study(1));
+	let series2 = RecordId::from_series(uid_gen.series(2, 1));
+
+	let moved = move_series(&series2, &study1, &actor).await?;
+	assert_eq!(moved.len(), 3);
+	for id in &moved {
+		assert_eq!(study_uid_of(id).await?, uid_gen.study(1));
+	}
+	assert_eq!(entries_for_record(&study1, "series").await?.len(), 2, "Study 1 should have both series after the move.");
+
+	let new_study = split_study(&study1, &[series2.clone()], &actor).await?;
+	for id in &moved {
+		assert_eq!(study_uid_of(id).await?, new_study.str_key());
+	}
+	assert_eq!(entries_for_record(&study1, "series").await?.len(), 1, "Study 1 should have one series after the split.");
+
+	let merged = merge_studies(&study1, &new_study, &actor).await?;
+	assert_eq!(merged.len(), moved.len());
+	for id in &merged {
+		assert_eq!(study_uid_of(id).await?, uid_gen.study(1));
+	}
+	assert!(merge_studies(&study1, &study1, &actor).await.is_err(), "Merging a study into itself should fail.");
+	assert!(move_series(&series2, &RecordId::from_series(uid_gen.series(1, 1)), &actor).await.is_err(), "Moving into a series should fail.");
+
+	cleanup().await.map_err(|e| e.into())
+}
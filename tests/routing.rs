@@ -0,0 +1,84 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db_with;
+use axum::http::HeaderMap;
+use axum::routing::post;
+use axum::Router;
+use rudicom::db::{LocalSession, Provenance, RegisterResult, DB};
+use rudicom::tools::conv::value_to_json;
+use rudicom::tools::routing;
+use rudicom::tools::store::store_ob;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+mod common;
+
+const SECRET:&str = "routing secret";
+
+/// a webhook receiver passing the received bodies on, together with whether their signature is valid
+async fn receiver() -> Result<(String, mpsc::UnboundedReceiver<(bool, serde_json::Value)>), Box<dyn std::error::Error>>
+{
+	let (tx, rx) = mpsc::unbounded_channel();
+	let app = Router::new().route("/hook", post(move |headers:HeaderMap, body:String| async move {
+		let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+		mac.update(body.as_bytes());
+		let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+		let signed = headers.get("X-Rudicom-Signature").is_some_and(|s|s.as_bytes() == expected.as_bytes());
+		tx.send((signed, serde_json::from_str(&body).unwrap())).unwrap();
+	}));
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let url = format!("http://{}/hook", listener.local_addr()?);
+	tokio::spawn(async {axum::serve(listener, app).await});
+	Ok((url, rx))
+}
+
+#[tokio::test]
+async fn routing() -> Result<(), Box<dyn std::error::Error>>
+{
+	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+	let uid_gen = UidSynthesizer::default();
+	let (url, mut received) = receiver().await?;
+	// nothing listens there anymore
+	let unreachable = format!("http://{}/hook", TcpListener::bind("127.0.0.1:0").await?.local_addr()?);
+
+	let config = std::env::temp_dir().join("rudicom_routing.toml");
+	std::fs::write(&config, format!(r#"
+		[routing]
+		retry_delay = 60
+		[[routing.rules]]
+		name = "series-1"
+		conditions = {{SeriesInstanceUID = "{}"}}
+		webhook = "{url}"
+		secret = "{SECRET}"
+		[[routing.rules]]
+		name = "broken"
+		conditions = {{SeriesInstanceUID = "{}"}}
+		webhook = "{unreachable}"
+	"#, uid_gen.series(1, 1), uid_gen.series(1, 1)))?;
+	init_db_with(Some(config)).await?.health().await?;
+	routing::resume();
+
+	let mut session = LocalSession::create(&DB, 1);
+	let RegisterResult::Stored(matching) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 1, 1), &Provenance::local(), &mut session).await?
+	else { panic!("Storing should return stored."); };
+	let RegisterResult::Stored(_) = store_ob(synthesize_dicom_obj(&uid_gen, 1, 2, 1), &Provenance::local(), &mut session).await?
+	else { panic!("Storing should return stored."); };
+
+	let (signed, body) = tokio::time::timeout(Duration::from_secs(10), received.recv()).await?.expect("webhook should be called");
+	assert!(signed, "The webhook body should be signed with the secret of the rule.");
+	assert_eq!(body["rule"], "series-1");
+	assert_eq!(body["target"], matching.str_path());
+	tokio::time::sleep(Duration::from_secs(2)).await;
+	assert!(received.try_recv().is_err(), "Only the matching instance should be forwarded.");
+
+	// the failed forward stays queued for retry
+	let queue:Vec<_> = routing::queue().await?.into_iter().map(value_to_json).collect();
+	assert_eq!(queue.len(), 1);
+	assert_eq!(queue[0]["rule"], "broken");
+	assert_eq!(queue[0]["attempts"], 1);
+	assert_eq!(queue[0]["failed"], false);
+
+	cleanup().await.map_err(|e| e.into())
+}
@@ -0,0 +1,47 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db_with;
+use rudicom::db::{self, RecordId};
+use rudicom::tools::{gateway, scu};
+use tokio::net::TcpListener;
+
+mod common;
+
+#[tokio::test]
+async fn store_roundtrip() -> Result<(), Box<dyn std::error::Error>>
+{
+	let config = std::env::temp_dir().join("rudicom_scu.toml");
+	std::fs::write(&config, r#"
+		[dimse]
+		aet = "RUDICOM"
+		address = "127.0.0.1:0"
+		[dimse.peers]
+	"#)?;
+	init_db_with(Some(config)).await?.health().await?;
+	let uid_gen = UidSynthesizer::default();
+
+	// our own SCP is the peer
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let addr = listener.local_addr()?;
+	tokio::spawn(gateway::serve(listener));
+
+	let dir = std::env::temp_dir().join("rudicom_scu");
+	std::fs::create_dir_all(&dir)?;
+	let files:Vec<_> = (1..=2).map(|i|{
+		let path = dir.join(format!("{i}.dcm"));
+		synthesize_dicom_obj(&uid_gen, 1, 1, i).write_to_file(&path).map(|_|path)
+	}).collect::<Result<_,_>>()?;
+	scu::store("RUDICOM", addr, files).await?;
+
+	// both instances went through the SCP into the database
+	for i in 1..=2 {
+		let instance = RecordId::from_instance(&uid_gen.instance(1, 1, i));
+		assert!(db::lookup(&instance).await?.is_some(), "{instance} should have been stored via C-STORE.");
+	}
+
+	// a peer that can't be reached fails the forward
+	let unreachable = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+	assert!(scu::store("NOBODY", unreachable, vec![dir.join("1.dcm")]).await.is_err(), "Storing to a peer that doesn't listen should fail.");
+
+	std::fs::remove_dir_all(dir)?;
+	cleanup().await.map_err(|e| e.into())
+}
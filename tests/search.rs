@@ -0,0 +1,80 @@
+use crate::common::dcm::{bulk_insert, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db;
+use dicom::core::{DataElement, PrimitiveValue, VR};
+use dicom::dictionary_std::tags;
+use rudicom::db::audit::Actor;
+use rudicom::db::migrate::migrate;
+use rudicom::db::{lookup_uid, search::search, RecordId, RegisterResult, DB};
+use rudicom::tools::correct::correct;
+use rudicom::tools::remove::remove;
+use rudicom::tools::reorganize::move_series;
+
+mod common;
+
+#[tokio::test]
+async fn search_studies() -> Result<(), Box<dyn std::error::Error>>
+{
+	init_db().await?.health().await?;
+	let uid_gen = UidSynthesizer::default();
+
+	let studies = [
+		(1, "Müller^Jürgen", "Knee MRI", "Sagittal PD"),
+		(2, "Doe^John", "Head", "Brain perfusion"),
+	];
+	let mut instances = vec![];
+	for (study, name, description, series_description) in studies {
+		let mut obj = synthesize_dicom_obj(&uid_gen, study, 1, 1);
+		obj.put(DataElement::new(tags::PATIENT_NAME, VR::PN, PrimitiveValue::from(name)));
+		obj.put(DataElement::new(tags::STUDY_DESCRIPTION, VR::LO, PrimitiveValue::from(description)));
+		obj.put(DataElement::new(tags::SERIES_DESCRIPTION, VR::LO, PrimitiveValue::from(series_description)));
+		instances.push(obj);
+	}
+	for stored in bulk_insert(instances.iter()).await? {
+		assert!(matches!(stored, RegisterResult::Stored(_)), "unexpected initial store: {stored:?}");
+	}
+
+	let found = |text:&'static str, fuzzy:bool| async move {
+		search(text, fuzzy, 10).await.map(|entries|
+			entries.iter().map(|e|e.id().str_key()).collect::<Vec<_>>()
+		)
+	};
+	// accents and case are ignored, words match their beginning, series are part of their study
+	assert_eq!(found("muller", false).await?, vec![uid_gen.study(1)]);
+	assert_eq!(found("JÜRG", false).await?, vec![uid_gen.study(1)]);
+	assert_eq!(found("brain perf", false).await?, vec![uid_gen.study(2)]);
+	assert_eq!(found("knee sagittal", false).await?, vec![uid_gen.study(1)]);
+	// typos only match fuzzy
+	assert!(found("Jurgan", false).await?.is_empty());
+	assert_eq!(found("Jurgan", true).await?, vec![uid_gen.study(1)]);
+	assert!(found("elbow", true).await?.is_empty());
+
+	// removed series aren't found anymore
+	let mut obj = synthesize_dicom_obj(&uid_gen, 2, 2, 1);
+	obj.put(DataElement::new(tags::PATIENT_NAME, VR::PN, PrimitiveValue::from("Doe^John")));
+	obj.put(DataElement::new(tags::SERIES_DESCRIPTION, VR::LO, PrimitiveValue::from("Localizer")));
+	bulk_insert([obj].iter()).await?;
+	assert_eq!(found("localizer", false).await?, vec![uid_gen.study(2)]);
+	remove(&RecordId::from_series(uid_gen.series(2, 2).as_str())).await?;
+	assert!(found("localizer", false).await?.is_empty());
+	assert_eq!(found("brain", false).await?, vec![uid_gen.study(2)]);
+
+	// the search terms are not part of the studies
+	let study = lookup_uid("studies", uid_gen.study(1)).await?.expect("study should exist");
+	assert!(study.get("search").is_none() && study.get("search_words").is_none() && study.get("search_terms").is_none());
+
+	// corrections and reorganizations are found by their new terms
+	let study1 = RecordId::from_study(uid_gen.study(1).as_str());
+	let study2 = RecordId::from_study(uid_gen.study(2).as_str());
+	correct(&study1, vec![(tags::STUDY_DESCRIPTION, Some("Ankle MRI".into()))], &Actor::local()).await?;
+	assert_eq!(found("ankle", false).await?, vec![uid_gen.study(1)]);
+	assert!(found("knee", false).await?.is_empty());
+	move_series(&RecordId::from_series(uid_gen.series(1, 1).as_str()), &study2, &Actor::local()).await?;
+	assert_eq!(found("sagittal", false).await?, vec![uid_gen.study(2)]);
+
+	// studies stored without search terms are indexed by the migration
+	DB.query("DELETE series_search, study_search, meta:search").await?.check()?;
+	assert!(found("brain", false).await?.is_empty());
+	migrate().await?;
+	assert_eq!(found("brain", false).await?, vec![uid_gen.study(2)]);
+	Ok(())
+}
@@ -2,7 +2,7 @@ use crate::common::dcm;
 use crate::common::init_db;
 use dicom::dictionary_std::tags;
 use tracing::debug;
-use rudicom::db::{lookup, LocalSession, Session, DB};
+use rudicom::db::{lookup, LocalSession, Provenance, Session, DB};
 use rudicom::db::RegisterResult;
 use rudicom::tools::store::store_ob;
 use crate::common::dcm::cleanup;
@@ -15,9 +15,9 @@ async fn single_dicom() -> Result<(), Box<dyn std::error::Error>>
 	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
 	init_db().await?.health().await?;
 	let obj = dcm::synthesize_dicom_obj(&dcm::UidSynthesizer::default(), 1, 1, 1);
-	if let RegisterResult::Stored(_) = store_ob(obj.clone(), &mut LocalSession::create(&DB, 1)).await? {}
+	if let RegisterResult::Stored(_) = store_ob(obj.clone(), &Provenance::local(), &mut LocalSession::create(&DB, 1)).await? {}
 	else { panic!("First store should return stored."); }
-	if let RegisterResult::AlreadyStored(stored) = store_ob(obj.clone(), &mut LocalSession::create(&DB, 1)).await? {
+	if let RegisterResult::AlreadyStored(stored) = store_ob(obj.clone(), &Provenance::local(), &mut LocalSession::create(&DB, 1)).await? {
 		let stored = lookup(&stored).await?.expect("existing object should be found");
 		let path = stored.get_file()?.get_path();
 		let red = dicom::object::open_file(&path)?;
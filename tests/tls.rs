@@ -0,0 +1,130 @@
+use crate::common::init_db_with;
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use rudicom::config::TlsCfg;
+use rudicom::tools::tls::{acceptor, connector, handshake, originate};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::pki_types::ServerName;
+
+mod common;
+
+/// writes a CA and two certificates for 127.0.0.1 signed by it into `dir`
+fn generate(dir:&Path) -> Result<(TlsCfg,TlsCfg), Box<dyn std::error::Error>>
+{
+	std::fs::create_dir_all(dir)?;
+	let ca_key = KeyPair::generate()?;
+	let mut ca_params = CertificateParams::new(Vec::<String>::new())?;
+	ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+	let ca = ca_params.self_signed(&ca_key)?;
+	std::fs::write(dir.join("ca.pem"), ca.pem())?;
+
+	let mut cfgs = vec![];
+	for name in ["server","client"] {
+		let key = KeyPair::generate()?;
+		let cert = CertificateParams::new(vec!["127.0.0.1".to_string()])?.signed_by(&key, &ca, &ca_key)?;
+		std::fs::write(dir.join(format!("{name}.pem")), cert.pem())?;
+		std::fs::write(dir.join(format!("{name}.key")), key.serialize_pem())?;
+		cfgs.push(TlsCfg{
+			cert:dir.join(format!("{name}.pem")),
+			key:dir.join(format!("{name}.key")),
+			ca:Some(dir.join("ca.pem")),
+			verify_clients:true
+		});
+	}
+	let client = cfgs.pop().unwrap();
+	Ok((cfgs.pop().unwrap(), client))
+}
+
+/// TLS echo server
+async fn echo_server(server:&TlsCfg) -> Result<SocketAddr, Box<dyn std::error::Error>>
+{
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let addr = listener.local_addr()?;
+	let acceptor = acceptor(server)?;
+	tokio::spawn(async move {
+		while let Ok((stream, _)) = listener.accept().await {
+			let acceptor = acceptor.clone();
+			tokio::spawn(async move {
+				let stream = handshake(&acceptor, stream).await?;
+				let (mut read, mut write) = tokio::io::split(stream);
+				tokio::io::copy(&mut read, &mut write).await
+			});
+		}
+	});
+	Ok(addr)
+}
+
+/// sends through a plain proxy that forwards via TLS to the echo server
+async fn roundtrip(server:&TlsCfg, client:&TlsCfg) -> Result<Vec<u8>, Box<dyn std::error::Error>>
+{
+	let echo = echo_server(server).await?;
+	let plain_listener = TcpListener::bind("127.0.0.1:0").await?;
+	let plain_addr = plain_listener.local_addr()?;
+	tokio::spawn(originate(plain_listener, connector(client)?, echo));
+
+	let mut stream = TcpStream::connect(plain_addr).await?;
+	stream.write_all(b"DICM").await?;
+	let mut buffer = vec![0;4];
+	let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await??;
+	buffer.truncate(read);
+	// the proxy is closed once it accepted a connection
+	assert!(TcpStream::connect(plain_addr).await.is_err(), "The proxy should not accept a second connection.");
+	Ok(buffer)
+}
+
+#[tokio::test]
+async fn tls() -> Result<(), Box<dyn std::error::Error>>
+{
+	let (server, client) = generate(&std::env::temp_dir().join("rudicom_tls"))?;
+	assert_eq!(roundtrip(&server, &client).await?, b"DICM");
+
+	// a client certificate from a different CA must be rejected
+	let (_, foreign) = generate(&std::env::temp_dir().join("rudicom_tls_foreign"))?;
+	let foreign = TlsCfg{ca:client.ca.clone(), ..foreign};
+	assert!(roundtrip(&server, &foreign).await.map_or(true,|echoed|echoed.is_empty()), "Foreign client certificate should be rejected.");
+	Ok(())
+}
+
+/// GET /api/info via HTTPS, returns the response
+async fn get_info(addr:SocketAddr, client:&TlsCfg) -> Result<String, Box<dyn std::error::Error>>
+{
+	let name = ServerName::IpAddress(addr.ip().into());
+	let mut stream = connector(client)?.connect(name, TcpStream::connect(addr).await?).await?;
+	stream.write_all(b"GET /api/info HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await?;
+	let mut response = String::new();
+	tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await??;
+	Ok(response)
+}
+
+#[tokio::test]
+async fn https() -> Result<(), Box<dyn std::error::Error>>
+{
+	let (server, client) = generate(&std::env::temp_dir().join("rudicom_https"))?;
+	let config = std::env::temp_dir().join("rudicom_https.toml");
+	std::fs::write(&config, format!(r#"
+		[auth]
+		anonymous = "read-only"
+		[tls]
+		cert = "{}"
+		key = "{}"
+		ca = "{}"
+		verify_clients = true
+	"#, server.cert.display(), server.key.display(), client.ca.as_ref().unwrap().display()))?;
+	init_db_with(Some(config)).await?.health().await?;
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let addr = listener.local_addr()?;
+	tokio::spawn(rudicom::server::serve(listener));
+
+	// a connection stuck before its handshake must not hold up others
+	let _stalled = TcpStream::connect(addr).await?;
+	let response = get_info(addr, &client).await?;
+	assert!(response.starts_with("HTTP/1.1 200"), "unexpected response {response}");
+
+	let (_, foreign) = generate(&std::env::temp_dir().join("rudicom_https_foreign"))?;
+	let foreign = TlsCfg{ca:client.ca.clone(), ..foreign};
+	assert!(get_info(addr, &foreign).await.map_or(true,|response|response.is_empty()), "Foreign client certificate should be rejected.");
+	Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::common::dcm::{synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_config;
+use dicom::core::value::{DataSetSequence, PixelFragmentSequence};
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::tags;
+use dicom::object::InMemDicomObject;
+use rudicom::db::{dicom_string, display_string};
+use rudicom::dcm::{extract, AttributeSelector};
+use rudicom::tools::conv::value_to_json;
+use serde_json::json;
+use std::collections::HashMap;
+use surrealdb::types as db_types;
+
+mod common;
+
+#[test]
+fn value_mapping() -> Result<(), Box<dyn std::error::Error>>
+{
+	init_config()?;
+	let mut obj = synthesize_dicom_obj(&UidSynthesizer::default(), 1, 1, 1);
+	let elements = [
+		(tags::PATIENT_BIRTH_DATE, VR::DA, "1980"),
+		(tags::ACQUISITION_DATE, VR::DA, "20251345"),
+		(tags::ACQUISITION_TIME, VR::TM, "1230"),
+		(tags::ACQUISITION_DATE_TIME, VR::DT, "20250101120000.000000"),
+		(tags::REFERRING_PHYSICIAN_NAME, VR::PN, "Doe^Jane^^Dr=ドウ^ジェーン"),
+		(tags::SLICE_THICKNESS, VR::DS, "1.5 "),
+		(tags::ECHO_NUMBERS, VR::IS, "two"),
+	];
+	for (tag, vr, value) in elements {
+		obj.put(DataElement::new(tag, vr, PrimitiveValue::from(value)));
+	}
+	let position = vec!["1".to_string(), "-2.5".to_string(), "3e1".to_string()];
+	obj.put(DataElement::new(tags::IMAGE_POSITION_PATIENT, VR::DS, PrimitiveValue::Strs(position.into())));
+	obj.put(DataElement::new(Tag(0x0029,0x1010), VR::OB, PrimitiveValue::from(vec![0_u8;16])));
+	obj.put(DataElement::new(tags::PIXEL_DATA, VR::OB, PixelFragmentSequence::new_fragments(vec![vec![0_u8;4], vec![0_u8;6]])));
+	obj.put(DataElement::new(tags::REFERENCED_IMAGE_SEQUENCE, VR::SQ, DataSetSequence::from(vec![
+		InMemDicomObject::from_element_iter([DataElement::new(tags::REFERENCED_FRAME_NUMBER, VR::IS, PrimitiveValue::from("3"))])
+	])));
+
+	let names = ["StudyDate","StudyTime","PatientBirthDate","AcquisitionDate","AcquisitionTime","AcquisitionDateTime",
+		"PatientName","ReferringPhysicianName","SliceThickness","ImagePositionPatient","InstanceNumber","EchoNumbers",
+		"(0029,1010)","PixelData","ReferencedImageSequence"];
+	let requested:HashMap<_,_> = names.iter()
+		.map(|name|Ok((name.to_string(), vec![name.parse::<AttributeSelector>()?])))
+		.collect::<Result<_,String>>()?;
+	let extracted:HashMap<_,_> = extract(&obj, &requested).into_iter()
+		.map(|(k,v)|(k.to_string(), v))
+		.collect();
+	let json = |name:&str| value_to_json(extracted[name].clone());
+
+	// complete dates and times become datetimes and can be turned back into DICOM
+	assert!(!matches!(extracted["StudyDate"], db_types::Value::String(_)), "complete dates should not be stored as strings");
+	assert_eq!(dicom_string(&extracted["StudyDate"], VR::DA).as_deref(), Some("20250101"));
+	assert_eq!(dicom_string(&extracted["StudyTime"], VR::TM).as_deref(), Some("000000"));
+	assert_eq!(dicom_string(&extracted["AcquisitionDateTime"], VR::DT).map(|dt|dt.len()), Some(14));
+	// partial ones keep their precision, invalid ones are flagged
+	assert_eq!(json("PatientBirthDate"), json!("1980"));
+	assert_eq!(json("AcquisitionTime"), json!("1230"));
+	assert_eq!(json("AcquisitionDate"), json!({"invalid": true, "raw": "20251345"}));
+	assert_eq!(json("EchoNumbers"), json!({"invalid": true, "raw": "two"}));
+	assert_eq!(dicom_string(&extracted["AcquisitionDate"], VR::DA).as_deref(), Some("20251345"));
+
+	assert_eq!(json("PatientName"), json!({"family": "Doe", "given": "John"}));
+	assert_eq!(json("ReferringPhysicianName"), json!({"family": "Doe", "given": "Jane", "prefix": "Dr", "ideographic": {"family": "ドウ", "given": "ジェーン"}}));
+	assert_eq!(dicom_string(&extracted["ReferringPhysicianName"], VR::PN).as_deref(), Some("Doe^Jane^^Dr=ドウ^ジェーン"));
+	// and are shown that way
+	assert_eq!(display_string(&extracted["PatientName"]), "Doe^John");
+	assert_eq!(display_string(&extracted["EchoNumbers"]), "two");
+
+	assert_eq!(json("SliceThickness"), json!(1.5));
+	assert_eq!(json("ImagePositionPatient"), json!([1.0, -2.5, 30.0]));
+	assert_eq!(json("InstanceNumber"), json!(1));
+
+	assert_eq!(json("(0029,1010)"), json!({"binary": true, "vr": "OB", "size": 16}));
+	assert_eq!(json("PixelData"), json!({"binary": true, "vr": "OB", "size": 10}));
+	assert_eq!(json("ReferencedImageSequence"), json!({"ReferencedFrameNumber": 3}));
+	Ok(())
+}
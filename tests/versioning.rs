@@ -0,0 +1,60 @@
+use crate::common::dcm::{cleanup, synthesize_dicom_obj, UidSynthesizer};
+use crate::common::init_db;
+use dicom::core::{DataElement, VR};
+use dicom::dictionary_std::tags;
+use rudicom::db::{list_versions, lookup, lookup_version, register_instance_version, FileInfo, LocalSession, Provenance, RegisterResult, Session, DB};
+use rudicom::db::audit::Actor;
+use rudicom::tools::correct::correct;
+use rudicom::tools::remove::remove;
+use rudicom::tools::store::store_ob;
+
+mod common;
+
+#[tokio::test]
+async fn versioning() -> Result<(), Box<dyn std::error::Error>>
+{
+	tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+	init_db().await?.health().await?;
+	let mut session = LocalSession::create(&DB, 1);
+	let mut obj = synthesize_dicom_obj(&UidSynthesizer::default(), 1, 1, 1);
+
+	let RegisterResult::Stored(id) = store_ob(obj.clone(), &Provenance::local(), &mut session).await?
+	else { panic!("First store should return stored."); };
+	let first_file = lookup(&id).await?.expect("stored instance should be found").get_file()?;
+
+	// the same data again is no new version
+	if let RegisterResult::AlreadyStored(_) = register_instance_version(obj.clone(), &mut FileInfo::Store, &Provenance::local(), &mut session).await? {}
+	else { panic!("Registering equal data should return already stored."); }
+	assert!(list_versions(&id).await?.is_empty(), "There should be no versions for equal data.");
+
+	// corrected patient name creates a new version
+	obj.put(DataElement::new(tags::PATIENT_NAME, VR::PN, "Doe^Jane"));
+	let sender = Provenance::dimse(Some("MODALITY".into()), Some("10.0.0.1".into()));
+	if let RegisterResult::Stored(_) = register_instance_version(obj.clone(), &mut FileInfo::Store, &sender, &mut session).await? {}
+	else { panic!("Registering different data should return stored."); }
+
+	let versions = list_versions(&id).await?;
+	assert_eq!(versions.len(), 1, "There should be exactly one previous version.");
+	let previous = lookup_version(&id, 1).await?.expect("version 1 should be found");
+	assert_eq!(previous.get_file()?.get_md5(), first_file.get_md5());
+	assert_eq!(previous.replaced_by.as_ref(), Some(&sender), "The version should record who sent the new data.");
+	assert_eq!(serde_json::Value::from(previous)["diff"][0]["path"], "/studies/Name/given");
+
+	let current = lookup(&id).await?.expect("stored instance should be found");
+	let current_file = current.get_file()?;
+	assert_ne!(current_file.get_path(), first_file.get_path());
+	assert_eq!(current_file.read().await?.element(tags::PATIENT_NAME)?.to_str()?, "Doe^Jane");
+
+	// a correction replaces the file of the current version, the file of the previous one stays
+	correct(&id, vec![(tags::IMAGE_COMMENTS, Some("checked".into()))], &Actor::local()).await?;
+	let corrected_file = lookup(&id).await?.expect("corrected instance should be found").get_file()?;
+	assert_eq!(corrected_file.get_path(), current_file.get_path());
+	assert_eq!(corrected_file.read().await?.element(tags::IMAGE_COMMENTS)?.to_str()?, "checked");
+	assert_eq!(lookup_version(&id, 1).await?.expect("version 1 should be found").get_file()?.read().await?.element(tags::PATIENT_NAME)?.to_str()?, "Doe^John");
+
+	remove(&id).await?;
+	assert!(list_versions(&id).await?.is_empty(), "Versions should be gone after remove.");
+	assert!(!first_file.get_path().exists(), "Files of previous versions should be gone after remove.");
+
+	cleanup().await.map_err(|e| e.into())
+}